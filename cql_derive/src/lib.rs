@@ -0,0 +1,196 @@
+//! Derive macros for the cql crate:
+//!
+//! - `#[derive(FromRow)]` builds a struct from a `CqlRow`, looking columns up by name.
+//! - `#[derive(IntoValues)]` implements `ToCqlValues`, the fields in declaration order
+//!   are the values bound to a prepared statement.
+//! - `#[derive(CqlUdt)]` implements `FromCql` and `ToCql` for a user defined type.
+//!
+//! Fields accept `#[cql(rename = "column")]`, to use another column or field name,
+//! and `#[cql(default)]`, to use `Default::default()` when the column is null or missing.
+//! `Option<T>` fields are None when the column is null or missing.
+
+extern crate proc_macro;
+extern crate syn;
+#[macro_use]
+extern crate quote;
+
+use proc_macro::TokenStream;
+use syn::{Body, DeriveInput, Field, Lit, MetaItem, NestedMetaItem, PathParameters, Ty, VariantData};
+
+#[proc_macro_derive(FromRow, attributes(cql))]
+pub fn derive_from_row(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    impl_from_row(&ast).parse().unwrap()
+}
+
+#[proc_macro_derive(IntoValues, attributes(cql))]
+pub fn derive_into_values(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    impl_into_values(&ast).parse().unwrap()
+}
+
+#[proc_macro_derive(CqlUdt, attributes(cql))]
+pub fn derive_cql_udt(input: TokenStream) -> TokenStream {
+    let ast = syn::parse_derive_input(&input.to_string()).unwrap();
+    impl_cql_udt(&ast).parse().unwrap()
+}
+
+// Options of a field, from its #[cql(...)] attributes
+struct FieldOptions {
+    name: String,
+    default: bool
+}
+
+fn struct_fields<'a>(ast: &'a DeriveInput, derive: &str) -> &'a Vec<Field> {
+    match ast.body {
+        Body::Struct(VariantData::Struct(ref fields)) => fields,
+        _ => panic!("#[derive({})] is only supported on structs with named fields", derive)
+    }
+}
+
+fn field_options(field: &Field) -> FieldOptions {
+    let mut options = FieldOptions {
+        name: field.ident.as_ref().unwrap().to_string(),
+        default: false
+    };
+    for attr in field.attrs.iter() {
+        let items = match attr.value {
+            MetaItem::List(ref ident, ref items) if ident == "cql" => items,
+            _ => continue
+        };
+        for item in items.iter() {
+            match *item {
+                NestedMetaItem::MetaItem(MetaItem::NameValue(ref ident, Lit::Str(ref name, _))) if ident == "rename" => {
+                    options.name = name.clone();
+                },
+                NestedMetaItem::MetaItem(MetaItem::Word(ref ident)) if ident == "default" => {
+                    options.default = true;
+                },
+                _ => panic!("Unknown cql attribute on field {}, expected rename = \"...\" or default", options.name)
+            }
+        }
+    }
+    options
+}
+
+// The T of an Option<T> field
+fn option_inner(ty: &Ty) -> Option<&Ty> {
+    let path = match *ty {
+        Ty::Path(None, ref path) => path,
+        _ => return None
+    };
+    let segment = match path.segments.last() {
+        Some(segment) if segment.ident == "Option" => segment,
+        _ => return None
+    };
+    match segment.parameters {
+        PathParameters::AngleBracketed(ref data) if data.types.len() == 1 => Some(&data.types[0]),
+        _ => None
+    }
+}
+
+fn impl_from_row(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let fields = struct_fields(ast, "FromRow").iter().map(|field| {
+        let ident = &field.ident;
+        let options = field_options(field);
+        let column = &options.name;
+        match option_inner(&field.ty) {
+            Some(inner) => quote! {
+                #ident: try!(row.get_opt_by_name::<#inner>(#column))
+            },
+            None if options.default => {
+                let ty = &field.ty;
+                quote! {
+                    #ident: try!(row.get_opt_by_name::<#ty>(#column)).unwrap_or_default()
+                }
+            },
+            None => {
+                let ty = &field.ty;
+                quote! {
+                    #ident: try!(row.get_by_name::<#ty>(#column))
+                }
+            }
+        }
+    }).collect::<Vec<_>>();
+
+    quote! {
+        impl #impl_generics ::cql::FromRow for #name #ty_generics #where_clause {
+            fn from_row(row: &::cql::CqlRow) -> ::cql::RCResult<Self> {
+                Ok(#name {
+                    #(#fields),*
+                })
+            }
+        }
+    }
+}
+
+fn impl_into_values(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let values = struct_fields(ast, "IntoValues").iter().map(|field| {
+        let ident = &field.ident;
+        quote! {
+            ::cql::ToCql::to_cql(&self.#ident)
+        }
+    }).collect::<Vec<_>>();
+
+    quote! {
+        impl #impl_generics ::cql::ToCqlValues for #name #ty_generics #where_clause {
+            fn to_cql_values(&self) -> Vec<::cql::CqlValue> {
+                vec![#(#values),*]
+            }
+        }
+    }
+}
+
+fn impl_cql_udt(ast: &DeriveInput) -> quote::Tokens {
+    let name = &ast.ident;
+    let (impl_generics, ty_generics, where_clause) = ast.generics.split_for_impl();
+    let fields = struct_fields(ast, "CqlUdt");
+
+    let from_fields = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let options = field_options(field);
+        let field_name = &options.name;
+        let ty = &field.ty;
+        if options.default && option_inner(ty).is_none() {
+            quote! {
+                #ident: try!(::cql::from_udt_field::<Option<#ty>>(fields, #field_name)).unwrap_or_default()
+            }
+        } else {
+            quote! {
+                #ident: try!(::cql::from_udt_field::<#ty>(fields, #field_name))
+            }
+        }
+    }).collect::<Vec<_>>();
+
+    let to_fields = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let field_name = field_options(field).name;
+        quote! {
+            (::std::borrow::Cow::Borrowed(#field_name), ::cql::ToCql::to_cql(&self.#ident))
+        }
+    }).collect::<Vec<_>>();
+
+    let type_name = name.to_string();
+    quote! {
+        impl #impl_generics ::cql::FromCql for #name #ty_generics #where_clause {
+            fn from_cql(value: &::cql::CqlValue) -> ::cql::RCResult<Self> {
+                match *value {
+                    ::cql::CqlValue::CqlUdt(Some(ref fields)) => Ok(#name {
+                        #(#from_fields),*
+                    }),
+                    _ => Err(::cql::RCError::new(format!("Can't convert {:?} into {}", value, #type_name), ::cql::RCErrorType::ConversionError))
+                }
+            }
+        }
+
+        impl #impl_generics ::cql::ToCql for #name #ty_generics #where_clause {
+            fn to_cql(&self) -> ::cql::CqlValue {
+                ::cql::CqlValue::CqlUdt(Some(vec![#(#to_fields),*]))
+            }
+        }
+    }
+}
@@ -0,0 +1,90 @@
+extern crate cql;
+#[macro_use]
+extern crate cql_derive;
+
+use std::borrow::Cow;
+use std::sync::Arc;
+use cql::*;
+
+#[derive(Debug, PartialEq, FromRow, IntoValues)]
+struct User {
+	id: i32,
+	#[cql(rename = "user_name")]
+	name: String,
+	email: Option<String>,
+	#[cql(default)]
+	logins: i64,
+	address: Option<Address>
+}
+
+#[derive(Debug, PartialEq, CqlUdt)]
+struct Address {
+	street: String,
+	#[cql(rename = "zip_code")]
+	zip: Option<i32>
+}
+
+fn column(name: &'static str, col_type: CqlColType) -> CqlColMetadata {
+	CqlColMetadata {
+		keyspace: Cow::Borrowed("rust"),
+		table: Cow::Borrowed("users"),
+		col_name: Cow::Borrowed(name),
+		col_type: col_type
+	}
+}
+
+#[test]
+fn test_derive_from_row(){
+	let metadata = Arc::new(CqlMetadata {
+		flags: 0,
+		column_count: 4,
+		keyspace: Cow::Borrowed("rust"),
+		table: Cow::Borrowed("users"),
+		row_metadata: vec![
+			column("id", CqlColType::new(CqlValueType::ColumnInt)),
+			column("user_name", CqlColType::new(CqlValueType::ColumnVarChar)),
+			column("logins", CqlColType::new(CqlValueType::ColumnBigInt)),
+			column("address", CqlColType::new(CqlValueType::ColumnUdt))
+		]
+	});
+	let row = CqlRow {
+		cols: vec![
+			CqlInt(Some(1)),
+			CqlVarchar(Some(Cow::Borrowed("alice"))),
+			CqlBigInt(None),
+			CqlUdt(Some(vec![(Cow::Borrowed("street"), CqlVarchar(Some(Cow::Borrowed("Main St"))))]))
+		],
+		metadata: metadata
+	};
+
+	// email is not selected, logins is null and the address has no zip code
+	let user = User::from_row(&row).unwrap();
+	assert_eq!(user, User {
+		id: 1,
+		name: "alice".to_string(),
+		email: None,
+		logins: 0,
+		address: Some(Address { street: "Main St".to_string(), zip: None })
+	});
+}
+
+#[test]
+fn test_derive_into_values(){
+	let user = User {
+		id: 1,
+		name: "alice".to_string(),
+		email: None,
+		logins: 3,
+		address: Some(Address { street: "Main St".to_string(), zip: Some(8000) })
+	};
+	assert_eq!(user.to_cql_values(), vec![
+		CqlInt(Some(1)),
+		CqlVarchar(Some(Cow::Borrowed("alice"))),
+		CqlRaw(None),
+		CqlBigInt(Some(3)),
+		CqlUdt(Some(vec![
+			(Cow::Borrowed("street"), CqlVarchar(Some(Cow::Borrowed("Main St")))),
+			(Cow::Borrowed("zip_code"), CqlInt(Some(8000)))
+		]))
+	]);
+}
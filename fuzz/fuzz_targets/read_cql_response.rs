@@ -0,0 +1,13 @@
+#![no_main]
+#[macro_use] extern crate libfuzzer_sys;
+extern crate cql;
+
+use cql::CqlReader;
+
+fuzz_target!(|data: &[u8]| {
+    // Any result is fine, as long as decoding doesn't panic
+    for version in 1 .. 4 {
+        let mut bytes = data;
+        let _ = bytes.read_cql_response(version);
+    }
+});
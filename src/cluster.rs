@@ -1,21 +1,22 @@
 
-use std::collections::BTreeMap;
-use std::sync::{Arc, RwLock};
+use std::collections::{BTreeMap, BTreeSet};
+use std::mem;
+use std::sync::{Arc, RwLock, Mutex};
 use std::net::{SocketAddr,IpAddr,Ipv4Addr};
 use std::error::Error;
 use std::thread;
 use mio::{EventLoop,EventLoopConfig, Sender, Handler};
 
-use eventual::Async;
+use eventual::{self, Async, Future};
 use util;
 use def::*;
 use def::TopologyChangeType::*;
 use def::StatusChangeType::*;
 use def::CqlResponseBody::*;
 use def::CqlValue::*;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use node::Node;
-use connection_pool::ConnectionPool;
+use connection_pool::{ConnectionPool, duration_ms};
 use connection::CqlMsg;
 use std::convert::AsRef;
 use std::rc::Rc;
@@ -24,9 +25,29 @@ use std::cell::RefCell;
 use load_balancing::*;
 use error::*;
 use error::RCErrorType::*;
+use convert::ToCqlValues;
+use prepared_cache::PreparedCache;
+use statement::{Statement, StatementQuery};
+use timestamp::{TimestampGenerator, ServerSideTimestampGenerator};
+use query_trace::*;
+use compression::available_compressions;
+use request_limits::{RequestLimits, RequestStats};
+use retry::{RetryPolicy, RetryDecision};
+use cluster_builder::{ClusterBuilder, ClusterConfig};
+use control_connection::{self, ControlConnection, ControlMsg, ShutdownDeadline};
+use schema::SchemaMetadata;
+use cluster_events::{ClusterEvent, ClusterListener, EventBus};
+use uuid::Uuid;
 use std::sync::mpsc;
 
 type ArcMap = Arc<RwLock<BTreeMap<IpAddr,Node>>>;
+const QUERY_TRACE_ATTEMPTS: u32 = 5;
+const QUERY_TRACE_RETRY_MS: u64 = 100;
+type ArcPrepared = Arc<RwLock<PreparedCache>>;
+type ArcSchema = Arc<RwLock<SchemaMetadata>>;
+type ArcEvents = Arc<Mutex<EventBus>>;
+// Replaced when the control connection thread starts
+type ArcControl = Arc<Mutex<mpsc::Sender<ControlMsg>>>;
 
 pub struct Cluster{
 	// Index of the current_node we are using
@@ -36,21 +57,61 @@ pub struct Cluster{
 	channel_cpool: Sender<CqlMsg>,
 	// https://doc.rust-lang.org/error-index.html#E0038
 	balancer:  Arc<RwLock<LoadBalancing+Send+Sync>>,
-	balancer_sender: mpsc::Sender<()>
+	balancer_sender: mpsc::Sender<()>,
+	balancer_thread: Option<thread::JoinHandle<()>>,
+	// Control connection thread, started once connected.
+	// The event handler uses it to confirm topology changes
+	control_sender: ArcControl,
+	control_thread: Option<thread::JoinHandle<()>>,
+	control_shutdown: ShutdownDeadline,
+	// Stops the event handler loop on shutdown
+	channel_event_handler: Sender<EventMsg>,
+	// Event handler and connection pool threads, joined on shutdown
+	threads: Vec<thread::JoinHandle<()>>,
+	// Prepared statements by query string
+	prepared: ArcPrepared,
+	// Updated by the event handler on SCHEMA_CHANGE events
+	schema: ArcSchema,
+	// Subscribers to the events the event handler gets
+	events: ArcEvents,
+	// Default timestamp of statements and batches that don't set one
+	timestamp_generator: Arc<TimestampGenerator>,
+	// Frame compression, shared with the connection pool
+	compression: Arc<RwLock<Compression>>,
+	// In-flight limits enforced by the connection pool, and its counters
+	request_limits: Arc<RwLock<RequestLimits>>,
+	request_stats: Arc<RequestStats>,
+	config: ClusterConfig
 }
 
 
 impl Cluster {
 
 	pub fn new() -> Cluster{
+		Cluster::with_config(ClusterConfig::new())
+	}
+
+	pub fn builder() -> ClusterBuilder{
+		ClusterBuilder::new()
+	}
+
+	// The config isn't validated, ClusterBuilder::build does it
+	pub fn with_config(cluster_config: ClusterConfig) -> Cluster{
 		let availables 	 = Arc::new(RwLock::new(BTreeMap::new()));
 		let unavailables = Arc::new(RwLock::new(BTreeMap::new()));
+		let prepared = Arc::new(RwLock::new(PreparedCache::new()));
+		let schema = Arc::new(RwLock::new(SchemaMetadata::new()));
+		let events = Arc::new(Mutex::new(EventBus::new()));
+		let compression = Arc::new(RwLock::new(cluster_config.compression));
+		let request_limits = Arc::new(RwLock::new(cluster_config.request_limits.clone()));
+		let request_stats = Arc::new(RequestStats::new());
+		let control_sender = Arc::new(Mutex::new(mpsc::channel().0));
 
 		//Start EventLoop<ConnectionPool>
 
 		let mut config = EventLoopConfig::new();
-			config.notify_capacity(65_536)
-	        .messages_per_tick(1_024)
+			config.notify_capacity(cluster_config.notify_capacity)
+	        .messages_per_tick(cluster_config.messages_per_tick)
 	        //.timer_tick(Duration::from_millis(100))
 	        .timer_wheel_size(1_024)
 	        .timer_capacity(65_536);
@@ -60,7 +121,7 @@ impl Cluster {
         let mut channel_cpool= event_loop_conn_pool.channel();
 
 
-    	let balancer = Arc::new(RwLock::new(RoundRobin{index:0}));
+    	let balancer = new_balancer(&cluster_config.balancer);
         let current_node = Arc::new(RwLock::new(IpAddr::V4(Ipv4Addr::new(0,0,0,0))));
 
 		//Start EventLoop<EventHandler>
@@ -70,12 +131,18 @@ impl Cluster {
         let mut event_handler = EventHandler::new(	availables.clone(),
         										  	unavailables.clone(),
         										  	channel_cpool.clone(),
-        										  	current_node.clone());
+        										  	current_node.clone(),
+        										  	prepared.clone(),
+        										  	schema.clone(),
+        										  	events.clone(),
+        										  	control_sender.clone(),
+        										  	event_handler_channel.clone(),
+        										  	cluster_config.clone());
 
         // Only keep the event loop channel
-        thread::Builder::new().name("event_handler".to_string()).spawn(move || {
+        let event_handler_thread = thread::Builder::new().name("event_handler".to_string()).spawn(move || {
         	event_loop.run(&mut event_handler).ok().expect("Failed to start event loop");
-		});
+		}).ok().expect("Couldn't start event handler thread");
 
         
 
@@ -83,22 +150,40 @@ impl Cluster {
         // We will need the event loop to register a new socket
         // but on creating the thread we borrow the even_loop.
         // So we 'give away' the connection pool and keep the channel.
-        let mut connection_pool = ConnectionPool::new(event_handler_channel);
+        let mut connection_pool = ConnectionPool::new(event_handler_channel.clone(), compression.clone(),
+        												request_limits.clone(), request_stats.clone(),
+        												cluster_config.credentials.clone(), cluster_config.connect_timeout,
+        												cluster_config.request_timeout, cluster_config.pool_size,
+        												cluster_config.tls.clone());
 
         //println!("Starting event loop...");
         // Only keep the event loop channel
-        thread::Builder::new().name("connection_pool".to_string()).spawn(move || {
+        let connection_pool_thread = thread::Builder::new().name("connection_pool".to_string()).spawn(move || {
                 event_loop_conn_pool.run(&mut connection_pool).ok().expect("Failed to start event loop");
-        });
+        }).ok().expect("Couldn't start connection pool thread");
 
 
 		Cluster{
 			available_nodes: availables.clone(),
 			unavailable_nodes: unavailables.clone(),
 			channel_cpool: channel_cpool,
-			current_node: Arc::new(RwLock::new(IpAddr::V4(Ipv4Addr::new(0,0,0,0)))),
+			current_node: current_node,
 			balancer: balancer,
-			balancer_sender: mpsc::channel().0
+			balancer_sender: mpsc::channel().0,
+			balancer_thread: None,
+			control_sender: control_sender,
+			control_thread: None,
+			control_shutdown: Arc::new(Mutex::new(None)),
+			channel_event_handler: event_handler_channel,
+			threads: vec![connection_pool_thread, event_handler_thread],
+			prepared: prepared,
+			schema: schema,
+			events: events,
+			timestamp_generator: cluster_config.timestamp_generator.clone(),
+			compression: compression,
+			request_limits: request_limits,
+			request_stats: request_stats,
+			config: cluster_config
 		}
 	}
 
@@ -106,160 +191,385 @@ impl Cluster {
 		let availables = self.available_nodes.clone();
         let current_node = self.current_node.clone();
         let balancer = self.balancer.clone();
-        let tx = 
+        let (tx, handle) = 
 	        util::set_interval(duration,move || {
 	        	//println!("set_interval");
-	        	let mut node = current_node.write().unwrap();
-	        	*node = balancer.write().unwrap().select_node(&availables.read().unwrap());
+	        	let availables = availables.read().unwrap();
+	        	// The current node is kept while every node is down
+	        	if !availables.is_empty() {
+	        		*current_node.write().unwrap() = balancer.write().unwrap().select_node(&availables);
+	        	}
 	        });
 	    self.balancer_sender = tx;
+	    self.balancer_thread = Some(handle);
 	}
-	
-	pub fn set_load_balancing(&mut self,balancer: BalancerType,duration: Duration){
+
+	fn stop_load_balancing(&mut self){
 		//Stop load balancer thread sending a '()' message
 		self.balancer_sender.send(());
-		match balancer{
-			BalancerType::RoundRobin => self.balancer = Arc::new(RwLock::new(RoundRobin{index:0})),
-			BalancerType::LatencyAware => self.balancer = Arc::new(RwLock::new(LatencyAware)),
+		if let Some(handle) = self.balancer_thread.take() {
+			handle.join();
 		}
+	}
+	
+	pub fn set_load_balancing(&mut self,balancer: BalancerType,duration: Duration){
+		self.stop_load_balancing();
+		self.balancer = new_balancer(&balancer);
+		self.config.balancer = balancer;
+		self.config.balancer_interval = duration;
 		self.start_load_balancing(duration);
 	}
 
+	pub fn set_timestamp_generator<T: TimestampGenerator + 'static>(&mut self, generator: T){
+		self.timestamp_generator = Arc::new(generator);
+	}
+
+	// Only connections opened afterwards are affected, so set it before connecting
+	pub fn set_compression(&mut self, compression: Compression) -> RCResult<()>{
+		if compression != Compression::None && !available_compressions().contains(&compression) {
+			return Err(RCError::new(format!("{} compression requires the '{}' feature", compression.as_str(), compression.as_str()), ClusterError));
+		}
+		*try_unwrap!(self.compression.write()) = compression;
+		self.config.compression = compression;
+		Ok(())
+	}
+
+	// Stops accepting requests and gives the in-flight ones until the timeout to be
+	// answered, failing the rest. Then closes the sockets and joins every thread.
+	// It must not be called from a future's callback, those run in the pool thread.
+	pub fn shutdown(&mut self, timeout: Duration) -> RCResult<()>{
+		if self.threads.is_empty() {
+			return Ok(());
+		}
+		self.stop_load_balancing();
+		// The control thread may be reconnecting, it gives up once the deadline passes
+		let deadline = Instant::now() + timeout;
+		if let Ok(mut control_shutdown) = self.control_shutdown.lock() {
+			*control_shutdown = Some(deadline);
+		}
+		if let Ok(control_sender) = self.control_sender.lock() {
+			control_sender.send(ControlMsg::Shutdown);
+		}
+		if let Some(handle) = self.control_thread.take() {
+			handle.join();
+		}
+		let now = Instant::now();
+		let timeout = if now < deadline { deadline - now } else { Duration::from_millis(0) };
+		self.channel_cpool.send(CqlMsg::Shutdown{timeout: timeout});
+		self.channel_event_handler.send(EventMsg::Shutdown);
+		if let Ok(mut events) = self.events.lock() {
+			events.close();
+		}
+		let mut result = Ok(());
+		for handle in self.threads.drain(..) {
+			if handle.join().is_err() {
+				result = Err(RCError::new("A cluster thread panicked", ClusterError));
+			}
+		}
+		result
+	}
+
+	// Applies to the requests sent from now on
+	pub fn set_request_limits(&mut self, limits: RequestLimits) -> RCResult<()>{
+		*try_unwrap!(self.request_limits.write()) = limits.clone();
+		self.config.request_limits = limits;
+		Ok(())
+	}
+
+	// Requests sent to the nodes and not answered yet
+	pub fn in_flight_requests(&self) -> usize{
+		self.request_stats.in_flight()
+	}
+
+	// Requests held back by the in-flight limits
+	pub fn queued_requests(&self) -> usize{
+		self.request_stats.queued()
+	}
+
 	pub fn are_available_nodes(&self) -> bool{
 		self.available_nodes.read()
 							.unwrap()
 							.len() == 0
 	}
 
-	fn add_node(&self,ip: IpAddr) -> RCResult<CqlResponse>{
-		let address = SocketAddr::new(ip,CQL_DEFAULT_PORT);
-		let mut node = Node::new(address,self.channel_cpool.clone());
-		node.set_channel_cpool(self.channel_cpool.clone());
+	pub fn config(&self) -> &ClusterConfig{
+		&self.config
+	}
 
-		let response = {
-			try_unwrap!(node.connect().await())
-		};
+	fn add_node(&self,address: SocketAddr) -> RCResult<CqlResponse>{
+		let node = Node::new(address,self.channel_cpool.clone(),self.config.version);
+		add_node(&self.available_nodes,&self.unavailable_nodes,node)
+	}
 
-		match response {
-			Ok(_) => {
-				try_unwrap!(self.available_nodes.write())
-							.insert(address.ip(),node);
-			}
-			Err(_) =>{
-				try_unwrap!(self.unavailable_nodes.write())
-							.insert(address.ip(),node);
-			}
-		}
-		response
+	// Connects to the contact points set in the config.
+	// This operation blocks
+	pub fn connect(&mut self) -> RCResult<CqlResponse>{
+		let contact_points = self.config.contact_points.clone();
+		self.connect_contact_points(&contact_points)
 	}
 
 	// This operation blocks
 	pub fn connect_cluster(&mut self,address: SocketAddr) -> RCResult<CqlResponse>{
+		self.connect_contact_points(&[address])
+	}
+
+	// Contact points are tried in turn, hostnames are resolved and each of their addresses tried.
+	// The first node that answers gets the event registration and the peers query.
+	// This operation blocks
+	pub fn connect_contact_points<S: ToString>(&mut self,contact_points: &[S]) -> RCResult<CqlResponse>{
 		// No avaiables nodes make sure that 'tick' thread is not writing
-		if self.are_available_nodes(){
-			{
-			let mut node = self.current_node.write().unwrap();
-			*node = address.ip();
-			}
-			{
-			self.start_load_balancing(Duration::from_secs(1));
-			}
-			return self.create_and_register();
+		if !self.are_available_nodes(){
+			return Err(RCError::new("Already connected to cluster", ClusterError))
 		}
-		else{
-			return Err(RCError::new("Already connected to cluster", ClusterError)) 
+		if contact_points.is_empty() {
+			return Err(RCError::new("No contact points set", ClusterError))
 		}
+		let mut errors = vec![];
+		let mut failed = vec![];
+		for contact_point in contact_points.iter().map(|contact_point| contact_point.to_string()) {
+			let addresses = match util::resolve_contact_point(&contact_point,self.config.port) {
+				Ok(addresses) => addresses,
+				Err(err) => {
+					errors.push(err.desc.to_string());
+					continue;
+				}
+			};
+			for address in addresses {
+				{
+				let mut node = self.current_node.write().unwrap();
+				*node = address.ip();
+				}
+				match self.create_and_register(address) {
+					Ok(response) => {
+						let interval = self.config.balancer_interval;
+						self.start_load_balancing(interval);
+						// Contact points that didn't answer are checked again like any down node
+						for (address,error) in failed {
+							self.node_failed(address,error);
+						}
+						return Ok(response);
+					},
+					Err(err) => {
+						errors.push(format!("{}: {}", address, err.desc));
+						failed.push((address,err.desc.to_string()));
+					}
+				}
+			}
+		}
+		Err(RCError::new(format!("Couldn't connect to any contact point -> {}", errors.join(", ")), ConnectionError))
 	}
 
-	fn create_and_register(&mut self) -> RCResult<CqlResponse>{
-		let connect_response = self.add_node(self.current_node.read().unwrap().clone());
-		match connect_response{
-			Ok(_) => {
-				//Register the connection to get Events from Cassandra
-				try_unwrap!(try_unwrap!(self.register().await()));
-
-				//Get the currrent nodes from a system query
-				let peers = try_unwrap!(try_unwrap!(self.get_peers().await()));
-				let ip_nodes = try_unwrap!(parse_nodes(peers));
-				self.create_nodes(ip_nodes);
-			},
-			Err(_) =>{
-				()
+	// Events, peers and the schema come from a control connection to the same node
+	fn create_and_register(&mut self,address: SocketAddr) -> RCResult<CqlResponse>{
+		let connect_response = try!(self.add_node(address));
+		let control = ControlConnection::open(address,&self.config,self.channel_event_handler.clone(),self.control_shutdown.clone())
+							.and_then(|mut conn| {
+								let peers = try!(conn.refresh());
+								let schema = try!(conn.load_schema());
+								Ok((conn,peers,schema))
+							});
+		let (conn,peers,schema) = match control {
+			Ok(control) => control,
+			Err(err) => {
+				// The next contact point is tried, so this one isn't left as the current node
+				let node = try_unwrap!(self.available_nodes.write()).remove(&address.ip());
+				if let Some(node) = node {
+					try_unwrap!(self.unavailable_nodes.write()).insert(address.ip(),node);
+				}
+				return Err(err);
 			}
-		}
-		connect_response
+		};
+		*try_unwrap!(self.schema.write()) = schema;
+		self.create_nodes(peers);
+		let (tx, handle) = control_connection::start(conn,self.config.clone(),self.available_nodes.clone());
+		*try_unwrap!(self.control_sender.lock()) = tx;
+		self.control_thread = Some(handle);
+		Ok(connect_response)
 	}
 
-	
+	// Every subscriber gets its own copy of the events, until the cluster is shut down
+	pub fn subscribe(&self) -> mpsc::Receiver<ClusterEvent>{
+		self.events.lock().unwrap().subscribe()
+	}
+
+	pub fn add_listener<L: ClusterListener + 'static>(&self, listener: L){
+		self.events.lock().unwrap().add_listener(Box::new(listener));
+	}
+
+	// Keyspaces, tables, types, functions and aggregates as last seen
+	pub fn schema(&self) -> SchemaMetadata{
+		self.schema.read().unwrap().clone()
+	}
+
+	// Reads the peers and the schema again through the control connection. Nodes that left
+	// are removed and new ones connected, in the background.
+	pub fn refresh_nodes(&self){
+		self.control_sender.lock().unwrap().send(ControlMsg::Refresh);
+	}
 
 	fn create_nodes(&mut self,ips: Vec<IpAddr>){
 		for ip in ips {
-		    self.add_node(ip);
+			let address = SocketAddr::new(ip,self.config.port);
+			if let Err(err) = self.add_node(address) {
+				self.node_failed(address,err.desc.to_string());
+			}
 		}
 	}
 
+	// The event handler checks it again following the reconnection policy
+	fn node_failed(&self,address: SocketAddr,error: String){
+		self.channel_event_handler.send(EventMsg::NodeFailed{address: address,attempt: 0,error: error});
+	}
 
-	pub fn get_peers(&mut self) -> CassFuture{
+
+	// The balancer's pick, or any available node if it went down since. None once every node is down
+	fn get_current_node(&self) -> Option<Node>{
 		let map = self.available_nodes
 			   .read()
 			   .unwrap();
-		let node = map.get(&self.current_node.read().unwrap())
-					   .unwrap();
-		node.get_peers()
+		map.get(&self.current_node.read().unwrap())
+		   .or_else(|| map.values().next())
+		   .cloned()
+	}
+
+	pub fn get_peers(&mut self) -> CassFuture{
+		match self.get_current_node() {
+			Some(node) => node.get_peers(),
+			None => return Future::of(Err(RCError::new("No available nodes", ClusterError)))
+		}
 	}
 
 
 	pub fn exec_query(&mut self, query_str: &str, con: Consistency) -> CassFuture {
-		let map = self.available_nodes
-					   .read()
-					   .unwrap();
-		let node = map.get(&self.current_node.read().unwrap())
-					   .unwrap();
-					   
-		node.exec_query(query_str,con)
+		self.execute(Statement::query(query_str).consistency(con))
+	}
+
+	//This operation blocks
+	//Waits for the trace of a request sent with Statement::tracing to be complete, the response
+	//of that request has the tracing_id
+	pub fn get_query_trace(&mut self, tracing_id: &Uuid) -> RCResult<QueryTrace> {
+		for attempt in 0..QUERY_TRACE_ATTEMPTS {
+			if attempt > 0 {
+				thread::sleep(Duration::from_millis(QUERY_TRACE_RETRY_MS * attempt as u64));
+			}
+			let session = try!(self.exec_trace_query(TRACE_SESSION_QUERY, tracing_id));
+			let events = try!(self.exec_trace_query(TRACE_EVENTS_QUERY, tracing_id));
+			if let Some(trace) = try!(parse_trace(&session, &events)) {
+				return Ok(trace);
+			}
+		}
+		Err(RCError::new(format!("Trace {} not complete after {} attempts", tracing_id, QUERY_TRACE_ATTEMPTS), NoDataError))
+	}
+
+	fn exec_trace_query(&mut self, query_str: &str, tracing_id: &Uuid) -> RCResult<CqlRows> {
+		let statement = Statement::query(query_str).values(&(tracing_id,)).consistency(Consistency::One);
+		let response = match self.execute(statement).await() {
+			Ok(response) => try!(response),
+			Err(_) => return Err(RCError::new("Error reading query trace, future failed", ClusterError))
+		};
+		match response.body {
+			ResultRows(rows) => Ok(rows),
+			ResponseError(_, ref msg) => Err(RCError::new(format!("Error reading query trace: {}", msg), ClusterError)),
+			_ => Err(RCError::new("Error CqlResponse type must be ResultRows", ClusterError))
+		}
+	}
+
+	// Values of prepared statements are checked against its bind markers before being sent.
+	// If the node answers Unprepared the statement is prepared again on it and executed once more,
+	// unless a schema change invalidated it.
+	// Failed requests are executed again as the retry policy decides.
+	pub fn execute(&mut self, mut statement: Statement) -> CassFuture {
+		statement.apply_defaults(self.config.consistency, self.config.page_size);
+		if statement.params.default_timestamp.is_none() {
+			statement.params.default_timestamp = self.timestamp_generator.next_timestamp();
+		}
+		if let StatementQuery::Prepared(ref preps) = statement.query {
+			match preps.bind(&statement.params.values) {
+				Ok(values) => statement.params.values = values,
+				Err(err) => return Future::of(Err(err))
+			}
+		}
+		let node = match self.get_current_node() {
+			Some(node) => node,
+			None => return Future::of(Err(RCError::new("No available nodes", ClusterError)))
+		};
+
+		execute_on_node(node, self.available_nodes.clone(), self.prepared.clone(), self.config.retry_policy.clone(), statement, 0)
 	}
 
 	//This operation blocks
 	pub fn prepared_statement(&mut self, query_str: &str) -> RCResult<CqlPreparedStat> {
-		let map = self.available_nodes
-			   .read()
-			   .unwrap();
-		let node = map.get(&self.current_node.read().unwrap())
-					   .unwrap();
-					   
-		node.prepared_statement(query_str)
+		match self.prepare(query_str, false).await() {
+			Ok(result) => result,
+			Err(_) => Err(RCError::new("Error preparing statement, future failed", ClusterError))
+		}
 	}
 
-	 pub fn exec_prepared(&mut self, preps: &Vec<u8>, params: &Vec<CqlValue>, con: Consistency) -> CassFuture{
-		let map = self.available_nodes
-			   .read()
-			   .unwrap();
-		let node = map.get(&self.current_node.read().unwrap())
-					   .unwrap();
-					   
-		node.exec_prepared(preps,params,con)
+	// Statements are cached by query string, preparing the same query twice doesn't reach the server
+	// unless all_nodes is set. Then it is prepared on every available node at once and the future
+	// completes when all of them have answered. It fails only if no node could prepare it.
+	pub fn prepare(&mut self, query_str: &str, all_nodes: bool) -> PreparedFuture {
+		if !all_nodes {
+			if let Some(preps) = self.prepared.read().ok().and_then(|cache| cache.get(query_str).cloned()) {
+				return Future::of(Ok(preps));
+			}
+		}
+
+		let nodes: Vec<Node> = if all_nodes {
+			self.available_nodes.read().unwrap().values().cloned().collect()
+		} else {
+			self.get_current_node().into_iter().collect()
+		};
+		if nodes.is_empty() {
+			return Future::of(Err(RCError::new("No available nodes to prepare the statement", ClusterError)));
+		}
+
+		// A node that fails doesn't fail the join, its error is kept with the others
+		let futures: Vec<CassFuture> = nodes.iter().map(|node| settled(node.prepare(query_str))).collect();
+		let prepared = self.prepared.clone();
+		let query = query_str.to_string();
+		eventual::join(futures)
+			.map(move |responses| {
+				let mut result = None;
+				let mut errors = vec![];
+				for (node, response) in nodes.iter().zip(responses.into_iter()) {
+					match parse_prepared(response) {
+						Ok(preps) => if result.is_none() { result = Some(preps) },
+						Err(err) => errors.push(format!("{}: {}", node.get_sock_addr(), err.desc))
+					}
+				}
+				match result {
+					Some(preps) => {
+						if let Ok(mut cache) = prepared.write() {
+							cache.insert(query, preps.clone());
+						}
+						Ok(preps)
+					},
+					None => Err(RCError::new(format!("Error preparing statement -> {}", errors.join(", ")), ClusterError))
+				}
+			})
+	}
+
+	pub fn exec_prepared<V: ToCqlValues + ?Sized>(&mut self, preps: &CqlPreparedStat, params: &V, con: Consistency) -> CassFuture{
+		self.execute(Statement::prepared(preps).values(params).consistency(con))
 	}
 
 	pub fn exec_batch(&mut self, q_type: BatchType, q_vec: Vec<Query>, con: Consistency) -> CassFuture {
-		let map = self.available_nodes
-			   .read()
-			   .unwrap();
-		let node = map.get(&self.current_node.read().unwrap())
-					   .unwrap();
-					   
-		node.exec_batch(q_type,q_vec,con)
+		self.exec_batch_params(q_type, q_vec, CqlQueryParams::new(con))
 	}
 
+	// Consistency, serial consistency and default timestamp of the batch are taken from params
+	pub fn exec_batch_params(&mut self, q_type: BatchType, q_vec: Vec<Query>, mut params: CqlQueryParams) -> CassFuture {
+		if params.default_timestamp.is_none() {
+			params.default_timestamp = self.timestamp_generator.next_timestamp();
+		}
+		match self.get_current_node() {
+			Some(node) => node.exec_batch(q_type,q_vec,params),
+			None => return Future::of(Err(RCError::new("No available nodes", ClusterError)))
+		}
+	}
 
 
-	fn register(&mut self) -> CassFuture{
-		let map = self.available_nodes
-			   		.read()
-			   		.unwrap();
-		let node = 	map.get(&self.current_node.read().unwrap())
-			   			.unwrap();
-		node.send_register()
-	}
 
 	// This temporal until I return some type
 	pub fn show_cluster_information(&self){
@@ -292,46 +602,370 @@ impl Cluster {
 	}
 }
 
+// Connects to the node and keeps it as available or unavailable depending on the result.
+// This operation blocks
+fn add_node(available_nodes: &ArcMap,unavailable_nodes: &ArcMap,node: Node) -> RCResult<CqlResponse>{
+	let address = node.get_sock_addr();
+	let response = match try_unwrap!(node.connect().await()) {
+		Ok(CqlResponse{body: ResponseError(_, ref msg), ..}) =>
+			Err(RCError::new(format!("Error connecting to {}: {}", address, msg), ConnectionError)),
+		response => response
+	};
+
+	// Nodes are kept by ip, so a node is in one of the maps only
+	match response {
+		Ok(_) => {
+			try_unwrap!(unavailable_nodes.write())
+						.remove(&address.ip());
+			try_unwrap!(available_nodes.write())
+						.insert(address.ip(),node);
+		}
+		Err(_) =>{
+			if !try_unwrap!(available_nodes.read()).contains_key(&address.ip()) {
+				try_unwrap!(unavailable_nodes.write())
+							.insert(address.ip(),node);
+			}
+		}
+	}
+	response
+}
+
+// Runs on the thread completing the futures, so it must not block.
+// Retries go to the next available node, the failed one would likely fail again
+fn execute_on_node(node: Node, available_nodes: ArcMap, prepared: ArcPrepared, policy: Arc<RetryPolicy>, statement: Statement, attempt: u32) -> CassFuture {
+	node.execute(&statement)
+		.and_then(move |response| {
+			let decision = match response {
+				Ok(CqlResponse{body: ResponseError(code, _), ..}) if code == CQL_ERROR_UNPREPARED => {
+					if let StatementQuery::Prepared(_) = statement.query {
+						return reprepare_and_exec(node, prepared, statement);
+					}
+					RetryDecision::Rethrow
+				},
+				Ok(CqlResponse{body: ResponseError(code, _), ..}) => policy.on_server_error(&statement, code, attempt),
+				Err(ref err) => policy.on_client_error(&statement, err, attempt),
+				Ok(_) => RetryDecision::Rethrow
+			};
+			let next = match decision {
+				RetryDecision::Retry => next_node(&available_nodes, &node),
+				RetryDecision::Rethrow => None
+			};
+			match next {
+				Some(next) => execute_on_node(next, available_nodes, prepared, policy, statement, attempt + 1),
+				None => Future::of(response)
+			}
+		})
+}
+
+// The node after `node` in the available ones, None if there's no other
+fn next_node(available_nodes: &ArcMap, node: &Node) -> Option<Node> {
+	let map = match available_nodes.read() {
+		Ok(map) => map,
+		Err(_) => return None
+	};
+	let ip = node.get_sock_addr().ip();
+	map.iter()
+	   .find(|&(other, _)| *other > ip)
+	   .or_else(|| map.iter().next())
+	   .and_then(|(other, next)| if *other != ip { Some(next.clone()) } else { None })
+}
+
+// Runs on the thread completing the futures, so it must not block.
+// The values already bound are sent as they are with the new id
+fn reprepare_and_exec(node: Node, prepared: ArcPrepared, mut statement: Statement) -> CassFuture {
+	let id = match statement.query {
+		StatementQuery::Prepared(ref preps) => preps.id.clone(),
+		StatementQuery::Query(_) => return Future::of(Err(RCError::new("Only prepared statements can be prepared again", ClusterError)))
+	};
+	let query = match prepared.read().ok().and_then(|cache| cache.query_of(&id).cloned()) {
+		Some(query) => query,
+		None => return Future::of(Err(RCError::new("Statement unprepared by the node and not found in the cache", ClusterError)))
+	};
+	node.prepare(&query)
+		.and_then(move |response| {
+			let preps = match parse_prepared(response) {
+				Ok(preps) => preps,
+				Err(err) => return Future::of(Err(err))
+			};
+			if let Ok(mut cache) = prepared.write() {
+				cache.insert(query, preps.clone());
+			}
+			statement.query = StatementQuery::Prepared(preps);
+			node.execute(&statement)
+		})
+}
+
+// Prepares every cached statement on a node, without waiting for the responses
+fn prepare_on_node(node: &Node, prepared: &ArcPrepared) {
+	let queries = match prepared.read() {
+		Ok(cache) => cache.queries(),
+		Err(_) => return
+	};
+	for query in queries.iter() {
+		node.prepare(query);
+	}
+}
+
+fn new_balancer(balancer: &BalancerType) -> Arc<RwLock<LoadBalancing+Send+Sync>> {
+	match *balancer {
+		BalancerType::RoundRobin => Arc::new(RwLock::new(RoundRobin{index:0})),
+		BalancerType::LatencyAware => Arc::new(RwLock::new(LatencyAware)),
+	}
+}
+
+// Completes with the result of the request, or with an error if it was dropped unanswered
+fn settled(future: CassFuture) -> CassFuture {
+	let (tx, settled) = Future::pair();
+	future.receive(move |result| {
+		tx.complete(result.unwrap_or_else(|_| Err(RCError::new("The request was dropped without an answer", ClusterError))));
+	});
+	settled
+}
+
+pub fn parse_prepared(response: RCResult<CqlResponse>) -> RCResult<CqlPreparedStat>{
+	match try_rc!(response, "Error preparing statement").body {
+		ResultPrepared(preps) => Ok(preps),
+		ResponseError(_, ref msg) => Err(RCError::new(format!("Error preparing statement: {}", msg), ClusterError)),
+		_ => Err(RCError::new("Response does not contain prepared statement", ClusterError))
+	}
+}
+
 pub fn parse_nodes(response: CqlResponse) -> RCResult<Vec<IpAddr>>{
 		let mut nodes = Vec::new();
 		match response.body {
 			ResultRows(cql_rows) => {
-				if cql_rows.rows.len() > 0 {
-					let rows = cql_rows.rows.clone();
-					for row in rows {
-						//println!("Col: {:?}",row);
-						match *row.cols.get(0).unwrap() {
-							CqlInet(Some(ip)) => {
-								nodes.push(ip);
-							},
-							_ => return Err(RCError::new("Error CqlResponse contains no rows", ReadError)),
-						}
-					}
-					Ok(nodes)
-				}
-				else{
-					Err(RCError::new("Error CqlResponse contains no rows", ReadError))
+				// A single node cluster has no peers
+				let rows = cql_rows.rows.clone();
+				for row in rows {
+					//println!("Col: {:?}",row);
+					nodes.push(try_rc!(row.get::<IpAddr>(0), "Error reading peer address"));
 				}
+				Ok(nodes)
 			},
 			_ => Err(RCError::new("Error CqlResponse type must be ResultRows", ClusterError)),
 		}
 	}
 
+impl Drop for Cluster {
+	fn drop(&mut self){
+		let timeout = self.config.shutdown_timeout;
+		self.shutdown(timeout);
+	}
+}
+
+// Timeouts of the event handler loop
+pub enum EventTimeout {
+	// End of the window topology and status events are coalesced over
+	Flush,
+	// Connection attempt to a node that went down, and the attempt number
+	HealthCheck(IpAddr,u32)
+}
+
 struct EventHandler{
 	available_nodes: ArcMap,
 	unavailable_nodes: ArcMap,
 	channel_cpool: Sender<CqlMsg>,
-	current_node: Arc<RwLock<IpAddr>>
+	current_node: Arc<RwLock<IpAddr>>,
+	prepared: ArcPrepared,
+	schema: ArcSchema,
+	events: ArcEvents,
+	control_sender: ArcControl,
+	// Own loop channel, connection attempts report back through it
+	channel: Sender<EventMsg>,
+	config: ClusterConfig,
+	// Events received since the last flush. Only the last status of a node counts,
+	// and topology changes are confirmed by a refresh.
+	pending_status: BTreeMap<IpAddr,StatusChangeType>,
+	pending_topology: bool,
+	flush_scheduled: bool,
+	// Nodes with a health check scheduled, and with a connection attempt going on
+	health_checks: BTreeSet<IpAddr>,
+	connecting: BTreeSet<IpAddr>
 }
 
 impl EventHandler{
 	fn new(availables: ArcMap,unavailables: ArcMap,channel_cpool : Sender<CqlMsg>,
-		   current_node: Arc<RwLock<IpAddr>>) -> EventHandler{
+		   current_node: Arc<RwLock<IpAddr>>,prepared: ArcPrepared,schema: ArcSchema,
+		   events: ArcEvents,control_sender: ArcControl,channel: Sender<EventMsg>,config: ClusterConfig) -> EventHandler{
 		EventHandler{
 			available_nodes: availables,
 			unavailable_nodes: unavailables,
 			channel_cpool: channel_cpool,
-			current_node: current_node
+			current_node: current_node,
+			prepared: prepared,
+			schema: schema,
+			events: events,
+			control_sender: control_sender,
+			channel: channel,
+			config: config,
+			pending_status: BTreeMap::new(),
+			pending_topology: false,
+			flush_scheduled: false,
+			health_checks: BTreeSet::new(),
+			connecting: BTreeSet::new()
+		}
+	}
+
+	fn schedule_flush(&mut self,event_loop: &mut EventLoop<EventHandler>){
+		if self.flush_scheduled {
+			return;
+		}
+		match event_loop.timeout_ms(EventTimeout::Flush,duration_ms(self.config.event_debounce)) {
+			Ok(_) => self.flush_scheduled = true,
+			Err(_) => self.flush(event_loop)
+		}
+	}
+
+	// Acts on the events of the window that just ended
+	fn flush(&mut self,event_loop: &mut EventLoop<EventHandler>){
+		self.flush_scheduled = false;
+		let statuses = mem::replace(&mut self.pending_status,BTreeMap::new());
+		for (ip,status) in statuses {
+			match status {
+				Down => self.mark_down(event_loop,ip),
+				// Nodes send UP before their native port is open, so it's only a hint
+				Up if self.is_known(&ip) => self.check_health(ip,0),
+				Up => self.pending_topology = true,
+				UnknownStatus => ()
+			}
+		}
+		if mem::replace(&mut self.pending_topology,false) {
+			// The peers are read again, new nodes are connected before being available
+			if let Ok(control_sender) = self.control_sender.lock() {
+				control_sender.send(ControlMsg::Refresh);
+			}
+		}
+	}
+
+	fn is_known(&self,ip: &IpAddr) -> bool{
+		self.available_nodes.read().unwrap().contains_key(ip) ||
+			self.unavailable_nodes.read().unwrap().contains_key(ip)
+	}
+
+	fn mark_down(&mut self,event_loop: &mut EventLoop<EventHandler>,ip: IpAddr){
+		let node = match self.available_nodes.write().unwrap().remove(&ip) {
+			Some(node) => node,
+			None => return
+		};
+		let address = node.get_sock_addr();
+		self.unavailable_nodes.write().unwrap().insert(ip,node);
+		self.replace_current_node(&ip);
+		self.publish(ClusterEvent::HostDown(address));
+		self.schedule_health_check(event_loop,address,0);
+	}
+
+	// Connects to a node that isn't available, which only becomes so once it answers
+	fn check_health(&mut self,ip: IpAddr,attempt: u32){
+		if self.available_nodes.read().unwrap().contains_key(&ip) {
+			return;
+		}
+		let address = match self.unavailable_nodes.read().unwrap().get(&ip) {
+			Some(node) => node.get_sock_addr(),
+			// Removed from the cluster meanwhile
+			None => return
+		};
+		self.start_connect(address,attempt);
+	}
+
+	// The loop isn't blocked while connecting, the outcome comes back
+	// as a NodeUp or NodeFailed message
+	fn start_connect(&mut self,address: SocketAddr,attempt: u32){
+		if !self.connecting.insert(address.ip()) {
+			return;
+		}
+		let channel = self.channel.clone();
+		let node = Node::new(address,self.channel_cpool.clone(),self.config.version);
+		node.connect().receive(move |result| {
+			let error = match result {
+				Ok(Ok(CqlResponse{body: ResponseError(_, ref msg), ..})) => msg.to_string(),
+				Ok(Ok(_)) => {
+					channel.send(EventMsg::NodeUp(address));
+					return;
+				},
+				Ok(Err(err)) => err.desc.to_string(),
+				Err(_) => "Connection attempt dropped".to_string()
+			};
+			channel.send(EventMsg::NodeFailed{address: address,attempt: attempt,error: error});
+		});
+	}
+
+	fn node_up(&mut self,address: SocketAddr){
+		let ip = address.ip();
+		self.connecting.remove(&ip);
+		// Removed from the cluster while connecting
+		let node = match self.unavailable_nodes.write().unwrap().remove(&ip) {
+			Some(node) => node,
+			None => return
+		};
+		prepare_on_node(&node,&self.prepared);
+		self.available_nodes.write().unwrap().insert(ip,node);
+		self.publish(ClusterEvent::HostUp(address));
+	}
+
+	fn node_failed(&mut self,event_loop: &mut EventLoop<EventHandler>,address: SocketAddr,attempt: u32,error: String){
+		self.connecting.remove(&address.ip());
+		if self.unavailable_nodes.read().unwrap().contains_key(&address.ip()) {
+			self.publish(ClusterEvent::HostUnreachable(address,error));
+			self.schedule_health_check(event_loop,address,attempt + 1);
+		}
+	}
+
+	fn schedule_health_check(&mut self,event_loop: &mut EventLoop<EventHandler>,address: SocketAddr,attempt: u32){
+		let ip = address.ip();
+		if self.health_checks.contains(&ip) {
+			return;
+		}
+		match self.config.reconnection_policy.next_delay(attempt) {
+			Some(delay) => {
+				if event_loop.timeout_ms(EventTimeout::HealthCheck(ip,attempt),duration_ms(delay)).is_ok() {
+					self.health_checks.insert(ip);
+				}
+			},
+			// It stays unavailable until it's reported up
+			None => self.publish(ClusterEvent::HostGivenUp(address))
+		}
+	}
+
+	// Requests must not keep going to a node that isn't available
+	fn replace_current_node(&self,ip: &IpAddr){
+		let mut current_node = self.current_node.write().unwrap();
+		if *current_node == *ip {
+			if let Some(other) = self.available_nodes.read().unwrap().keys().next() {
+				*current_node = *other;
+			}
+		}
+	}
+
+	// Nodes that left the cluster are removed and new ones connected, they are unavailable
+	// until they answer. Down nodes are left to their health checks.
+	fn refresh(&mut self,control: SocketAddr,peers: Vec<IpAddr>){
+		let mut ips = peers;
+		ips.push(control.ip());
+		let availables: Vec<IpAddr> = self.available_nodes.read().unwrap().keys().cloned().collect();
+		let unavailables: Vec<IpAddr> = self.unavailable_nodes.read().unwrap().keys().cloned().collect();
+		for ip in availables.iter().filter(|ip| !ips.contains(ip)) {
+			if let Some(node) = self.available_nodes.write().unwrap().remove(ip) {
+				self.replace_current_node(ip);
+				self.publish(ClusterEvent::HostRemoved(node.get_sock_addr()));
+			}
+		}
+		for ip in unavailables.iter().filter(|ip| !ips.contains(ip)) {
+			if let Some(node) = self.unavailable_nodes.write().unwrap().remove(ip) {
+				self.publish(ClusterEvent::HostRemoved(node.get_sock_addr()));
+			}
+		}
+		for ip in ips.into_iter().filter(|ip| !availables.contains(ip) && !unavailables.contains(ip)) {
+			let address = if ip == control.ip() { control } else { SocketAddr::new(ip,self.config.port) };
+			let node = Node::new(address,self.channel_cpool.clone(),self.config.version);
+			self.unavailable_nodes.write().unwrap().insert(ip,node);
+			self.publish(ClusterEvent::HostAdded(address));
+			self.start_connect(address,0);
+		}
+	}
+
+	fn publish(&self,event: ClusterEvent){
+		if let Ok(mut events) = self.events.lock() {
+			events.publish(event);
 		}
 	}
 	pub fn show_cluster_information(&self){
@@ -362,77 +996,87 @@ impl EventHandler{
 }
 
 impl Handler for EventHandler {
-    type Timeout = ();
+    type Timeout = EventTimeout;
 
-    type Message = CqlEvent; 
+    type Message = EventMsg; 
 
-    fn notify(&mut self, event_loop: &mut EventLoop<EventHandler>, msg: CqlEvent) {
+    fn notify(&mut self, event_loop: &mut EventLoop<EventHandler>, msg: EventMsg) {
     	println!("EventHandler::notify");
+    	let msg = match msg {
+    		EventMsg::Event(event) => event,
+    		EventMsg::Refresh{control,peers,schema} => {
+    			match schema {
+    				Ok(schema) => *self.schema.write().unwrap() = schema,
+    				Err(error) => {
+    					self.schema.write().unwrap().stale = true;
+    					self.publish(ClusterEvent::SchemaStale(error));
+    				}
+    			}
+    			self.refresh(control,peers);
+    			return;
+    		},
+    		EventMsg::NodeUp(address) => {
+    			self.node_up(address);
+    			return;
+    		},
+    		EventMsg::NodeFailed{address,attempt,error} => {
+    			self.node_failed(event_loop,address,attempt,error);
+    			return;
+    		},
+    		EventMsg::Publish(event) => {
+    			self.publish(event);
+    			return;
+    		},
+    		EventMsg::Shutdown => {
+    			event_loop.shutdown();
+    			return;
+    		}
+    	};
     	match msg {
-    		CqlEvent::TopologyChange(change_type,socket_addr) =>{
-    			let mut map = 
-	    				self.available_nodes
-						   	.write()
-						   	.ok().expect("Can't write in available_nodes");
-    			match change_type{
-    				NewNode =>{
-    					map.insert(socket_addr.ip(),
-    					Node::new(socket_addr,self.channel_cpool.clone()));
-    				},
-    				RemovedNode =>{
-    					map.remove(&socket_addr.ip());
-    				},
-    				MovedNode =>{
-    					//Not sure about this.
-    					map.insert(socket_addr.ip(),
-    					Node::new(socket_addr,self.channel_cpool.clone()));
-    				},
-    				Unknown => ()
+    		CqlEvent::TopologyChange(change_type,_) =>{
+    			// Confirmed against system.peers once the window ends
+    			if change_type != UnknownChange {
+    				self.pending_topology = true;
+    				self.schedule_flush(event_loop);
     			}
 			},
 			CqlEvent::StatusChange(change_type,socket_addr) =>{
-				//Need for a unavailable_nodes list (down)
-				let mut map_unavailable = self.unavailable_nodes
-					   		.write()
-					   		.ok().expect("Can't write in unavailable_nodes");
-				let mut map_available = self.available_nodes
-										.write()
-										.ok().expect("Can't write in unavailable_nodes");
-				match change_type{
-					Up =>{
-					   	//To-do: treat error if node doesn't exist
-    					let result_node = map_unavailable.remove(&socket_addr.ip());
-
-    					match result_node {
-    					    Some(node) => {  					
-		    					map_available.insert(node.get_sock_addr().ip(),node);
-    						},
-    					    None => println!("Node with ip {:?} wasn't found in unavailable_nodes",&socket_addr.ip()),
-    					}
-  
-					},
-					Down =>{
-					   	//To-do: treat error if node doesn't exist
-    					let result_node = map_available.remove(&socket_addr.ip());
-
-    					match result_node {
-    					    Some(node) => {
-	    						map_unavailable.insert(node.get_sock_addr().ip(),node);
-    					    },
-    					    None => println!("Node with ip {:?} wasn't found in available_nodes",&socket_addr.ip()),
-    					}
-    					
-					},
-					UnknownStatus => ()
+				if change_type != UnknownStatus {
+					self.pending_status.insert(socket_addr.ip(),change_type);
+					self.schedule_flush(event_loop);
 				}
 			},
-			CqlEvent::SchemaChange(change_type,socket_addr) =>{
-				println!("Schema changes are not supported yet");
+			CqlEvent::SchemaChange(change_type,options) =>{
+				self.schema.write().unwrap().apply(change_type,&options);
+				// Statements using a dropped or altered table would have stale metadata. The columns
+				// of a user defined type aren't tracked, so every statement of its keyspace goes
+				let invalidated = match options {
+					SchemaChangeOptions::Keyspace(_) if change_type == SchemaChangeType::Dropped => Some(None),
+					SchemaChangeOptions::Table(_,ref table) if change_type != SchemaChangeType::Created => Some(Some(table.to_string())),
+					SchemaChangeOptions::Type(..) if change_type != SchemaChangeType::Created => Some(None),
+					_ => None
+				};
+				if let Some(table) = invalidated {
+					self.prepared.write().unwrap().invalidate(options.keyspace(),table.as_ref().map(|table| &**table));
+				}
+				// Host events are published once the node maps change
+				if let Some(event) = ClusterEvent::from_cql(&CqlEvent::SchemaChange(change_type,options)) {
+					self.publish(event);
+				}
 			},
 			CqlEvent::UnknownEvent=> {
 				println!("We've got an UnkownEvent");
 			}
 		}
-	
    }
-}
\ No newline at end of file
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<EventHandler>, timeout: EventTimeout) {
+    	match timeout {
+    		EventTimeout::Flush => self.flush(event_loop),
+    		EventTimeout::HealthCheck(ip,attempt) => {
+    			self.health_checks.remove(&ip);
+    			self.check_health(ip,attempt);
+    		}
+    	}
+    }
+}
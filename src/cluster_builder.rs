@@ -0,0 +1,243 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use def::*;
+use error::*;
+use error::RCErrorType::*;
+use cluster::Cluster;
+use compression::available_compressions;
+use tls::TlsConfig;
+#[cfg(feature = "tls")]
+use tls::rustls;
+use load_balancing::BalancerType;
+use reconnection::{ReconnectionPolicy, ExponentialReconnectionPolicy};
+use request_limits::RequestLimits;
+use retry::{RetryPolicy, DefaultRetryPolicy};
+use timestamp::{TimestampGenerator, ServerSideTimestampGenerator};
+
+// Everything a cluster is created with. The defaults are those of Cluster::new().
+#[derive(Clone)]
+pub struct ClusterConfig {
+    // "host", "host:port", "ip" or "ip:port", resolved when connecting
+    pub contact_points: Vec<String>,
+    // Port of the contact points that don't set one, and of the nodes discovered through system.peers
+    pub port: u16,
+    pub version: u8,
+    // User name and password, for PasswordAuthenticator
+    pub credentials: Option<(String, String)>,
+    pub compression: Compression,
+    // Connections are encrypted when set, see ClusterBuilder::tls
+    pub tls: Option<TlsConfig>,
+    pub request_limits: RequestLimits,
+    // Messages the connection pool can have queued, and handles on each event loop tick
+    pub notify_capacity: usize,
+    pub messages_per_tick: usize,
+    pub connect_timeout: Duration,
+    // Requests not answered within it fail, None waits forever
+    pub request_timeout: Option<Duration>,
+    // Connections opened to each node, requests go through the least busy one
+    pub pool_size: usize,
+    // Time given to in-flight requests when the cluster is dropped
+    pub shutdown_timeout: Duration,
+    pub retry_policy: Arc<RetryPolicy>,
+    // Also paces the health checks of nodes that went down
+    pub reconnection_policy: Arc<ReconnectionPolicy>,
+    // Topology and status events are coalesced over this window before being acted on
+    pub event_debounce: Duration,
+    pub balancer: BalancerType,
+    pub balancer_interval: Duration,
+    // Used by statements that don't set their own
+    pub consistency: Consistency,
+    pub page_size: Option<i32>,
+    pub timestamp_generator: Arc<TimestampGenerator>,
+}
+
+impl ClusterConfig {
+    pub fn new() -> ClusterConfig {
+        ClusterConfig {
+            contact_points: vec![],
+            port: CQL_DEFAULT_PORT,
+            version: CQL_MAX_SUPPORTED_VERSION,
+            credentials: None,
+            compression: Compression::None,
+            tls: None,
+            request_limits: RequestLimits::new(),
+            notify_capacity: 65_536,
+            messages_per_tick: 1_024,
+            connect_timeout: Duration::from_secs(5),
+            request_timeout: Some(Duration::from_secs(12)),
+            pool_size: 1,
+            shutdown_timeout: Duration::from_secs(5),
+            retry_policy: Arc::new(DefaultRetryPolicy),
+            reconnection_policy: Arc::new(ExponentialReconnectionPolicy::new(Duration::from_secs(1), Duration::from_secs(600))),
+            event_debounce: Duration::from_secs(1),
+            balancer: BalancerType::RoundRobin,
+            balancer_interval: Duration::from_secs(1),
+            consistency: Consistency::One,
+            page_size: None,
+            timestamp_generator: Arc::new(ServerSideTimestampGenerator),
+        }
+    }
+
+    pub fn validate(&self) -> RCResult<()> {
+        if self.version < 1 || self.version > CQL_MAX_SUPPORTED_VERSION {
+            return Err(RCError::new(format!("Protocol version {} is not supported, it must be between 1 and {}",
+                                            self.version, CQL_MAX_SUPPORTED_VERSION), ClusterError));
+        }
+        if self.credentials.is_some() && self.version < 2 {
+            return Err(RCError::new("Authentication is not supported for v1 protocol", ClusterError));
+        }
+        if self.compression != Compression::None && !available_compressions().contains(&self.compression) {
+            return Err(RCError::new(format!("{} compression requires the '{}' feature",
+                                            self.compression.as_str(), self.compression.as_str()), ClusterError));
+        }
+        if self.request_timeout == Some(Duration::from_secs(0)) {
+            return Err(RCError::new("The request timeout can't be zero, None disables it", ClusterError));
+        }
+        if self.pool_size == 0 {
+            return Err(RCError::new("Nodes need at least one connection", ClusterError));
+        }
+        if self.notify_capacity == 0 || self.messages_per_tick == 0 {
+            return Err(RCError::new("The connection pool queue can't be empty", ClusterError));
+        }
+        Ok(())
+    }
+}
+
+//     let mut cluster = Cluster::builder()
+//                           .contact_points(&["10.0.0.1", "cassandra.local:9042"])
+//                           .credentials("cassandra", "cassandra")
+//                           .consistency(Consistency::Quorum)
+//                           .build()
+//                           .unwrap();
+//     cluster.connect();
+pub struct ClusterBuilder {
+    config: ClusterConfig
+}
+
+impl ClusterBuilder {
+    pub fn new() -> ClusterBuilder {
+        ClusterBuilder {
+            config: ClusterConfig::new()
+        }
+    }
+
+    // A SocketAddr, or a string with a hostname or ip and optionally a port
+    pub fn contact_point<S: ToString>(mut self, address: S) -> ClusterBuilder {
+        self.config.contact_points.push(address.to_string());
+        self
+    }
+
+    pub fn contact_points<S: ToString>(mut self, addresses: &[S]) -> ClusterBuilder {
+        self.config.contact_points.extend(addresses.iter().map(|address| address.to_string()));
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> ClusterBuilder {
+        self.config.port = port;
+        self
+    }
+
+    pub fn protocol_version(mut self, version: u8) -> ClusterBuilder {
+        self.config.version = version;
+        self
+    }
+
+    pub fn credentials(mut self, user: &str, password: &str) -> ClusterBuilder {
+        self.config.credentials = Some((user.to_string(), password.to_string()));
+        self
+    }
+
+    pub fn compression(mut self, compression: Compression) -> ClusterBuilder {
+        self.config.compression = compression;
+        self
+    }
+
+    // Needs the `tls` feature. Certificates are checked against the node's ip address,
+    // not the contact point's hostname, since the nodes are discovered by address.
+    #[cfg(feature = "tls")]
+    pub fn tls(mut self, config: Arc<rustls::ClientConfig>) -> ClusterBuilder {
+        self.config.tls = Some(TlsConfig::new(config));
+        self
+    }
+
+    pub fn request_limits(mut self, limits: RequestLimits) -> ClusterBuilder {
+        self.config.request_limits = limits;
+        self
+    }
+
+    pub fn notify_capacity(mut self, capacity: usize) -> ClusterBuilder {
+        self.config.notify_capacity = capacity;
+        self
+    }
+
+    pub fn messages_per_tick(mut self, messages: usize) -> ClusterBuilder {
+        self.config.messages_per_tick = messages;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> ClusterBuilder {
+        self.config.connect_timeout = timeout;
+        self
+    }
+
+    pub fn request_timeout(mut self, timeout: Option<Duration>) -> ClusterBuilder {
+        self.config.request_timeout = timeout;
+        self
+    }
+
+    pub fn pool_size(mut self, connections: usize) -> ClusterBuilder {
+        self.config.pool_size = connections;
+        self
+    }
+
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> ClusterBuilder {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    pub fn retry_policy<P: RetryPolicy + 'static>(mut self, policy: P) -> ClusterBuilder {
+        self.config.retry_policy = Arc::new(policy);
+        self
+    }
+
+    pub fn reconnection_policy<P: ReconnectionPolicy + 'static>(mut self, policy: P) -> ClusterBuilder {
+        self.config.reconnection_policy = Arc::new(policy);
+        self
+    }
+
+    pub fn event_debounce(mut self, window: Duration) -> ClusterBuilder {
+        self.config.event_debounce = window;
+        self
+    }
+
+    pub fn load_balancing(mut self, balancer: BalancerType, interval: Duration) -> ClusterBuilder {
+        self.config.balancer = balancer;
+        self.config.balancer_interval = interval;
+        self
+    }
+
+    pub fn consistency(mut self, consistency: Consistency) -> ClusterBuilder {
+        self.config.consistency = consistency;
+        self
+    }
+
+    pub fn page_size(mut self, page_size: i32) -> ClusterBuilder {
+        self.config.page_size = Some(page_size);
+        self
+    }
+
+    pub fn timestamp_generator<T: TimestampGenerator + 'static>(mut self, generator: T) -> ClusterBuilder {
+        self.config.timestamp_generator = Arc::new(generator);
+        self
+    }
+
+    pub fn config(&self) -> &ClusterConfig {
+        &self.config
+    }
+
+    pub fn build(self) -> RCResult<Cluster> {
+        try!(self.config.validate());
+        Ok(Cluster::with_config(self.config))
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, Sender};
+
+use def::*;
+use def::TopologyChangeType::*;
+use def::StatusChangeType::*;
+
+// What the cluster tells subscribers about nodes and the schema
+#[derive(Debug, Clone, PartialEq)]
+pub enum ClusterEvent {
+    HostAdded(SocketAddr),
+    HostRemoved(SocketAddr),
+    HostUp(SocketAddr),
+    HostDown(SocketAddr),
+    // A connection attempt to an unavailable node failed, with the error
+    HostUnreachable(SocketAddr, String),
+    // The reconnection policy gave up on an unavailable node
+    HostGivenUp(SocketAddr),
+    // The control connection to a node broke, with the error
+    ControlConnectionLost(SocketAddr, String),
+    // The control connection couldn't be reopened to a node, with the error
+    ControlConnectionFailed(SocketAddr, String),
+    // The control connection was reopened to a node
+    ControlConnectionRestored(SocketAddr),
+    // The reconnection policy gave up reopening the control connection, after the given attempts
+    ControlConnectionGivenUp(u32),
+    SchemaCreated(SchemaChangeOptions),
+    SchemaUpdated(SchemaChangeOptions),
+    SchemaDropped(SchemaChangeOptions),
+    // A refresh couldn't read the schema, with the error. It stays stale until a refresh succeeds
+    SchemaStale(String)
+}
+
+impl ClusterEvent {
+    // None for the events subscribers aren't told about (moved nodes, unknown changes)
+    pub fn from_cql(event: &CqlEvent) -> Option<ClusterEvent> {
+        match *event {
+            CqlEvent::TopologyChange(NewNode, address) => Some(ClusterEvent::HostAdded(address)),
+            CqlEvent::TopologyChange(RemovedNode, address) => Some(ClusterEvent::HostRemoved(address)),
+            CqlEvent::StatusChange(Up, address) => Some(ClusterEvent::HostUp(address)),
+            CqlEvent::StatusChange(Down, address) => Some(ClusterEvent::HostDown(address)),
+            CqlEvent::SchemaChange(SchemaChangeType::Created, ref options) => Some(ClusterEvent::SchemaCreated(options.clone())),
+            CqlEvent::SchemaChange(SchemaChangeType::Updated, ref options) => Some(ClusterEvent::SchemaUpdated(options.clone())),
+            CqlEvent::SchemaChange(SchemaChangeType::Dropped, ref options) => Some(ClusterEvent::SchemaDropped(options.clone())),
+            _ => None
+        }
+    }
+}
+
+// Called from the event handler thread for every event, so it must return quickly.
+// It must not subscribe or add listeners, the subscribers are locked meanwhile.
+pub trait ClusterListener: Send {
+    fn on_event(&self, event: &ClusterEvent);
+}
+
+// Subscribers and listeners of a cluster's events
+pub struct EventBus {
+    subscribers: Vec<Sender<ClusterEvent>>,
+    listeners: Vec<Box<ClusterListener>>
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus {
+            subscribers: vec![],
+            listeners: vec![]
+        }
+    }
+
+    pub fn subscribe(&mut self) -> Receiver<ClusterEvent> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    pub fn add_listener(&mut self, listener: Box<ClusterListener>) {
+        self.listeners.push(listener);
+    }
+
+    // Subscribers whose receiver was dropped are forgotten
+    pub fn publish(&mut self, event: ClusterEvent) {
+        self.subscribers.retain(|tx| tx.send(event.clone()).is_ok());
+        for listener in self.listeners.iter() {
+            listener.on_event(&event);
+        }
+    }
+
+    // Receivers get disconnected, so iterating them ends
+    pub fn close(&mut self) {
+        self.subscribers.clear();
+        self.listeners.clear();
+    }
+}
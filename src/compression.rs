@@ -0,0 +1,96 @@
+#[cfg(feature = "lz4")]
+extern crate lz4_flex;
+#[cfg(feature = "snappy")]
+extern crate snap;
+
+use def::*;
+use error::*;
+use error::RCErrorType::*;
+
+// Algorithms this build was compiled with, in order of preference
+pub fn available_compressions() -> Vec<Compression> {
+    let mut compressions = vec![];
+    if cfg!(feature = "lz4") {
+        compressions.push(Compression::Lz4);
+    }
+    if cfg!(feature = "snappy") {
+        compressions.push(Compression::Snappy);
+    }
+    compressions
+}
+
+pub fn compress(compression: Compression, data: &[u8]) -> RCResult<Vec<u8>> {
+    match compression {
+        Compression::None => Ok(data.to_vec()),
+        Compression::Lz4 => compress_lz4(data),
+        Compression::Snappy => compress_snappy(data)
+    }
+}
+
+pub fn decompress(compression: Compression, data: &[u8]) -> RCResult<Vec<u8>> {
+    match compression {
+        Compression::None => Err(RCError::new("Compressed frame received but no compression was negotiated", ReadError)),
+        Compression::Lz4 => decompress_lz4(data),
+        Compression::Snappy => decompress_snappy(data)
+    }
+}
+
+#[cfg(not(all(feature = "lz4", feature = "snappy")))]
+fn not_compiled(compression: Compression) -> RCError {
+    RCError::new(format!("{} compression requires the '{}' feature", compression.as_str(), compression.as_str()), CompressionError)
+}
+
+// Cassandra prepends the uncompressed length, as a big endian [int], to the LZ4 block
+#[cfg(feature = "lz4")]
+fn compress_lz4(data: &[u8]) -> RCResult<Vec<u8>> {
+    let len = data.len() as u32;
+    let mut buf = vec![(len >> 24) as u8, (len >> 16) as u8, (len >> 8) as u8, len as u8];
+    buf.extend_from_slice(&lz4_flex::block::compress(data));
+    Ok(buf)
+}
+
+#[cfg(feature = "lz4")]
+fn decompress_lz4(data: &[u8]) -> RCResult<Vec<u8>> {
+    if data.len() < 4 {
+        return Err(RCError::new("Error decompressing LZ4 frame (missing length)", CompressionError));
+    }
+    let len = ((data[0] as usize) << 24) | ((data[1] as usize) << 16) | ((data[2] as usize) << 8) | data[3] as usize;
+    // The length comes from the wire, don't let it allocate more than the frame could expand to
+    if len > data.len().saturating_mul(255) {
+        return Err(RCError::new("Error decompressing LZ4 frame (wrong length)", CompressionError));
+    }
+    lz4_flex::block::decompress(&data[4..], len)
+        .map_err(|err| RCError::new(format!("Error decompressing LZ4 frame -> {}", err), CompressionError))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn compress_lz4(_: &[u8]) -> RCResult<Vec<u8>> {
+    Err(not_compiled(Compression::Lz4))
+}
+
+#[cfg(not(feature = "lz4"))]
+fn decompress_lz4(_: &[u8]) -> RCResult<Vec<u8>> {
+    Err(not_compiled(Compression::Lz4))
+}
+
+#[cfg(feature = "snappy")]
+fn compress_snappy(data: &[u8]) -> RCResult<Vec<u8>> {
+    snap::raw::Encoder::new().compress_vec(data)
+        .map_err(|err| RCError::new(format!("Error compressing Snappy frame -> {}", err), CompressionError))
+}
+
+#[cfg(feature = "snappy")]
+fn decompress_snappy(data: &[u8]) -> RCResult<Vec<u8>> {
+    snap::raw::Decoder::new().decompress_vec(data)
+        .map_err(|err| RCError::new(format!("Error decompressing Snappy frame -> {}", err), CompressionError))
+}
+
+#[cfg(not(feature = "snappy"))]
+fn compress_snappy(_: &[u8]) -> RCResult<Vec<u8>> {
+    Err(not_compiled(Compression::Snappy))
+}
+
+#[cfg(not(feature = "snappy"))]
+fn decompress_snappy(_: &[u8]) -> RCResult<Vec<u8>> {
+    Err(not_compiled(Compression::Snappy))
+}
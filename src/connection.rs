@@ -4,11 +4,13 @@ use mio::util::Slab;
 use mio::tcp::TcpStream;
 use mio::{Token,EventLoop, Sender, TryRead, TryWrite, EventSet};
 use bytes::{ByteBuf, MutByteBuf};
-use std::{mem, str};
+use std::{io, mem, str};
 use std::net::{SocketAddr,IpAddr,Ipv4Addr};
+use std::borrow::Cow;
+use std::time::{Duration, Instant};
 use std::error::Error;
 use connection_pool::ConnectionPool;
-use std::collections::{VecDeque,BTreeMap};
+use std::collections::{VecDeque,BTreeMap,BTreeSet};
 use def::*;
 use def::OpcodeRequest::*;
 use def::CqlRequestBody::*;
@@ -17,77 +19,158 @@ use serialize::CqlSerializable;
 use reader::*;
 use error::*;
 use error::RCErrorType::*;
-use util;
+use frame::{FrameBuffer, frame_stream};
+use stream_id::StreamIdAllocator;
+use tls::{TlsConfig, TlsSession};
 
+// Requests are serialized together until the buffer reaches this size
+const WRITE_BUFFER_SIZE: usize = 64 * 1024;
 
 #[derive(Debug)]
 pub struct Connection {
     // The connection's TCP socket 
     socket: TcpStream,
+    // Node address, kept since the socket can't tell it once the connection is lost
+    address: SocketAddr,
     // The token used to register this connection with the EventLoop
     token: mio::Token,
-    // The response from reading a socket
-    response: CassResponse,
+    // Bytes read from the socket that don't make a whole frame yet
+    response: FrameBuffer,
     // Pending messages to be send (CQL requests)
     pendings_send: VecDeque<CqlMsg>,
     // Pending messages to be complete (CQL requests)
     pendings_complete: BTreeMap<i16,CqlMsg>,
+    // Requests held back by the in-flight limits, without a stream id yet
+    waiting: VecDeque<CqlMsg>,
     // CQL version v1, v2 or v3
     version: u8,
     // Channel to EventHandler
-    event_handler: Sender<CqlEvent>,
-    // Stream id  of the next CQL Request to send
-    stream_id: i16
+    event_handler: Sender<EventMsg>,
+    // Stream ids of the requests in flight
+    stream_ids: StreamIdAllocator,
+    // Compression of frame bodies, requested on STARTUP
+    compression: Compression,
+    // What the server answered to OPTIONS, used to build the STARTUP
+    capabilities: Option<ServerCapabilities>,
+    // STARTUP waits until OPTIONS has been answered
+    options_pending: bool,
+    // Serialized requests not written yet, starting at write_pos
+    write_buf: Vec<u8>,
+    write_pos: usize,
+    // User name and password, sent if the server asks for authentication
+    credentials: Option<(String,String)>,
+    // Set once the server has answered READY or AUTH_SUCCESS
+    ready: bool,
+    // Requests not answered within it fail, None waits forever
+    request_timeout: Option<Duration>,
+    // When each request sent through the connection times out, by stream id
+    deadlines: BTreeMap<i16,Instant>,
+    // Stream ids of requests that timed out, not reused until the node answers them
+    timed_out: BTreeSet<i16>,
+    // Set when the cluster was built with TLS, frames go through it
+    tls: Option<TlsSession>
 }
 
 
 impl Connection {
 
-    pub fn new(socket:TcpStream,version: u8,event_handler: Sender<CqlEvent>) -> Connection{
-        let max_request = 
-            match version{
-                1 | 2 => 128,
-                3 => 32768,
-                _ => -1
-            };
-
+    pub fn new(socket:TcpStream,address: SocketAddr,version: u8,event_handler: Sender<EventMsg>,compression: Compression,
+               credentials: Option<(String,String)>,request_timeout: Option<Duration>,tls: Option<TlsSession>) -> Connection{
         Connection {
             socket: socket,
+            address: address,
             token: Token(1),
-            response: CassResponse::new(),
+            response: FrameBuffer::new(),
             pendings_send: VecDeque::new(),
             pendings_complete: BTreeMap::new(),
+            waiting: VecDeque::new(),
             version: version,
             event_handler: event_handler,
-            stream_id: -1
+            stream_ids: StreamIdAllocator::new(version),
+            compression: compression,
+            capabilities: None,
+            options_pending: false,
+            write_buf: Vec::with_capacity(WRITE_BUFFER_SIZE),
+            write_pos: 0,
+            credentials: credentials,
+            ready: false,
+            request_timeout: request_timeout,
+            deadlines: BTreeMap::new(),
+            timed_out: BTreeSet::new(),
+            tls: tls
         }
     }
 
-    pub fn reset_response(&mut self){
-        self.response = CassResponse::new();
+    pub fn address(&self) -> SocketAddr{
+        self.address
     }
 
     pub fn set_token(&mut self, token: Token){
         self.token = token;
     }
 
-    pub fn insert_request(&mut self,msg: CqlMsg) -> RCResult<()>{
+    pub fn is_ready(&self) -> bool{
+        self.ready
+    }
+
+    // The message is completed with an error if there are no stream ids left
+    // The message is given back with the error if no stream id is left
+    fn set_stream(&mut self,msg: CqlMsg) -> Result<CqlMsg,(CqlMsg,RCError)>{
         let mut cql_msg = msg;
-        self.stream_id = 
-        match self.next_stream_id(){
-            Ok(id)=> id,
-            Err(err) =>{
-                cql_msg.complete(Err(RCError::new("Maximum request reached", EventLoopError)));
-                return Err(RCError::new("Maximum request reached", EventLoopError));
+        let stream = match self.stream_ids.allocate(){
+            Some(id)=> id,
+            None =>{
+                let msg = format!("Maximum of {} requests reached for current CQL v{:?}",self.stream_ids.capacity(),self.version);
+                return Err((cql_msg, RCError::new(msg, EventLoopError)));
             },
         };
-        //println!("Stream id provided = {:?} for Token = {:?}",self.stream_id,self.token);
-        try_unwrap!(cql_msg.set_stream(self.stream_id));
-        //println!("Stream id set");  
+        //println!("Stream id provided = {:?} for Token = {:?}",stream,self.token);
+        if let Err(err) = cql_msg.set_stream(stream) {
+            self.stream_ids.release(stream);
+            return Err((cql_msg, err));
+        }
+        Ok(cql_msg)
+    }
+
+    // Whoever sent the message completes it if it can't be inserted
+    pub fn insert_request(&mut self,msg: CqlMsg) -> Result<(),(CqlMsg,RCError)>{
+        let cql_msg = try!(self.set_stream(msg));
+        // The handshake has the connect timeout instead
+        if let (&CqlMsg::Request{ref request,..},Some(timeout)) = (&cql_msg,self.request_timeout) {
+            self.deadlines.insert(request.stream,Instant::now() + timeout);
+        }
         self.pendings_send.push_front(cql_msg);
         Ok(())
     }
 
+    // Fails the requests whose deadline has passed. The stream ids of those already sent stay
+    // in use until the node answers them, so a late answer can't complete another request.
+    pub fn expire_requests(&mut self,now: Instant){
+        let expired: Vec<i16> = self.deadlines.iter()
+                                    .filter(|&(_,deadline)| *deadline <= now)
+                                    .map(|(stream,_)| *stream)
+                                    .collect();
+        for stream in expired {
+            self.deadlines.remove(&stream);
+            let msg = match self.pendings_complete.remove(&stream) {
+                Some(msg) => {
+                    self.timed_out.insert(stream);
+                    Some(msg)
+                },
+                None => {
+                    let position = self.pendings_send.iter().position(|msg| msg.get_stream().ok() == Some(stream));
+                    position.and_then(|position| {
+                        self.stream_ids.release(stream);
+                        self.pendings_send.remove(position)
+                    })
+                }
+            };
+            if let Some(msg) = msg {
+                msg.complete(Err(RCError::new(format!("Request to {} timed out", self.address), TimeoutError)));
+            }
+        }
+    }
+
     pub fn are_pendings_send(&self) -> bool{
         !self.pendings_send.is_empty()
     }
@@ -96,130 +179,226 @@ impl Connection {
         !self.pendings_complete.is_empty()
     }
 
-    fn total_requests(&self) -> usize{
-        self.pendings_complete.len()+self.pendings_send.len() 
+    pub fn in_flight(&self) -> usize{
+        self.stream_ids.in_flight()
     }
 
-    fn max_requests_reached(&self) -> bool{
-        util::max_stream_id(self.stream_id,self.version) 
-        && (self.total_requests() as i32) >= self.stream_id as i32
+    pub fn max_in_flight(&self) -> usize{
+        self.stream_ids.capacity()
     }
 
-    fn next_stream_id(&self) -> RCResult<i16>{
-        let mut stream_id = 0;
-        if self.are_pendings_send() || self.are_pendings_complete(){
-            if !util::max_stream_id(self.stream_id,self.version) {
-                stream_id = self.stream_id+1;
-            }
-            else{      
-                if !self.max_requests_reached()
-                {
-                    // Look up for the lowest stream_id that is not in pendings_send nor in pendings_complete 
-                    // (yes, it's a bit slow)
-                    let mut lowest = CQL_MAX_STREAM_ID_V3;
-                    let mut previous_id = -99999;
-
-                    for pendings in self.pendings_send.iter(){
-                        let next_id = pendings.get_stream().unwrap();
-                        if  next_id as i32 - previous_id as i32 > 1{
-                            lowest = next_id;
-                        }
-                        previous_id = next_id;
-                    }
+    pub fn queue_waiting(&mut self,msg: CqlMsg){
+        self.waiting.push_back(msg);
+    }
 
-                    for pendings in self.pendings_send.iter(){
-                        let next_id = pendings.get_stream().unwrap();
-                        if  previous_id as i32 - next_id as i32 > 1{
-                            lowest = next_id;
-                        }
-                        previous_id = next_id;
-                    }
-                    stream_id = lowest;
+    pub fn pop_waiting(&mut self) -> Option<CqlMsg>{
+        self.waiting.pop_front()
+    }
+
+    pub fn waiting_len(&self) -> usize{
+        self.waiting.len()
+    }
+
+    // Completes every request not answered yet with an error
+    pub fn fail_all(&mut self,msg: &str){
+        let pendings = self.waiting.drain(..)
+                           .chain(self.pendings_send.drain(..))
+                           .chain(mem::replace(&mut self.pendings_complete,BTreeMap::new()).into_iter().map(|(_,msg)| msg))
+                           .collect::<Vec<CqlMsg>>();
+        for pending in pendings {
+            pending.complete(Err(RCError::new(msg.to_string(), ClusterError)));
+        }
+        self.write_buf.clear();
+        self.write_pos = 0;
+        self.stream_ids = StreamIdAllocator::new(self.version);
+        self.deadlines.clear();
+        self.timed_out.clear();
+    }
+
+    // Reads until the socket would block. Fails if the socket is closed or broken,
+    // frames read before that are kept
+    pub fn read(&mut self, event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()> {
+        if self.tls.is_some() {
+            return self.read_tls(event_loop);
+        }
+        loop {
+            let mut buf = ByteBuf::mut_with_capacity(2048);
+
+            match self.socket.try_read_buf(&mut buf) {
+                Ok(Some(0)) => {
+                    //println!("read 0 bytes");
+                    return Err(RCError::new("Connection closed by the node", ConnectionError))
+                }
+                Ok(Some(n)) => {
+                    self.response.extend(&buf.bytes());
+                    //println!("read {} bytes", n);
+                    //println!("Read: {:?}",buf.bytes());
                 }
-                else{
-                    // Max requests reached
-                    return Err(RCError::new(format!("Maximum request reached for current CQL v{:?}",self.version)
-                            , RCErrorType::EventLoopError));
+                Ok(None) => {
+                    //println!("Reading buf = None");
+                    let interest = self.interest();
+                    self.reregister(event_loop,interest);
+                    return Ok(())
+                }
+                Err(e) => {
+                    return Err(RCError::new(format!("Error reading from the node -> {}", e), ConnectionError))
                 }
             }
         }
-        Ok(stream_id)
     }
 
-    fn decrease_stream(&mut self,stream: i16){
-        if stream == self.stream_id{
-            self.stream_id=self.stream_id-1;
+    fn read_tls(&mut self, event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()> {
+        let tls = self.tls.as_mut().unwrap();
+        loop {
+            match tls.read_from(&mut self.socket, &mut self.response) {
+                Ok(0) => return Err(RCError::new("Connection closed by the node", ConnectionError)),
+                Ok(_) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(RCError::new(format!("Error reading from the node -> {}", e), ConnectionError))
+            }
         }
+        // The handshake may have something to answer
+        let interest = self.interest();
+        self.reregister(event_loop,interest);
+        Ok(())
     }
 
-    pub fn read(&mut self, event_loop: &mut EventLoop<ConnectionPool>) {
-        let mut buf = ByteBuf::mut_with_capacity(2048);
-
-        match self.socket.try_read_buf(&mut buf) {
-            Ok(Some(0)) => {
-                //println!("read 0 bytes");
+    // Serializes pending requests into the write buffer until it holds WRITE_BUFFER_SIZE bytes
+    fn fill_write_buffer(&mut self) {
+        while self.write_buf.len() < WRITE_BUFFER_SIZE && self.are_pendings_send() {
+            if self.options_pending && self.is_startup_next() {
+                break;
             }
-            Ok(Some(n)) => {
-                self.response.mut_read_buf().extend_from_slice(&buf.bytes());
-                //println!("read {} bytes", n);
-                //println!("Read: {:?}",buf.bytes());
-                self.read(event_loop);  //Recursion here, care
+            match self.pendings_send
+                      .pop_back()
+                      .unwrap()
+                 {
+                 CqlMsg::Request{request,tx,address} => {
+                    //println!("Sending a request.");
+                    // Frames share the buffer, so a request is only added once it's serialized whole
+                    let mut frame = Vec::new();
+                    match request.serialize_compressed(&mut frame,self.version,self.compression) {
+                        Ok(_) => {
+                            self.write_buf.extend_from_slice(&frame);
+                            self.pendings_complete.insert(request.stream,CqlMsg::Request{request:request,tx:tx,address:address});
+                        },
+                        Err(err) => {
+                            self.stream_ids.release(request.stream);
+                            self.deadlines.remove(&request.stream);
+                            tx.complete(Err(err));
+                        }
+                    }
+                 },
+                 CqlMsg::Connect{mut request,tx,address} =>{
+                    //println!("Sending a connect request.");
+                    if request.opcode == OpcodeStartup {
+                        request.body = RequestStartup(self.startup_body());
+                    }
+                    let mut frame = Vec::new();
+                    match request.serialize(&mut frame,self.version) {
+                        Ok(_) => {
+                            self.write_buf.extend_from_slice(&frame);
+                            self.pendings_complete.insert(request.stream,CqlMsg::Connect{request:request,tx:tx,address:address});
+                        },
+                        Err(err) => {
+                            self.stream_ids.release(request.stream);
+                            tx.complete(Err(err));
+                        }
+                    }
+                 },
+                 CqlMsg::Shutdown{..} => {
+                    panic!("Shutdown messages shouldn't be at pendings");
+                 },
+            }
+        }
+    }
+
+    // Whether there are bytes left to write or requests that can be sent
+    pub fn has_writes(&self) -> bool {
+        self.write_pos < self.write_buf.len()
+        || self.tls.as_ref().map_or(false, |tls| tls.wants_write())
+        || (self.are_pendings_send() && !(self.options_pending && self.is_startup_next()))
+    }
+
+    fn interest(&self) -> EventSet {
+        if self.has_writes() {
+            EventSet::readable() | EventSet::writable()
+        } else {
+            EventSet::readable()
+        }
+    }
 
+    // Writes as many requests as possible in each syscall and keeps writing until
+    // the socket would block. What's left of a partial write is sent first next time.
+    pub fn write(&mut self, event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()> {
+        //println!("self.pendings.len = {:?}",self.pendings_send.len());
+        if self.tls.is_some() {
+            try!(self.write_tls());
+            let interest = self.interest();
+            self.reregister(event_loop,interest);
+            return Ok(());
+        }
+        loop {
+            if self.write_pos == self.write_buf.len() {
+                self.write_buf.clear();
+                self.write_pos = 0;
+                self.fill_write_buffer();
+                if self.write_buf.is_empty() {
+                    break;
+                }
             }
-            Ok(None) => {
-                //println!("Reading buf = None");
-                if !self.are_pendings_send(){
-                    self.reregister(event_loop,EventSet::readable());
+            match self.socket.try_write(&self.write_buf[self.write_pos..]) {
+                Ok(Some(0)) => {
+                    // Writing nothing to a writable socket means it's closed
+                    return Err(RCError::new("Connection closed by the node", ConnectionError));
                 }
-                else{
-                    self.reregister(event_loop,EventSet::writable());
+                Ok(Some(n)) => {
+                    //println!("Written {} bytes",n);
+                    self.write_pos += n;
+                }
+                Ok(None) => {
+                    // The socket isn't ready, the event loop will tell us when it is
+                    break;
+                }
+                Err(e) => {
+                    return Err(RCError::new(format!("Error writing to the node -> {}", e), ConnectionError));
                 }
-            }
-            Err(e) => {
-                panic!("got an error trying to read; err={:?}", e);
             }
         }
+        let interest = self.interest();
+        self.reregister(event_loop,interest);
+        Ok(())
+
+        //println!("Ended write"); 
     }
 
-    pub fn write(&mut self, event_loop: &mut EventLoop<ConnectionPool>) {
-        let mut buf = ByteBuf::mut_with_capacity(2048);
-        //println!("self.pendings.len = {:?}",self.pendings_send.len());
-        match self.pendings_send
-                  .pop_back()
-                  .unwrap()
-             {
-             CqlMsg::Request{request,tx,address} => {
-                //println!("Sending a request.");
-                request.serialize(&mut buf,self.version);
-                //println!("Sending: {:?}",request);
-                self.pendings_complete.insert(request.stream,CqlMsg::Request{request:request,tx:tx,address:address});
-             },
-             CqlMsg::Connect{request,tx,address} =>{
-                //println!("Sending a connect request.");
-                request.serialize(&mut buf,self.version);
-                self.pendings_complete.insert(request.stream,CqlMsg::Connect{request:request,tx:tx,address:address});
-             },
-             CqlMsg::Shutdown => {
-                panic!("Shutdown messages shouldn't be at pendings");
-             },
-        }
-        match self.socket.try_write_buf(&mut buf.flip()) 
-            {
-            Ok(Some(n)) => {
-                //println!("Written {} bytes",n);
-                self.reregister(event_loop,EventSet::readable());
+    // Hands the write buffer to the TLS session and sends its records until the socket
+    // would block, the session can't take more (handshake not done) or nothing is left
+    fn write_tls(&mut self) -> RCResult<()> {
+        loop {
+            if self.write_pos == self.write_buf.len() {
+                self.write_buf.clear();
+                self.write_pos = 0;
+                self.fill_write_buffer();
+            }
+            let tls = self.tls.as_mut().unwrap();
+            let mut taken = 0;
+            if self.write_pos < self.write_buf.len() {
+                match tls.write_plain(&self.write_buf[self.write_pos..]) {
+                    Ok(n) => taken = n,
+                    Err(e) => return Err(RCError::new(format!("Error writing to the node -> {}", e), ConnectionError))
+                }
             }
-            Ok(None) => {
-                // The socket wasn't actually ready, re-register the socket
-                // with the event loop
-                self.reregister(event_loop,EventSet::writable());
+            self.write_pos += taken;
+            match tls.write_to(&mut self.socket) {
+                Ok(_) => {},
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(RCError::new(format!("Error writing to the node -> {}", e), ConnectionError))
             }
-            Err(e) => {
-                panic!("got an error trying to read; err={:?}", e);
+            if taken == 0 {
+                return Ok(());
             }
         }
-
-        //println!("Ended write"); 
     }
 
     pub fn reregister(&self, event_loop: &mut EventLoop<ConnectionPool>,events : EventSet) {
@@ -233,6 +412,12 @@ impl Connection {
                   .ok().expect("Couldn't reregister connection");
     }
     
+    pub fn deregister(&self, event_loop: &mut EventLoop<ConnectionPool>) {
+        if event_loop.deregister(&self.socket).is_err() {
+            println!("Couldn't deregister connection");
+        }
+    }
+
     pub fn register(&self, event_loop: &mut EventLoop<ConnectionPool>,events : EventSet) {
 
         //println!("Connection::register");
@@ -244,95 +429,102 @@ impl Connection {
                     .ok().expect("Couldn't register connection");;
     }
 
-    pub fn queue_message(&mut self,event_loop: &mut EventLoop<ConnectionPool>,request: CqlMsg){
+    pub fn queue_message(&mut self,event_loop: &mut EventLoop<ConnectionPool>,request: CqlMsg) -> RCResult<()>{
+        let request = match self.set_stream(request) {
+            Ok(request) => request,
+            Err((request, err)) => {
+                request.complete(Err(err.clone()));
+                return Err(err);
+            }
+        };
         self.pendings_send.push_back(request);    //Inserted in the last position to give it more priority
         //self.reregister(event_loop,EventSet::writable());
+        Ok(())
     }
 
 
-    fn approve_authenticator(&self, authenticator: &CowStr) -> bool {
-        authenticator == "org.apache.cassandra.auth.PasswordAuthenticator"
+    // Answer to an AUTHENTICATE, sent in place of the STARTUP's response
+    fn auth_response(&self, authenticator: &CowStr) -> RCResult<CqlRequest> {
+        auth_response(authenticator, self.version, &self.credentials)
     }
 
-    ///
-    /// Makes an authentication response token that is compatible with PasswordAuthenticator.
-    ///
-    fn make_token(&self, creds: &Vec<CowStr>) -> Vec<u8> {
-        let mut token : Vec<u8> = Vec::new();
-        for cred in creds {
-            token.push(0);
-            token.extend(cred.as_bytes());
+    fn is_startup_next(&self) -> bool {
+        match self.pendings_send.back() {
+            Some(&CqlMsg::Connect{ref request,..}) => request.opcode == OpcodeStartup,
+            _ => false
         }
-        return token;
-    }
-
-    pub fn continue_startup_request(&mut self,response: CqlResponse ,event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()> {
-        
-        match response.body {
-            ResponseReady =>  Ok(()),
-            /*
-            ResponseAuthenticate(authenticator) => {
-                if self.approve_authenticator(&authenticator) {
-                    match creds {
-                        Some(ref cred) => {
-                            if self.version >= 2 {
-                                let msg_auth = CqlRequest {
-                                    version: self.version,
-                                    flags: 0x00,
-                                    stream: 0x01,
-                                    opcode: OpcodeAuthResponse,
-                                    body: RequestAuthResponse(self.make_token(cred)),
-                                };
-                                self.queue_message(event_loop,msg_auth);
-
-                            } else {
-                                Err(RCError::new("Authentication is not supported for v1 protocol", ReadError)) 
-                            }
-                        },
-                        None => Err(RCError::new("Credential should be provided for authentication", ReadError))
-                    }
-                } else {
-                    Err(RCError::new(format!("Unexpected authenticator: {}", authenticator), ReadError))
-                }
+    }
 
-            },
-            */
-            ResponseAuthSuccess(_) => Ok(()),
-            // ResponseError(_, ref msg) => Err(RCError::new(format!("Error in authentication: {}", msg), ReadError)),
-            ResponseError(_, ref msg) => Err(RCError::new(format!("Error connecting: {}", msg), ReadError)),
-            _ => Err(RCError::new("Wrong response to startup", ReadError))
+    // CQL_VERSION and COMPRESSION are chosen among the ones the server supports
+    fn startup_body(&self) -> CqlStringMap {
+        let default_version = CQL_VERSION_STRINGS[(self.version-1) as usize];
+        let cql_version = match self.capabilities {
+            Some(ref capabilities) => capabilities.cql_version().unwrap_or(default_version).to_string(),
+            None => default_version.to_string()
+        };
+        let mut pairs = vec![CqlPair{key: "CQL_VERSION", value: Cow::Owned(cql_version)}];
+        if self.compression != Compression::None {
+            pairs.push(CqlPair{key: "COMPRESSION", value: Cow::Borrowed(self.compression.as_str())});
+        }
+        CqlStringMap {
+            pairs: pairs,
         }
     }
 
-    pub fn send_startup(&mut self, creds: Option<Vec<CowStr>>,event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()>{
-        //println!("Connection::send_startup");
-        let body = CqlStringMap {
-            pairs:vec![CqlPair{key: "CQL_VERSION", value: CQL_VERSION_STRINGS[(self.version-1) as usize]}],
-        };
-        let msg_startup = CqlRequest {
+    // The connection fails if the server doesn't support the compression asked for
+    fn set_capabilities(&mut self, capabilities: ServerCapabilities) -> RCResult<()> {
+        if !capabilities.supports_compression(self.compression) {
+            return Err(RCError::new(format!("Server at {} doesn't support {} compression", self.address, self.compression.as_str()), ConnectionError));
+        }
+        self.capabilities = Some(capabilities);
+        Ok(())
+    }
+
+    // First message of the handshake, the STARTUP is sent once it is answered
+    pub fn send_options(&mut self, event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()>{
+        let msg_options = CqlRequest {
             version: self.version,
             flags: 0x00,
             stream: 0x01,
-            opcode: OpcodeStartup,
-            body: RequestStartup(body),
+            opcode: OpcodeOptions,
+            body: RequestOptions,
         };
-        let (tx, future) = Future::<RCResult<CqlResponse>, ()>::pair();
+        let (tx, _) = Future::<RCResult<CqlResponse>, ()>::pair();
 
         let msg_connect = CqlMsg::Connect{
-            request: msg_startup,
+            request: msg_options,
             tx: tx,
             // This macro can return an error
             address: try_unwrap!(self.socket.peer_addr())
         };
-        let mut future = self.queue_message(event_loop,msg_connect);
+        try!(self.queue_message(event_loop,msg_connect));
+        self.options_pending = true;
         Ok(())
     }
 
-    pub fn read_cql_response(&self) -> (RCResult<CqlResponse>,bool){
-        self.response.read_cql_response(self.version)
+    // Parses the next complete frame read so far, if any
+    // The stream id comes from the header, so a frame that can't be decoded
+    // still fails the request it answers
+    pub fn read_cql_response(&mut self) -> Option<(i16,RCResult<CqlResponse>,bool)>{
+        let frame = match self.response.next_frame(self.version) {
+            Some(frame) => frame,
+            None => return None
+        };
+        let stream = frame_stream(&frame,self.version);
+        let cql_response = match frame.as_slice().read_cql_response_compressed(self.version,self.compression) {
+            Ok(val) => val,
+            Err(ref err) => {
+                // Events are sent with a negative stream id
+                return Some((stream,Err(RCError::new(format!("Error decoding the response -> {}", err.description()), RCErrorType::IOError)),stream < 0))
+            }
+        };
+        let is_event = cql_response.is_event();
+        Some((stream,Ok(cql_response),is_event))
     }
 
-    pub fn handle_response(&mut self,response: RCResult<CqlResponse>, event_loop: &mut EventLoop<ConnectionPool>, is_event : bool ){
+    // Fails if a frame can't be decoded and it isn't known which request it answers,
+    // the connection can't be trusted anymore then
+    pub fn handle_response(&mut self,stream: i16,response: RCResult<CqlResponse>, event_loop: &mut EventLoop<ConnectionPool>, is_event : bool ) -> RCResult<()>{
         if is_event {
             //println!("It seems we've got an event!");
             //Do event stuff
@@ -340,29 +532,39 @@ impl Connection {
                 Ok(event) => {
                     match event.body {
                         ResponseEvent(cql_event) =>{
-                            self.event_handler.send(cql_event);
+                            self.event_handler.send(EventMsg::Event(cql_event));
                         },
                         _ =>{
                             println!("Oops! The event wasn't an event at all..");
                         }
                     }
                 },
-                RCError => (),
+                Err(err) => return Err(err)
             }
         }
         else{
+            // Its request already failed
+            if self.timed_out.remove(&stream) {
+                self.stream_ids.release(stream);
+                return Ok(());
+            }
+            self.deadlines.remove(&stream);
             let cql_response = 
             match response{
                 Ok(res) => {
                     res
                 },
                 Err(err) =>{
-                    //TODO handle error
-                    println!("Error in response {:?}",err);
-                    return;
+                    return match self.pendings_complete.remove(&stream) {
+                        Some(pending) => {
+                            self.stream_ids.release(stream);
+                            pending.complete(Err(err));
+                            Ok(())
+                        },
+                        None => Err(err)
+                    };
                 },
             };
-            let stream = cql_response.stream;
             // Completes the future with a CqlResponse
             // which is a RCResult<CqlResponse>
             // so we can handle errors properly
@@ -372,15 +574,31 @@ impl Connection {
                 {
                     Some(CqlMsg::Request{request,tx,address}) => {
                         tx.complete(Ok(cql_response));
-                        self.decrease_stream(stream);
+                        self.stream_ids.release(stream);
                     },
                     Some(CqlMsg::Connect{request,tx,address}) => {
-                        //let result = self.continue_startup_request(response.clone().unwrap(),event_loop);
+                        self.stream_ids.release(stream);
+                        match cql_response.body {
+                            ResponseSupported(ref capabilities) if request.opcode == OpcodeOptions => {
+                                self.options_pending = false;
+                                try!(self.set_capabilities(capabilities.clone()));
+                            },
+                            ResponseReady | ResponseAuthSuccess(_) => self.ready = true,
+                            // The connect future completes when the authentication does
+                            ResponseAuthenticate(ref authenticator) => {
+                                match self.auth_response(authenticator) {
+                                    Ok(auth) => {
+                                        let _ = self.queue_message(event_loop,CqlMsg::Connect{request:auth,tx:tx,address:address});
+                                    },
+                                    Err(err) => tx.complete(Err(err))
+                                }
+                                return Ok(());
+                            },
+                            _ => ()
+                        }
                         tx.complete(Ok(cql_response));
-                        self.decrease_stream(stream);
-
                     },
-                    Some(CqlMsg::Shutdown) => {
+                    Some(CqlMsg::Shutdown{..}) => {
                         panic!("Shutdown messages shouldn't be at pendings");
                     },
                     None =>{
@@ -389,12 +607,63 @@ impl Connection {
                 }
             }
         }
+        Ok(())
+    }
+}
+
+fn approve_authenticator(authenticator: &CowStr) -> bool {
+    authenticator == "org.apache.cassandra.auth.PasswordAuthenticator"
+}
+
+///
+/// Makes an authentication response token that is compatible with PasswordAuthenticator.
+///
+fn make_token(user: &str, password: &str) -> Vec<u8> {
+    let mut token : Vec<u8> = Vec::new();
+    for cred in &[user, password] {
+        token.push(0);
+        token.extend(cred.as_bytes());
     }
+    return token;
 }
 
-pub fn connect(address: SocketAddr, creds:Option<Vec<CowStr>>,event_loop: &mut EventLoop<ConnectionPool>,event_handler: Sender<CqlEvent>) -> RCResult<Connection> {
+// AUTH_RESPONSE to the AUTHENTICATE a node answers to STARTUP
+// The body is filled in from the server's OPTIONS answer before it's sent
+pub fn startup_request(version: u8) -> CqlRequest {
+    let body = CqlStringMap {
+        pairs:vec![CqlPair{key: "CQL_VERSION", value: Cow::Borrowed(CQL_VERSION_STRINGS[(version-1) as usize])}],
+    };
+    CqlRequest {
+        version: version,
+        flags: 0x00,
+        stream: 0x01,
+        opcode: OpcodeStartup,
+        body: RequestStartup(body),
+    }
+}
+
+pub fn auth_response(authenticator: &CowStr, version: u8, credentials: &Option<(String,String)>) -> RCResult<CqlRequest> {
+    if !approve_authenticator(authenticator) {
+        return Err(RCError::new(format!("Unexpected authenticator: {}", authenticator), AuthenticationError));
+    }
+    if version < 2 {
+        return Err(RCError::new("Authentication is not supported for v1 protocol", AuthenticationError));
+    }
+    match *credentials {
+        Some((ref user, ref password)) => Ok(CqlRequest {
+            version: version,
+            flags: 0x00,
+            stream: 0x01,
+            opcode: OpcodeAuthResponse,
+            body: RequestAuthResponse(make_token(user, password)),
+        }),
+        None => Err(RCError::new("Credential should be provided for authentication", AuthenticationError))
+    }
+}
+
+pub fn connect(address: SocketAddr, version: u8, creds: Option<(String,String)>,event_loop: &mut EventLoop<ConnectionPool>,event_handler: Sender<EventMsg>,compression: Compression,
+               request_timeout: Option<Duration>,tls: Option<&TlsConfig>) -> RCResult<Connection> {
 
-    let mut version = CQL_MAX_SUPPORTED_VERSION;
     //println!("Connection::connect");
 
     let res = TcpStream::connect(&address);
@@ -402,11 +671,15 @@ pub fn connect(address: SocketAddr, creds:Option<Vec<CowStr>>,event_loop: &mut E
         return Err(RCError::new(format!("Failed to connect to server at {}", address), ConnectionError));
     }
     let mut socket = res.ok().expect("Failed to unwrap the socket");
-    let mut conn = Connection::new(socket,version,event_handler);
+    let tls = match tls {
+        Some(config) => Some(try!(config.session(address.ip()))),
+        None => None
+    };
+    let mut conn = Connection::new(socket,address,version,event_handler,compression,creds,request_timeout,tls);
     // Once a connection is created we have to register it,
     // later on we can 'reregister' if necessary
     conn.register(event_loop,EventSet::writable());
-    let result = conn.send_startup(creds.clone(),event_loop);
+    let result = conn.send_options(event_loop);
     match result{
         Ok(_) => Ok(conn),
         Err(err) => Err(err)
@@ -414,50 +687,6 @@ pub fn connect(address: SocketAddr, creds:Option<Vec<CowStr>>,event_loop: &mut E
     
 }
 
-#[derive(Debug)]
-struct CassResponse {
-    data : Vec<u8>
-}
-
-impl CassResponse {
-
-    fn new() -> CassResponse {
-        CassResponse {
-            data: Vec::new()
-        }
-    }
-
-    
-    fn read_buf(&self) -> &Vec<u8> {
-        &self.data
-    }
-
-    fn mut_read_buf(&mut self) -> &mut Vec<u8>{
-        &mut self.data
-    }
-
-    pub fn read_cql_response(&self,version: u8) -> (RCResult<CqlResponse>,bool){
-        //println!("Connection::CassResponse::read_cql_response");
-        //println!("CqlResponse := {:?}",self.read_buf());
-        //println!("Length slice vec: {:?}",self.read_buf().len());
-        //let mut response : ByteBuf = ByteBuf::from_slice(self.read_buf().as_slice());
-        //println!("Capacity: {:?}",response.capacity());
-        let rc_result = self.read_buf().as_slice().read_cql_response(version);
-        let cql_response =  match rc_result {
-            Ok(val) => val,
-            Err(ref err) => {
-                use std::error::Error;
-                println!("We've got an error reading response: {:?}",err);
-                return (Err(RCError::new(format!("{} -> {}", "", err.description()), RCErrorType::IOError)),false)
-            }
-        };
-        //println!("CqlResponse := {:?}",cql_response);
-        let is_event = cql_response.is_event();
-        (Ok(cql_response),is_event)
-    }
-
-}
-
 #[derive(Debug)]
 pub enum CqlMsg{
     Request{
@@ -470,7 +699,10 @@ pub enum CqlMsg{
         tx: Complete<RCResult<CqlResponse>,()>,
         address: SocketAddr
     },
-    Shutdown
+    // Stops the pool once in-flight requests are answered, or the timeout expires
+    Shutdown{
+        timeout: Duration
+    }
 }
 
 impl CqlMsg{
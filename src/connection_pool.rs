@@ -8,26 +8,70 @@ use std::net::{SocketAddr,IpAddr,Ipv4Addr};
 use std::collections::BTreeMap;
 use std::borrow::Cow;
 use std::error::Error;
-use def::CqlEvent;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use def::{EventMsg, Compression, CqlEvent, StatusChangeType, CqlResponse};
 use error::{RCResult,RCError,RCErrorType};
 use error::RCErrorType::*;
-use connection::{Connection,CqlMsg,connect};
-
+use connection::{Connection,CqlMsg,connect,startup_request};
+use request_limits::{RequestLimits, RequestStats, OverloadPolicy};
+use tls::TlsConfig;
 
+// How often requests are checked against their timeout
+const REQUEST_TIMEOUT_TICK_MS: u64 = 100;
 
+pub enum PoolTimeout {
+    // The shutdown timeout expired
+    Shutdown,
+    // The connection with this token had until now to complete its handshake
+    Connect(Token),
+    // Time to fail the requests that timed out
+    Requests
+}
 
 pub struct ConnectionPool {
-    token_by_ip: BTreeMap<IpAddr,Token>,
+    // Connections of each node
+    token_by_ip: BTreeMap<IpAddr,Vec<Token>>,
     connections: Slab<Connection>,
-    event_handler: Sender<CqlEvent>
+    event_handler: Sender<EventMsg>,
+    // Set by the cluster before connecting, read for every new connection
+    compression: Arc<RwLock<Compression>>,
+    limits: Arc<RwLock<RequestLimits>>,
+    stats: Arc<RequestStats>,
+    // Sent if a node asks for authentication
+    credentials: Option<(String,String)>,
+    connect_timeout: Duration,
+    request_timeout: Option<Duration>,
+    // Connections opened to each node
+    pool_size: usize,
+    // Connections are encrypted when set
+    tls: Option<TlsConfig>,
+    // Set once requests are checked for timeouts periodically
+    ticking: bool,
+    // Set on shutdown, new requests are refused from then on
+    closing: bool
 }
 
 impl ConnectionPool {
-    pub fn new(event_handler: Sender<CqlEvent>) -> ConnectionPool {
+    pub fn new(event_handler: Sender<EventMsg>,compression: Arc<RwLock<Compression>>,
+               limits: Arc<RwLock<RequestLimits>>,stats: Arc<RequestStats>,
+               credentials: Option<(String,String)>,connect_timeout: Duration,
+               request_timeout: Option<Duration>,pool_size: usize,
+               tls: Option<TlsConfig>) -> ConnectionPool {
         ConnectionPool {
             token_by_ip: BTreeMap::new(),
             connections: Slab::new_starting_at(Token(1), 32768),
-            event_handler: event_handler
+            event_handler: event_handler,
+            compression: compression,
+            limits: limits,
+            stats: stats,
+            credentials: credentials,
+            connect_timeout: connect_timeout,
+            request_timeout: request_timeout,
+            pool_size: pool_size,
+            tls: tls,
+            ticking: false,
+            closing: false
         }
     }
     
@@ -42,17 +86,96 @@ impl ConnectionPool {
             self.find_connection_by_ip(address)
         }
     }
-    
-    fn create_connection(&mut self,event_loop: &mut EventLoop<ConnectionPool>,address:&IpAddr) -> RCResult<Token>{
+
+    // Opens the node's connections, replacing the old ones. The connect message goes through the
+    // first one, the others complete their handshake on their own and aren't waited for.
+    fn connect_node(&mut self,event_loop: &mut EventLoop<ConnectionPool>,msg: CqlMsg){
+        let (address,version) = match msg {
+            CqlMsg::Connect{ref request,address,..} => (address,request.version),
+            _ => unreachable!()
+        };
+        let old = self.token_by_ip.get(&address.ip()).cloned().unwrap_or(vec![]);
+        for token in old {
+            self.remove_connection(event_loop,token,"Replaced by a new connection",false);
+        }
+        let mut msg = Some(msg);
+        for _ in 0..self.pool_size {
+            let token = match self.create_connection(event_loop,address,version) {
+                Ok(token) => token,
+                Err(err) => {
+                    if let Some(msg) = msg.take() {
+                        msg.complete(Err(RCError::new(format!("Couldn't connect to host: {}",err.desc), RCErrorType::ConnectionError)));
+                    }
+                    return;
+                }
+            };
+            let startup = msg.take().unwrap_or_else(|| {
+                let (tx,_) = Future::<RCResult<CqlResponse>, ()>::pair();
+                CqlMsg::Connect{request: startup_request(version),tx: tx,address: address}
+            });
+            let conn = self.find_connection_by_token(token).unwrap();
+            match conn.insert_request(startup) {
+                Ok(_) => conn.reregister(event_loop,EventSet::readable() | EventSet::writable()),
+                Err((startup,err)) => startup.complete(Err(err))
+            }
+        }
+    }
+
+    fn create_connection(&mut self,event_loop: &mut EventLoop<ConnectionPool>,address: SocketAddr,version: u8) -> RCResult<Token>{
         //println!("[ConnectionPool::create_connection]");
-        let mut conn = try_rc!(connect(SocketAddr::new(address.clone(),9042),
-                                None,
+        let mut conn = try_rc!(connect(address,
+                                version,
+                                self.credentials.clone(),
                                 event_loop,
-                                self.event_handler.clone()),"Failed connecting");
-        let token = try_rc!(self.add_connection(address.clone(),conn),"Failed adding a new connection");
+                                self.event_handler.clone(),
+                                *self.compression.read().unwrap(),
+                                self.request_timeout,
+                                self.tls.as_ref()),"Failed connecting");
+        let token = try_rc!(self.add_connection(address.ip(),conn),"Failed adding a new connection");
+        if event_loop.timeout_ms(PoolTimeout::Connect(token),duration_ms(self.connect_timeout)).is_err() {
+            // It could hang forever without it
+            self.remove_connection(event_loop,token,"Couldn't set the connect timeout",false);
+            return Err(RCError::new(format!("Couldn't set the connect timeout of {}",address), EventLoopError));
+        }
+        if self.request_timeout.is_some() && !self.ticking {
+            try!(self.schedule_tick(event_loop));
+        }
         Ok(token)
     }
 
+    fn schedule_tick(&mut self,event_loop: &mut EventLoop<ConnectionPool>) -> RCResult<()>{
+        self.ticking = event_loop.timeout_ms(PoolTimeout::Requests,REQUEST_TIMEOUT_TICK_MS).is_ok();
+        if !self.ticking {
+            return Err(RCError::new("Couldn't schedule the request timeouts", EventLoopError));
+        }
+        Ok(())
+    }
+
+    // Fails its requests and closes the socket. A lost connection is reported to the
+    // event handler as the node going down, it checks the node before using it again.
+    fn remove_connection(&mut self,event_loop: &mut EventLoop<ConnectionPool>,token: Token,msg: &str,lost: bool){
+        let mut conn = match self.connections.remove(token) {
+            Some(conn) => conn,
+            None => return
+        };
+        conn.fail_all(msg);
+        conn.deregister(event_loop);
+        if lost && !self.closing {
+            self.event_handler.send(EventMsg::Event(CqlEvent::StatusChange(StatusChangeType::Down,conn.address())));
+        }
+        let ip = conn.address().ip();
+        let empty = match self.token_by_ip.get_mut(&ip) {
+            Some(tokens) => {
+                tokens.retain(|t| *t != token);
+                tokens.is_empty()
+            },
+            None => false
+        };
+        if empty {
+            self.token_by_ip.remove(&ip);
+        }
+    }
+
     fn add_connection(&mut self, address:IpAddr,connection: Connection)-> RCResult<Token>{
         //println!("[ConnectionPool::add_connection]");
         let result = self.connections.insert(connection);
@@ -64,7 +187,7 @@ impl ConnectionPool {
                 //println!("Setting token {:?}",token);
                 conn.set_token(token);
                 }
-                self.token_by_ip.insert(address,token);
+                self.token_by_ip.entry(address).or_insert_with(Vec::new).push(token);
                 Ok(token)
             },
             Err(err) => {
@@ -74,6 +197,102 @@ impl ConnectionPool {
     }
     
 
+    fn in_flight(&self) -> usize{
+        self.connections.iter().map(|conn| conn.in_flight()).sum()
+    }
+
+    // Requests in flight to each node, through all its connections
+    fn in_flight_by_ip(&self) -> BTreeMap<IpAddr,usize>{
+        let mut in_flight = BTreeMap::new();
+        for conn in self.connections.iter() {
+            *in_flight.entry(conn.address().ip()).or_insert(0) += conn.in_flight();
+        }
+        in_flight
+    }
+
+    fn queued(&self) -> usize{
+        self.connections.iter().map(|conn| conn.waiting_len()).sum()
+    }
+
+    fn update_stats(&self){
+        self.stats.set(self.in_flight(),self.queued());
+    }
+
+    // Sends the request, or applies the overload policy if the in-flight limits are reached
+    fn send_request(&mut self,event_loop: &mut EventLoop<ConnectionPool>,msg: CqlMsg){
+        let limits = self.limits.read().unwrap().clone();
+        let in_flight = self.in_flight();
+        let queued = self.queued();
+        let ip = msg.get_ip();
+        let host_in_flight = self.in_flight_by_ip().get(&ip).cloned().unwrap_or(0);
+        let conn = match self.get_connection_with_ip(event_loop,&ip) {
+            Ok(conn) => conn,
+            Err(err) => {
+                msg.complete(Err(RCError::new(format!("Couldn't send request to {}: {}",ip,err), ConnectionError)));
+                return;
+            }
+        };
+        // Requests already waiting for this host go first
+        let overloaded = conn.waiting_len() > 0
+                         || host_in_flight >= limits.max_in_flight_per_host
+                         || conn.in_flight() >= conn.max_in_flight()
+                         || in_flight >= limits.max_in_flight;
+        if !overloaded {
+            match conn.insert_request(msg){
+                Ok(_) => conn.reregister(event_loop,EventSet::readable() | EventSet::writable()),
+                Err((msg,err)) => msg.complete(Err(err)),
+            }
+            return;
+        }
+        match limits.policy {
+            OverloadPolicy::Wait => conn.queue_waiting(msg),
+            OverloadPolicy::Queue(max) if queued < max => conn.queue_waiting(msg),
+            _ => msg.complete(Err(RCError::new(format!("Too many requests in flight ({} to {}, {} in total)",
+                                                      host_in_flight,ip,in_flight), OverloadedError)))
+        }
+    }
+
+    // Moves waiting requests to their connection as long as the limits allow it
+    fn send_waiting(&mut self,event_loop: &mut EventLoop<ConnectionPool>){
+        let limits = self.limits.read().unwrap().clone();
+        let mut in_flight = self.in_flight();
+        let mut by_ip = self.in_flight_by_ip();
+        for conn in self.connections.iter_mut() {
+            let mut sent = false;
+            let host_in_flight = by_ip.entry(conn.address().ip()).or_insert(0);
+            while conn.waiting_len() > 0
+                  && *host_in_flight < limits.max_in_flight_per_host
+                  && conn.in_flight() < conn.max_in_flight()
+                  && in_flight < limits.max_in_flight {
+                let msg = conn.pop_waiting().unwrap();
+                match conn.insert_request(msg) {
+                    Ok(_) => {
+                        in_flight += 1;
+                        *host_in_flight += 1;
+                        sent = true;
+                    },
+                    Err((msg,err)) => msg.complete(Err(err))
+                }
+            }
+            if sent {
+                conn.reregister(event_loop,EventSet::readable() | EventSet::writable());
+            }
+        }
+    }
+
+    // Stops the event loop once every request has been answered
+    fn shutdown_if_drained(&mut self,event_loop: &mut EventLoop<ConnectionPool>){
+        if self.in_flight() == 0 && self.queued() == 0 {
+            event_loop.shutdown();
+        }
+    }
+
+    fn fail_all(&mut self){
+        for conn in self.connections.iter_mut() {
+            conn.fail_all("Cluster was shut down before the request was answered");
+        }
+    }
+
     fn exists_connection_by_ip(&mut self,address:&IpAddr) -> bool{
         self.token_by_ip.contains_key(address)
     }
@@ -82,18 +301,24 @@ impl ConnectionPool {
         self.connections.contains(token)
     }
 
+    // The node's connection with the fewest requests, preferring those done with the handshake
     fn find_connection_by_ip(&mut self,address:&IpAddr) -> Result<&mut Connection,&'static str>{
         //println!("[ConnectionPool::find_connection_by_ip]");
-        if !self.connections.is_empty() {
-            // Needs to be improved
-            let conn = 
-            Ok(self.connections.get_mut(self.token_by_ip
-                                                   .get(address).unwrap().clone()
-                                              ).unwrap());
-            //println!("{:?}",conn );
-            return conn;
+        let token = {
+            let connections = &self.connections;
+            self.token_by_ip.get(address)
+                .and_then(|tokens| tokens.iter()
+                                         .filter(|token| connections.contains(**token))
+                                         .min_by_key(|token| {
+                                             let conn = &connections[**token];
+                                             (!conn.is_ready(),conn.in_flight() + conn.waiting_len())
+                                         })
+                                         .cloned())
+        };
+        match token {
+            Some(token) => Ok(self.connections.get_mut(token).unwrap()),
+            None => Err("There is no connection found")
         }
-        Err("There is no connection found")
     }
 
     fn find_connection_by_token(&mut self,token: Token) -> Result<&mut Connection,&'static str>{
@@ -111,7 +336,7 @@ impl ConnectionPool {
 
 
 impl mio::Handler for ConnectionPool {
-    type Timeout = ();
+    type Timeout = PoolTimeout;
     // Left one is the internal Handler message type and
     // right one is our defined type
     type Message = CqlMsg; 
@@ -121,42 +346,62 @@ impl mio::Handler for ConnectionPool {
     fn notify(&mut self, event_loop: &mut EventLoop<ConnectionPool>, msg: CqlMsg) {
         //println!("[ConnectionPool::notify]");
         //self.show_connections();
-        let ip = &msg.get_ip();
         //let complete = msg.get_complete();
         match msg {
+            CqlMsg::Request{..} | CqlMsg::Connect{..} if self.closing => {
+                msg.complete(Err(RCError::new("Cluster is shutting down", ClusterError)));
+            },
             CqlMsg::Request{..} => {
-                let mut result = self.get_connection_with_ip(event_loop,ip);  
-                // Here is where we should do create a new connection if it doesn't exist.
-                // Connect, then send_startup with the queue_message
-                match result {
-                    Ok(conn) =>{
-                        match conn.insert_request(msg){
-                            Ok(_) => conn.reregister(event_loop,EventSet::writable()),
-                            Err(err) => (),
-                        }
-                    },
-                    Err(err) =>{
-                        //TO-DO 
-                        //Complete all requests with connection error
-                    }
-                }
+                self.send_request(event_loop,msg);
+                self.update_stats();
             },
             CqlMsg::Connect{..} => {
-                let mut result = self.create_connection(event_loop,ip);
-                match result {
-                    Ok(token) =>{
-                        let conn = self.find_connection_by_ip(ip).unwrap();
-                        conn.insert_request(msg);
-                        conn.reregister(event_loop,EventSet::writable());
-                    },
-                    Err(ref err) =>{
-                        msg.complete((Err(RCError::new("Couldn't connect to host", RCErrorType::ConnectionError))));
-                    }
+                self.connect_node(event_loop,msg);
+                self.update_stats();
+            },
+            CqlMsg::Shutdown{timeout} => {
+                self.closing = true;
+                if event_loop.timeout_ms(PoolTimeout::Shutdown,duration_ms(timeout)).is_err() {
+                    self.fail_all();
+                    event_loop.shutdown();
                 }
+                self.shutdown_if_drained(event_loop);
             },
-            CqlMsg::Shutdown => {
-                event_loop.shutdown();
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<ConnectionPool>, timeout: PoolTimeout) {
+        match timeout {
+            PoolTimeout::Shutdown => {
+                if self.closing {
+                    self.fail_all();
+                    self.update_stats();
+                    event_loop.shutdown();
+                }
             },
+            PoolTimeout::Connect(token) => {
+                let timed_out = self.connections.get(token).map(|conn| !conn.is_ready()).unwrap_or(false);
+                if timed_out {
+                    self.remove_connection(event_loop,token,"Timed out connecting to the node",false);
+                    self.update_stats();
+                }
+            },
+            PoolTimeout::Requests => {
+                let now = Instant::now();
+                for conn in self.connections.iter_mut() {
+                    conn.expire_requests(now);
+                }
+                // Requests that timed out before being sent leave room for the waiting ones
+                if self.queued() > 0 {
+                    self.send_waiting(event_loop);
+                }
+                self.update_stats();
+                if self.closing {
+                    self.shutdown_if_drained(event_loop);
+                }
+                // The next connection tries again if this fails
+                let _ = self.schedule_tick(event_loop);
+            }
         }
     }
 
@@ -165,23 +410,62 @@ impl mio::Handler for ConnectionPool {
         //println!("[Connection::ready]");      
         //println!("Assigned token is: {:?}",token);
         //println!("Events: {:?}",events);
-        let mut connection = self.find_connection_by_token(token).ok().expect("Couldn't get connection");           
+        if !self.connections.contains(token) {
+            return;
+        }
         if events.is_readable() {
-            //println!("    connection-EventSet::Readable");
-            connection.read(event_loop);
-            let pair = connection.read_cql_response();
-            let response = pair.0;
-            let is_event = pair.1;
-            //println!("Response from event_loop: {:?}",response);
-            //println!("Handling response..");
-            connection.handle_response(response,event_loop,is_event);
-            connection.reset_response();
+            let result = {
+                let mut connection = self.find_connection_by_token(token).ok().expect("Couldn't get connection");           
+                //println!("    connection-EventSet::Readable");
+                let mut result = connection.read(event_loop);
+                // A single read can complete several frames
+                while let Some((stream,response,is_event)) = connection.read_cql_response() {
+                    //println!("Response from event_loop: {:?}",response);
+                    //println!("Handling response..");
+                    if let Err(err) = connection.handle_response(stream,response,event_loop,is_event) {
+                        result = Err(err);
+                        break;
+                    }
+                }
+                result
+            };
+            if let Err(err) = result {
+                self.remove_connection(event_loop,token,&err.desc,true);
+            }
+        } else if events.is_error() || events.is_hup() {
+            // A refused connection is reported this way
+            self.remove_connection(event_loop,token,"Connection to the node failed",true);
         }
 
-        if events.is_writable() && connection.are_pendings_send(){
-            //println!("    connection-EventSet::Writable");
-            connection.write(event_loop);
+        // Answered requests make room for the waiting ones
+        if self.queued() > 0 {
+            self.send_waiting(event_loop);
+        }
+
+        if self.connections.contains(token) {
+            let result = {
+                let mut connection = self.find_connection_by_token(token).ok().expect("Couldn't get connection");           
+                // Handling responses can unblock writes (the STARTUP after OPTIONS), so this
+                // doesn't wait for the socket to be reported as writable
+                if connection.has_writes(){
+                    //println!("    connection-EventSet::Writable");
+                    connection.write(event_loop)
+                } else {
+                    Ok(())
+                }
+            };
+            if let Err(err) = result {
+                self.remove_connection(event_loop,token,&err.desc,true);
+            }
+        }
+        self.update_stats();
+        if self.closing {
+            self.shutdown_if_drained(event_loop);
         }
         //println!("[Connection::Ended ready]");
     }
-}
\ No newline at end of file
+}
+
+pub fn duration_ms(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + (duration.subsec_nanos() / 1_000_000) as u64
+}
@@ -0,0 +1,368 @@
+use mio::Sender;
+use std::borrow::Cow;
+use std::io::{Read, Write, ErrorKind};
+use std::net::{SocketAddr, IpAddr, TcpStream};
+use std::sync::{Arc, RwLock, Mutex};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use std::collections::BTreeMap;
+
+use def::*;
+use def::OpcodeRequest::*;
+use def::CqlRequestBody::*;
+use def::CqlResponseBody::*;
+use def::CqlValue::*;
+use def::StatusChangeType::*;
+use error::*;
+use error::RCErrorType::*;
+use cluster::parse_nodes;
+use cluster_builder::ClusterConfig;
+use cluster_events::ClusterEvent;
+use connection::auth_response;
+use frame::FrameBuffer;
+use node::Node;
+use reader::CqlReader;
+use schema::SchemaMetadata;
+use serialize::CqlSerializable;
+use tls::TlsSession;
+use util;
+
+// Requests are sent one at a time, so they all use the same stream
+const CONTROL_STREAM: i16 = 1;
+// How often the control thread checks for messages while waiting for events
+const POLL_INTERVAL_MS: u64 = 100;
+const REQUEST_TIMEOUT_SECS: u64 = 10;
+const LOCAL_QUERY: &'static str = "SELECT key FROM system.local";
+const PEERS_QUERY: &'static str = "SELECT peer FROM system.peers";
+// Schema tables of Cassandra 3, and of older versions
+const SCHEMA_QUERIES: [[&'static str; 3]; 2] = [
+    ["SELECT keyspace_name FROM system_schema.keyspaces",
+     "SELECT keyspace_name, table_name FROM system_schema.tables",
+     "SELECT keyspace_name, type_name FROM system_schema.types"],
+    ["SELECT keyspace_name FROM system.schema_keyspaces",
+     "SELECT keyspace_name, columnfamily_name FROM system.schema_columnfamilies",
+     "SELECT keyspace_name, type_name FROM system.schema_usertypes"]
+];
+
+// Set by Cluster::shutdown before sending ControlMsg::Shutdown. Blocking operations
+// of the control thread don't go past it.
+pub type ShutdownDeadline = Arc<Mutex<Option<Instant>>>;
+
+pub enum ControlMsg {
+    // Reads the peers and the schema again and updates the cluster's metadata
+    Refresh,
+    Shutdown
+}
+
+// Connection used only to get events and cluster metadata, separate from the
+// ones requests are sent through. It uses a blocking socket in its own thread.
+pub struct ControlConnection {
+    socket: TcpStream,
+    address: SocketAddr,
+    version: u8,
+    frames: FrameBuffer,
+    // Events read are forwarded here
+    event_handler: Sender<EventMsg>,
+    shutdown: ShutdownDeadline,
+    // Set when the cluster was built with TLS
+    tls: Option<TlsSession>
+}
+
+impl ControlConnection {
+    // Connects, authenticates and registers for events. This operation blocks
+    pub fn open(address: SocketAddr, config: &ClusterConfig, event_handler: Sender<EventMsg>,
+                shutdown: ShutdownDeadline) -> RCResult<ControlConnection> {
+        let connect_timeout = try!(time_left(&shutdown, config.connect_timeout));
+        let socket = try_io!(TcpStream::connect_timeout(&address, connect_timeout),
+                             format!("Error opening control connection to {}", address));
+        try_io!(socket.set_read_timeout(Some(Duration::from_millis(POLL_INTERVAL_MS))), "Error setting read timeout");
+        try_io!(socket.set_nodelay(true), "Error setting nodelay");
+        let tls = match config.tls {
+            Some(ref tls) => Some(try!(tls.session(address.ip()))),
+            None => None
+        };
+        let mut conn = ControlConnection {
+            socket: socket,
+            address: address,
+            version: config.version,
+            frames: FrameBuffer::new(),
+            event_handler: event_handler,
+            shutdown: shutdown,
+            tls: tls
+        };
+        try!(conn.startup(&config.credentials));
+        try!(conn.register());
+        Ok(conn)
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn startup(&mut self, credentials: &Option<(String, String)>) -> RCResult<()> {
+        let body = CqlStringMap {
+            pairs: vec![CqlPair{key: "CQL_VERSION", value: Cow::Borrowed(CQL_VERSION_STRINGS[(self.version-1) as usize])}],
+        };
+        let response = try!(self.request(OpcodeStartup, RequestStartup(body)));
+        let response = match response.body {
+            ResponseAuthenticate(ref authenticator) => {
+                let auth = try!(auth_response(authenticator, self.version, credentials));
+                try!(self.request(auth.opcode, auth.body))
+            },
+            _ => response
+        };
+        match response.body {
+            ResponseReady | ResponseAuthSuccess(_) => Ok(()),
+            ResponseError(_, ref msg) => Err(RCError::new(format!("Error connecting: {}", msg), ConnectionError)),
+            _ => Err(RCError::new("Wrong response to startup", ConnectionError))
+        }
+    }
+
+    fn register(&mut self) -> RCResult<()> {
+        let events = vec![CqlVarchar(Some(CqlEventType::StatusChange.get_str())),
+                          CqlVarchar(Some(CqlEventType::TopologyChange.get_str())),
+                          CqlVarchar(Some(CqlEventType::SchemaChange.get_str()))];
+        let response = try!(self.request(OpcodeRegister, RequestRegister(events)));
+        match response.body {
+            ResponseReady => Ok(()),
+            ResponseError(_, ref msg) => Err(RCError::new(format!("Error registering for events: {}", msg), ClusterError)),
+            _ => Err(RCError::new("Wrong response to register", ClusterError))
+        }
+    }
+
+    // Checks the node answers about itself and reads its peers
+    pub fn refresh(&mut self) -> RCResult<Vec<IpAddr>> {
+        try!(self.query(LOCAL_QUERY));
+        let peers = try!(self.query(PEERS_QUERY));
+        parse_nodes(peers)
+    }
+
+    pub fn load_schema(&mut self) -> RCResult<SchemaMetadata> {
+        let mut errors = vec![];
+        for queries in SCHEMA_QUERIES.iter() {
+            match self.query_rows(queries[0]).and_then(|keyspaces| {
+                      let tables = try!(self.query_rows(queries[1]));
+                      let types = try!(self.query_rows(queries[2]));
+                      SchemaMetadata::from_rows(&keyspaces, &tables, &types)
+                  }) {
+                Ok(schema) => return Ok(schema),
+                Err(err) => errors.push(err.desc.to_string())
+            }
+        }
+        Err(RCError::new(format!("Error reading the schema -> {}", errors.join(", ")), ClusterError))
+    }
+
+    // Everything the control connection keeps track of, for the event handler
+    pub fn refresh_metadata(&mut self) -> RCResult<EventMsg> {
+        let peers = try!(self.refresh());
+        let schema = self.load_schema().map_err(|err| err.desc.to_string());
+        Ok(EventMsg::Refresh{control: self.address, peers: peers, schema: schema})
+    }
+
+    fn query_rows(&mut self, query_str: &str) -> RCResult<CqlRows> {
+        match try!(self.query(query_str)).body {
+            ResultRows(rows) => Ok(rows),
+            _ => Err(RCError::new(format!("Error querying {}: no rows returned", query_str), ClusterError))
+        }
+    }
+
+    fn query(&mut self, query_str: &str) -> RCResult<CqlResponse> {
+        let response = try!(self.request(OpcodeQuery, RequestQuery(query_str.to_string(), CqlQueryParams::new(Consistency::One))));
+        match response.body {
+            ResponseError(_, ref msg) => Err(RCError::new(format!("Error querying {}: {}", query_str, msg), ClusterError)),
+            _ => Ok(response)
+        }
+    }
+
+    fn request(&mut self, opcode: OpcodeRequest, body: CqlRequestBody) -> RCResult<CqlResponse> {
+        let request = CqlRequest {
+            version: self.version,
+            flags: 0x00,
+            stream: CONTROL_STREAM,
+            opcode: opcode,
+            body: body
+        };
+        let mut buf = vec![];
+        try!(request.serialize(&mut buf, self.version));
+        let timeout = try!(time_left(&self.shutdown, Duration::from_secs(REQUEST_TIMEOUT_SECS)));
+        try_io!(self.socket.set_write_timeout(Some(timeout)), "Error setting write timeout");
+        match self.tls {
+            Some(ref mut tls) => {
+                // Buffered by the session until the handshake is done
+                let mut written = 0;
+                while written < buf.len() {
+                    written += try_io!(tls.write_plain(&buf[written..]), "Error writing to the control connection");
+                }
+                try_io!(tls.write_to(&mut self.socket), "Error writing to the control connection");
+            },
+            None => try_io!(self.socket.write_all(&buf), "Error writing to the control connection")
+        }
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            // Reads wait for the poll interval at most, the shutdown is noticed in between
+            let poll = try!(time_left(&self.shutdown, Duration::from_millis(POLL_INTERVAL_MS)));
+            try_io!(self.socket.set_read_timeout(Some(poll)), "Error setting read timeout");
+            match try!(self.read_response()) {
+                Some(response) => {
+                    if response.stream == CONTROL_STREAM {
+                        return Ok(response);
+                    }
+                },
+                None => {
+                    if Instant::now() >= deadline {
+                        return Err(RCError::new(format!("Control connection to {} timed out", self.address), ConnectionError));
+                    }
+                }
+            }
+        }
+    }
+
+    // Next response read, events are forwarded instead of returned.
+    // None if nothing arrived within the poll interval.
+    fn read_response(&mut self) -> RCResult<Option<CqlResponse>> {
+        loop {
+            if let Some(frame) = self.frames.next_frame(self.version) {
+                let response = try!(frame.as_slice().read_cql_response(self.version));
+                if !response.is_event() {
+                    return Ok(Some(response));
+                }
+                if let ResponseEvent(event) = response.body {
+                    self.event_handler.send(EventMsg::Event(event));
+                }
+                continue;
+            }
+            let socket = &mut self.socket;
+            let read = match self.tls {
+                Some(ref mut tls) => tls.read_from(socket, &mut self.frames).and_then(|n| {
+                    // The handshake may have something to answer
+                    try!(tls.write_to(socket));
+                    Ok(n)
+                }),
+                None => {
+                    let mut buf = [0u8; 4096];
+                    socket.read(&mut buf).map(|n| {
+                        self.frames.extend(&buf[..n]);
+                        n
+                    })
+                }
+            };
+            match read {
+                Ok(0) => return Err(RCError::new(format!("Control connection closed by {}", self.address), ConnectionError)),
+                Ok(_) => {},
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock || err.kind() == ErrorKind::TimedOut => return Ok(None),
+                Err(ref err) => return Err(RCError::new(format!("Error reading from the control connection -> {}", err), ConnectionError))
+            }
+        }
+    }
+}
+
+// Keeps reading events in a new thread. If the connection breaks its node is reported
+// down and the connection moves to another node, whose peers are then read again.
+// `timeout`, or less if the shutdown deadline comes first. Fails once it's passed.
+fn time_left(shutdown: &ShutdownDeadline, timeout: Duration) -> RCResult<Duration> {
+    let deadline = match shutdown.lock() {
+        Ok(deadline) => *deadline,
+        Err(_) => None
+    };
+    match deadline {
+        None => Ok(timeout),
+        Some(deadline) => {
+            let now = Instant::now();
+            if now >= deadline {
+                return Err(RCError::new("The cluster is shutting down", ConnectionError));
+            }
+            Ok(::std::cmp::min(timeout, deadline - now))
+        }
+    }
+}
+
+pub fn start(conn: ControlConnection, config: ClusterConfig, available_nodes: Arc<RwLock<BTreeMap<IpAddr, Node>>>)
+             -> (mpsc::Sender<ControlMsg>, JoinHandle<()>) {
+    let (tx, rx) = mpsc::channel();
+    let handle = thread::Builder::new().name("control_connection".to_string()).spawn(move || {
+        run(conn, &config, &available_nodes, &rx);
+    }).ok().expect("Couldn't start control connection thread");
+    (tx, handle)
+}
+
+fn run(mut conn: ControlConnection, config: &ClusterConfig, available_nodes: &Arc<RwLock<BTreeMap<IpAddr, Node>>>,
+       rx: &Receiver<ControlMsg>) {
+    loop {
+        let result = match rx.try_recv() {
+            Ok(ControlMsg::Shutdown) | Err(TryRecvError::Disconnected) => return,
+            Ok(ControlMsg::Refresh) => conn.refresh_metadata().map(|msg| {
+                conn.event_handler.send(msg);
+            }),
+            Err(TryRecvError::Empty) => conn.read_response().map(|_| ())
+        };
+        if let Err(err) = result {
+            let event_handler = conn.event_handler.clone();
+            let shutdown = conn.shutdown.clone();
+            event_handler.send(EventMsg::Publish(ClusterEvent::ControlConnectionLost(conn.address, err.desc.to_string())));
+            event_handler.send(EventMsg::Event(CqlEvent::StatusChange(Down, conn.address)));
+            conn = match reconnect(conn.address, config, available_nodes, event_handler, shutdown, rx) {
+                Some(conn) => conn,
+                None => return
+            };
+        }
+    }
+}
+
+// Tries every other node, then the failed one, waiting as the reconnection policy says between rounds
+fn reconnect(failed: SocketAddr, config: &ClusterConfig, available_nodes: &Arc<RwLock<BTreeMap<IpAddr, Node>>>,
+             event_handler: Sender<EventMsg>, shutdown: ShutdownDeadline, rx: &Receiver<ControlMsg>) -> Option<ControlConnection> {
+    let mut attempt = 0;
+    loop {
+        for address in candidates(failed, config, available_nodes) {
+            // Every attempt can block, the shutdown is checked before each of them
+            match rx.try_recv() {
+                Ok(ControlMsg::Shutdown) | Err(TryRecvError::Disconnected) => return None,
+                Ok(ControlMsg::Refresh) | Err(TryRecvError::Empty) => ()
+            }
+            let result = ControlConnection::open(address, config, event_handler.clone(), shutdown.clone())
+                             .and_then(|mut conn| conn.refresh_metadata().map(|msg| (conn, msg)));
+            match result {
+                Ok((conn, msg)) => {
+                    event_handler.send(msg);
+                    event_handler.send(EventMsg::Publish(ClusterEvent::ControlConnectionRestored(address)));
+                    return Some(conn);
+                },
+                Err(err) => {
+                    event_handler.send(EventMsg::Publish(ClusterEvent::ControlConnectionFailed(address, err.desc.to_string())));
+                }
+            }
+        }
+        let delay = match config.reconnection_policy.next_delay(attempt) {
+            Some(delay) => delay,
+            None => {
+                event_handler.send(EventMsg::Publish(ClusterEvent::ControlConnectionGivenUp(attempt + 1)));
+                return None;
+            }
+        };
+        attempt += 1;
+        match rx.recv_timeout(delay) {
+            Ok(ControlMsg::Shutdown) | Err(RecvTimeoutError::Disconnected) => return None,
+            Ok(ControlMsg::Refresh) | Err(RecvTimeoutError::Timeout) => ()
+        }
+    }
+}
+
+fn candidates(failed: SocketAddr, config: &ClusterConfig, available_nodes: &Arc<RwLock<BTreeMap<IpAddr, Node>>>) -> Vec<SocketAddr> {
+    let mut addresses: Vec<SocketAddr> = match available_nodes.read() {
+        Ok(map) => map.values().map(|node| node.get_sock_addr()).filter(|address| *address != failed).collect(),
+        Err(_) => vec![]
+    };
+    for contact_point in config.contact_points.iter() {
+        if let Ok(resolved) = util::resolve_contact_point(contact_point, config.port) {
+            for address in resolved {
+                if address != failed && !addresses.contains(&address) {
+                    addresses.push(address);
+                }
+            }
+        }
+    }
+    addresses.push(failed);
+    addresses
+}
@@ -0,0 +1,468 @@
+extern crate num;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::borrow::Cow;
+use uuid::Uuid;
+use self::num::bigint::BigInt;
+
+use def::*;
+use def::CqlValue::*;
+use def::CqlValueType::*;
+use error::*;
+use error::RCErrorType::*;
+
+// Conversion from a CqlValue (as read from a row) into a native Rust type
+pub trait FromCql: Sized {
+    fn from_cql(value: &CqlValue) -> RCResult<Self>;
+}
+
+fn conversion_error(value: &CqlValue, target: &str) -> RCError {
+    if value.is_null() {
+        RCError::new(format!("Can't convert null value into {}, use Option<{}> instead", target, target), ConversionError)
+    } else {
+        RCError::new(format!("Can't convert {:?} into {}", value, target), ConversionError)
+    }
+}
+
+macro_rules! from_cql_impl(
+    ($t: ty, $v: ident => $conv: expr, $($variant: ident),+) => {
+        impl FromCql for $t {
+            fn from_cql(value: &CqlValue) -> RCResult<$t> {
+                match *value {
+                    $( $variant(Some(ref $v)) => Ok($conv), )+
+                    _ => Err(conversion_error(value, stringify!($t)))
+                }
+            }
+        }
+    }
+);
+
+from_cql_impl!(bool, v => *v, CqlBoolean);
+from_cql_impl!(i32, v => *v, CqlInt);
+from_cql_impl!(i64, v => *v, CqlBigInt, CqlCounter);
+from_cql_impl!(u64, v => *v, CqlTimestamp);
+from_cql_impl!(f32, v => *v, CqlFloat);
+from_cql_impl!(f64, v => *v, CqlDouble);
+from_cql_impl!(String, v => v.to_string(), CqlASCII, CqlText, CqlVarchar);
+from_cql_impl!(CowStr, v => v.clone(), CqlASCII, CqlText, CqlVarchar);
+from_cql_impl!(Uuid, v => v.clone(), CqlUuid, CqlTimeUuid);
+from_cql_impl!(IpAddr, v => v.clone(), CqlInet);
+from_cql_impl!(BigInt, v => v.clone(), CqlVarint, CqlDecimal);
+
+// Blobs. u8 is not FromCql, so this doesn't overlap with lists
+impl FromCql for Vec<u8> {
+    fn from_cql(value: &CqlValue) -> RCResult<Vec<u8>> {
+        match *value {
+            CqlBlob(Some(ref v)) | CqlRaw(Some(ref v)) => Ok(v.clone()),
+            _ => Err(conversion_error(value, "Vec<u8>"))
+        }
+    }
+}
+
+impl<T: FromCql> FromCql for Option<T> {
+    fn from_cql(value: &CqlValue) -> RCResult<Option<T>> {
+        if value.is_null() {
+            Ok(None)
+        } else {
+            Ok(Some(try!(T::from_cql(value))))
+        }
+    }
+}
+
+fn from_cql_elements<T: FromCql>(elements: &Vec<CqlValue>) -> RCResult<Vec<T>> {
+    let mut result = Vec::with_capacity(elements.len());
+    for e in elements.iter() {
+        result.push(try!(T::from_cql(e)));
+    }
+    Ok(result)
+}
+
+impl<T: FromCql> FromCql for Vec<T> {
+    fn from_cql(value: &CqlValue) -> RCResult<Vec<T>> {
+        match *value {
+            CqlList(Some(ref v)) | CqlSet(Some(ref v)) => from_cql_elements(v),
+            _ => Err(conversion_error(value, "Vec"))
+        }
+    }
+}
+
+impl<T: FromCql + Eq + Hash> FromCql for HashSet<T> {
+    fn from_cql(value: &CqlValue) -> RCResult<HashSet<T>> {
+        match *value {
+            CqlList(Some(ref v)) | CqlSet(Some(ref v)) => Ok(try!(from_cql_elements(v)).into_iter().collect()),
+            _ => Err(conversion_error(value, "HashSet"))
+        }
+    }
+}
+
+impl<K: FromCql + Eq + Hash, V: FromCql> FromCql for HashMap<K, V> {
+    fn from_cql(value: &CqlValue) -> RCResult<HashMap<K, V>> {
+        match *value {
+            CqlMap(Some(ref v)) => {
+                let mut map = HashMap::with_capacity(v.len());
+                for pair in v.iter() {
+                    map.insert(try!(K::from_cql(&pair.key)), try!(V::from_cql(&pair.value)));
+                }
+                Ok(map)
+            },
+            _ => Err(conversion_error(value, "HashMap"))
+        }
+    }
+}
+
+impl<K: FromCql + Ord, V: FromCql> FromCql for BTreeMap<K, V> {
+    fn from_cql(value: &CqlValue) -> RCResult<BTreeMap<K, V>> {
+        match *value {
+            CqlMap(Some(ref v)) => {
+                let mut map = BTreeMap::new();
+                for pair in v.iter() {
+                    map.insert(try!(K::from_cql(&pair.key)), try!(V::from_cql(&pair.value)));
+                }
+                Ok(map)
+            },
+            _ => Err(conversion_error(value, "BTreeMap"))
+        }
+    }
+}
+
+// A tuple may hold fewer components than its type, missing ones are null
+fn tuple_component<T: FromCql>(components: &Vec<CqlValue>, index: usize) -> RCResult<T> {
+    match components.get(index) {
+        Some(value) => T::from_cql(value),
+        None => T::from_cql(&CqlTuple(None))
+    }
+}
+
+macro_rules! from_cql_tuple_impl(
+    ($($t: ident: $index: expr),+) => {
+        impl<$($t: FromCql),+> FromCql for ($($t,)+) {
+            fn from_cql(value: &CqlValue) -> RCResult<($($t,)+)> {
+                match *value {
+                    CqlTuple(Some(ref v)) => Ok(($(try!(tuple_component::<$t>(v, $index)),)+)),
+                    _ => Err(conversion_error(value, "tuple"))
+                }
+            }
+        }
+    }
+);
+
+from_cql_tuple_impl!(A: 0);
+from_cql_tuple_impl!(A: 0, B: 1);
+from_cql_tuple_impl!(A: 0, B: 1, C: 2);
+from_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3);
+from_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4);
+from_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+
+impl FromCql for CqlValue {
+    fn from_cql(value: &CqlValue) -> RCResult<CqlValue> {
+        Ok(value.clone())
+    }
+}
+
+impl CqlRow {
+    pub fn get<T: FromCql>(&self, index: usize) -> RCResult<T> {
+        match self.cols.get(index) {
+            Some(value) => T::from_cql(value).map_err(|err| {
+                RCError::new(format!("Error reading column {} -> {}", index, err.desc), ConversionError)
+            }),
+            None => Err(RCError::new(format!("Column {} out of range ({} columns)", index, self.cols.len()), NoDataError))
+        }
+    }
+
+    pub fn column_index(&self, name: &str) -> Option<usize> {
+        self.metadata.row_metadata.iter().position(|col| col.col_name == name)
+    }
+
+    pub fn get_by_name<T: FromCql>(&self, name: &str) -> RCResult<T> {
+        match self.column_index(name).and_then(|index| self.cols.get(index)) {
+            Some(value) => T::from_cql(value).map_err(|err| {
+                RCError::new(format!("Error reading column '{}' -> {}", name, err.desc), ConversionError)
+            }),
+            None => Err(RCError::new(format!("Column '{}' not found", name), NoDataError))
+        }
+    }
+
+    // None when the column is null or not selected at all
+    pub fn get_opt_by_name<T: FromCql>(&self, name: &str) -> RCResult<Option<T>> {
+        match self.column_index(name) {
+            Some(_) => self.get_by_name::<Option<T>>(name),
+            None => Ok(None)
+        }
+    }
+}
+
+// A struct built from a row by column name, see #[derive(FromRow)] in cql_derive
+pub trait FromRow: Sized {
+    fn from_row(row: &CqlRow) -> RCResult<Self>;
+}
+
+impl CqlRows {
+    pub fn parse<T: FromRow>(&self) -> RCResult<Vec<T>> {
+        let mut result = Vec::with_capacity(self.rows.len());
+        for row in self.rows.iter() {
+            result.push(try!(T::from_row(row)));
+        }
+        Ok(result)
+    }
+}
+
+// Result of a lightweight transaction (INSERT ... IF NOT EXISTS, UPDATE ... IF ...)
+#[derive(Debug)]
+pub struct LwtResult {
+    pub applied: bool,
+    // Rows with the current values when the condition failed, they also hold the [applied] column
+    pub current: Vec<CqlRow>
+}
+
+impl CqlRows {
+    pub fn lwt_result(&self) -> RCResult<LwtResult> {
+        let row = match self.rows.first() {
+            Some(row) => row,
+            None => return Err(RCError::new("Lightweight transaction result has no rows", NoDataError))
+        };
+        let applied = try!(row.get_by_name::<bool>("[applied]"));
+        Ok(LwtResult {
+            applied: applied,
+            current: if applied { vec![] } else { self.rows.clone() }
+        })
+    }
+}
+
+// Reads a field of a user defined type, fields missing from the value are null
+pub fn from_udt_field<T: FromCql>(fields: &CQLUdt, name: &str) -> RCResult<T> {
+    let value = fields.iter().find(|&&(ref field, _)| *field == name).map(|&(_, ref value)| value);
+    match value {
+        Some(value) => T::from_cql(value),
+        None => T::from_cql(&CqlUdt(None))
+    }.map_err(|err| RCError::new(format!("Error reading field '{}' -> {}", name, err.desc), ConversionError))
+}
+
+// Conversion from a native Rust type into a CqlValue, to bind it to a statement
+pub trait ToCql {
+    fn to_cql(&self) -> CqlValue;
+}
+
+macro_rules! to_cql_impl(
+    ($t: ty, $v: ident => $conv: expr) => {
+        impl ToCql for $t {
+            fn to_cql(&self) -> CqlValue {
+                let $v = self;
+                $conv
+            }
+        }
+    }
+);
+
+to_cql_impl!(bool, v => CqlBoolean(Some(*v)));
+to_cql_impl!(i32, v => CqlInt(Some(*v)));
+to_cql_impl!(i64, v => CqlBigInt(Some(*v)));
+to_cql_impl!(u64, v => CqlTimestamp(Some(*v)));
+to_cql_impl!(f32, v => CqlFloat(Some(*v)));
+to_cql_impl!(f64, v => CqlDouble(Some(*v)));
+to_cql_impl!(str, v => CqlVarchar(Some(Cow::Owned(v.to_string()))));
+to_cql_impl!(String, v => CqlVarchar(Some(Cow::Owned(v.clone()))));
+to_cql_impl!(CowStr, v => CqlVarchar(Some(v.clone())));
+to_cql_impl!(Uuid, v => CqlUuid(Some(v.clone())));
+to_cql_impl!(IpAddr, v => CqlInet(Some(v.clone())));
+to_cql_impl!(Vec<u8>, v => CqlBlob(Some(v.clone())));
+to_cql_impl!(CqlValue, v => v.clone());
+
+impl<'a, T: ToCql + ?Sized> ToCql for &'a T {
+    fn to_cql(&self) -> CqlValue {
+        (**self).to_cql()
+    }
+}
+
+// None doesn't know its type, it is sent as a raw null
+impl<T: ToCql> ToCql for Option<T> {
+    fn to_cql(&self) -> CqlValue {
+        match *self {
+            Some(ref v) => v.to_cql(),
+            None => CqlRaw(None)
+        }
+    }
+}
+
+impl<T: ToCql> ToCql for Vec<T> {
+    fn to_cql(&self) -> CqlValue {
+        CqlList(Some(self.iter().map(|v| v.to_cql()).collect()))
+    }
+}
+
+impl<T: ToCql + Eq + Hash> ToCql for HashSet<T> {
+    fn to_cql(&self) -> CqlValue {
+        CqlSet(Some(self.iter().map(|v| v.to_cql()).collect()))
+    }
+}
+
+impl<K: ToCql + Eq + Hash, V: ToCql> ToCql for HashMap<K, V> {
+    fn to_cql(&self) -> CqlValue {
+        CqlMap(Some(self.iter().map(|(k, v)| Pair { key: k.to_cql(), value: v.to_cql() }).collect()))
+    }
+}
+
+impl<K: ToCql + Ord, V: ToCql> ToCql for BTreeMap<K, V> {
+    fn to_cql(&self) -> CqlValue {
+        CqlMap(Some(self.iter().map(|(k, v)| Pair { key: k.to_cql(), value: v.to_cql() }).collect()))
+    }
+}
+
+macro_rules! to_cql_tuple_impl(
+    ($($t: ident: $index: tt),+) => {
+        impl<$($t: ToCql),+> ToCql for ($($t,)+) {
+            fn to_cql(&self) -> CqlValue {
+                CqlTuple(Some(vec![$(self.$index.to_cql()),+]))
+            }
+        }
+    }
+);
+
+to_cql_tuple_impl!(A: 0);
+to_cql_tuple_impl!(A: 0, B: 1);
+to_cql_tuple_impl!(A: 0, B: 1, C: 2);
+to_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3);
+to_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4);
+to_cql_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+
+// The values bound to a statement, either a list or a tuple of ToCql values
+pub trait ToCqlValues {
+    fn to_cql_values(&self) -> Vec<CqlValue>;
+}
+
+impl<T: ToCql> ToCqlValues for Vec<T> {
+    fn to_cql_values(&self) -> Vec<CqlValue> {
+        self.iter().map(|v| v.to_cql()).collect()
+    }
+}
+
+impl<T: ToCql> ToCqlValues for [T] {
+    fn to_cql_values(&self) -> Vec<CqlValue> {
+        self.iter().map(|v| v.to_cql()).collect()
+    }
+}
+
+impl<'a, V: ToCqlValues + ?Sized> ToCqlValues for &'a V {
+    fn to_cql_values(&self) -> Vec<CqlValue> {
+        (**self).to_cql_values()
+    }
+}
+
+impl ToCqlValues for () {
+    fn to_cql_values(&self) -> Vec<CqlValue> {
+        vec![]
+    }
+}
+
+macro_rules! to_cql_values_tuple_impl(
+    ($($t: ident: $index: tt),+) => {
+        impl<$($t: ToCql),+> ToCqlValues for ($($t,)+) {
+            fn to_cql_values(&self) -> Vec<CqlValue> {
+                vec![$(self.$index.to_cql()),+]
+            }
+        }
+    }
+);
+
+to_cql_values_tuple_impl!(A: 0);
+to_cql_values_tuple_impl!(A: 0, B: 1);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8);
+to_cql_values_tuple_impl!(A: 0, B: 1, C: 2, D: 3, E: 4, F: 5, G: 6, H: 7, I: 8, J: 9);
+
+// Sorts the fields of user defined types as declared by the server, which is
+// the order they are serialized in. Missing fields are sent as null.
+fn order_udt_fields(value: CqlValue, col_type: &CqlColType) -> CqlValue {
+    let params = &col_type.params;
+    match (value, &col_type.kind) {
+        (CqlUdt(Some(mut v)), &ColumnUdt) => {
+            let mut ordered = Vec::with_capacity(col_type.fields.len());
+            for (name, field_type) in col_type.fields.iter().zip(params.iter()) {
+                let value = match v.iter().position(|&(ref field, _)| field == name) {
+                    Some(i) => v.swap_remove(i).1,
+                    None => CqlRaw(None)
+                };
+                ordered.push((name.clone(), order_udt_fields(value, field_type)));
+            }
+            CqlUdt(Some(ordered))
+        },
+        (CqlList(Some(v)), _) if params.len() == 1 => CqlList(Some(v.into_iter().map(|e| order_udt_fields(e, &params[0])).collect())),
+        (CqlSet(Some(v)), _) if params.len() == 1 => CqlSet(Some(v.into_iter().map(|e| order_udt_fields(e, &params[0])).collect())),
+        (CqlMap(Some(v)), _) if params.len() == 2 => CqlMap(Some(v.into_iter().map(|p| Pair {
+            key: order_udt_fields(p.key, &params[0]),
+            value: order_udt_fields(p.value, &params[1])
+        }).collect())),
+        (CqlTuple(Some(v)), _) => CqlTuple(Some(v.into_iter().zip(params.iter()).map(|(e, t)| order_udt_fields(e, t)).collect())),
+        (value, _) => value
+    }
+}
+
+// Whether a value can be sent for a column of the given type. Strings, uuids and
+// collections are lenient where the wire format is the same.
+pub fn value_matches_type(value: &CqlValue, col_type: &CqlColType) -> bool {
+    if value.is_null() {
+        return true;
+    }
+    let params = &col_type.params;
+    match (value, &col_type.kind) {
+        (&CqlRaw(_), _) => true,
+        (&CqlBlob(_), &ColumnBlob) | (&CqlBlob(_), &ColumnCustom) => true,
+        (&CqlASCII(_), &ColumnASCII) | (&CqlASCII(_), &ColumnText) | (&CqlASCII(_), &ColumnVarChar) => true,
+        (&CqlText(_), &ColumnASCII) | (&CqlText(_), &ColumnText) | (&CqlText(_), &ColumnVarChar) => true,
+        (&CqlVarchar(_), &ColumnASCII) | (&CqlVarchar(_), &ColumnText) | (&CqlVarchar(_), &ColumnVarChar) => true,
+        (&CqlBigInt(_), &ColumnBigInt) | (&CqlBigInt(_), &ColumnCounter) => true,
+        (&CqlCounter(_), &ColumnBigInt) | (&CqlCounter(_), &ColumnCounter) => true,
+        (&CqlBoolean(_), &ColumnBoolean) => true,
+        (&CqlDecimal(_), &ColumnDecimal) => true,
+        (&CqlDouble(_), &ColumnDouble) => true,
+        (&CqlFloat(_), &ColumnFloat) => true,
+        (&CqlInet(_), &ColumnInet) => true,
+        (&CqlInt(_), &ColumnInt) => true,
+        (&CqlTimestamp(_), &ColumnTimestamp) => true,
+        (&CqlUuid(_), &ColumnUuid) | (&CqlUuid(_), &ColumnTimeUuid) => true,
+        (&CqlTimeUuid(_), &ColumnUuid) | (&CqlTimeUuid(_), &ColumnTimeUuid) => true,
+        (&CqlVarint(_), &ColumnVarint) => true,
+        (&CqlList(Some(ref v)), &ColumnList) | (&CqlList(Some(ref v)), &ColumnSet) |
+        (&CqlSet(Some(ref v)), &ColumnList) | (&CqlSet(Some(ref v)), &ColumnSet) => {
+            params.len() == 1 && v.iter().all(|e| value_matches_type(e, &params[0]))
+        },
+        (&CqlMap(Some(ref v)), &ColumnMap) => {
+            params.len() == 2 && v.iter().all(|p| value_matches_type(&p.key, &params[0]) && value_matches_type(&p.value, &params[1]))
+        },
+        (&CqlTuple(Some(ref v)), &ColumnTuple) => {
+            v.len() <= params.len() && v.iter().zip(params.iter()).all(|(e, t)| value_matches_type(e, t))
+        },
+        (&CqlUdt(Some(ref v)), &ColumnUdt) => {
+            v.iter().all(|&(ref name, ref e)| {
+                match col_type.fields.iter().position(|field| field == name) {
+                    Some(i) => i < params.len() && value_matches_type(e, &params[i]),
+                    None => false
+                }
+            })
+        },
+        _ => false
+    }
+}
+
+impl CqlPreparedStat {
+    // Converts the values and checks them against the bind markers of the statement
+    pub fn bind<V: ToCqlValues + ?Sized>(&self, values: &V) -> RCResult<Vec<CqlValue>> {
+        let values = values.to_cql_values();
+        let columns = &self.meta.row_metadata;
+        if values.len() != columns.len() {
+            return Err(RCError::new(format!("Prepared statement expects {} values but {} were given", columns.len(), values.len()), ConversionError));
+        }
+        for (i, (value, col)) in values.iter().zip(columns.iter()).enumerate() {
+            if !value_matches_type(value, &col.col_type) {
+                return Err(RCError::new(format!("Value {} ({:?}) doesn't match column '{}' of type {}", i, value, col.col_name, col.col_type), ConversionError));
+            }
+        }
+        Ok(values.into_iter().zip(columns.iter()).map(|(value, col)| order_udt_fields(value, &col.col_type)).collect())
+    }
+}
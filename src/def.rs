@@ -2,10 +2,15 @@ extern crate num;
 extern crate uuid;
 
 use std::net::{Ipv4Addr,Ipv6Addr,SocketAddr,IpAddr};
+use schema::SchemaMetadata;
+use cluster_events::ClusterEvent;
 use self::uuid::Uuid;
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::error::Error;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+use std::fmt;
 use eventual::{Future,Async, Timer};
 use error::*;
 //---------------------------CQL types------------------------------------------------------
@@ -84,7 +89,7 @@ impl OpcodeResponse {
     }
 }
 
-#[derive(Debug,Clone, Copy)]
+#[derive(Debug,Clone, Copy, PartialEq)]
 pub enum Consistency {
     Any = 0x0000,
     One = 0x0001,
@@ -94,6 +99,10 @@ pub enum Consistency {
     All = 0x0005,
     LocalQuorum = 0x0006,
     EachQuorum = 0x0007,
+    // Only valid as serial consistency of lightweight transactions
+    Serial = 0x0008,
+    LocalSerial = 0x0009,
+    LocalOne = 0x000A,
     Unknown,
 }
 
@@ -104,7 +113,25 @@ pub enum BatchType {
     Counter = 0x02
 }
 
-#[derive(Debug,PartialEq)]
+// Frame body compression, negotiated with the server on STARTUP
+#[derive(Debug,Clone,Copy,PartialEq)]
+pub enum Compression {
+    None,
+    Lz4,
+    Snappy
+}
+
+impl Compression {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Compression::None => "none",
+            Compression::Lz4 => "lz4",
+            Compression::Snappy => "snappy"
+        }
+    }
+}
+
+#[derive(Debug,Clone,PartialEq)]
 pub enum CqlValueType {
     ColumnCustom = 0x0000,
     ColumnASCII = 0x0001,
@@ -126,6 +153,8 @@ pub enum CqlValueType {
     ColumnList = 0x0020,
     ColumnMap = 0x0021,
     ColumnSet = 0x0022,
+    ColumnUdt = 0x0030,
+    ColumnTuple = 0x0031,
     ColumnUnknown,
 }
 
@@ -151,6 +180,8 @@ pub fn cql_column_type(val: u16) -> CqlValueType {
         0x0020 => CqlValueType::ColumnList,
         0x0021 => CqlValueType::ColumnMap,
         0x0022 => CqlValueType::ColumnSet,
+        0x0030 => CqlValueType::ColumnUdt,
+        0x0031 => CqlValueType::ColumnTuple,
         _ => CqlValueType::ColumnUnknown
     }
 }
@@ -215,16 +246,42 @@ impl CqlRequest{
 pub enum CqlRequestBody {
     RequestStartup(CqlStringMap),
     RequestCred(Vec<CowStr>),
-    RequestQuery(String, Consistency, u8),
+    RequestQuery(String, CqlQueryParams),
     RequestPrepare(String),
-    RequestExec(Vec<u8>, Vec<CqlValue>, Consistency, u8),
-    RequestBatch(Vec<Query>, BatchType, Consistency, u8),
+    RequestExec(Vec<u8>, CqlQueryParams),
+    // Only the consistencies and the default timestamp of the params are sent
+    RequestBatch(Vec<Query>, BatchType, CqlQueryParams),
     RequestOptions,
     RequestAuthResponse(Vec<u8>),
     RequestRegister(Vec<CqlValue>)
 }
 
 
+// Parameters of QUERY and EXECUTE requests, the query flags are derived from them
+#[derive(Debug, Clone)]
+pub struct CqlQueryParams {
+    pub consistency: Consistency,
+    pub values: Vec<CqlValue>,
+    pub page_size: Option<i32>,
+    pub paging_state: Option<Vec<u8>>,
+    pub serial_consistency: Option<Consistency>,
+    // Microseconds since epoch, only sent from v3
+    pub default_timestamp: Option<i64>
+}
+
+impl CqlQueryParams {
+    pub fn new(consistency: Consistency) -> CqlQueryParams {
+        CqlQueryParams {
+            consistency: consistency,
+            values: vec![],
+            page_size: None,
+            paging_state: None,
+            serial_consistency: None,
+            default_timestamp: None
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CqlStringMap {
     pub pairs: Vec<CqlPair>,
@@ -233,7 +290,49 @@ pub struct CqlStringMap {
 #[derive(Debug, Clone)]
 pub struct CqlPair {
     pub key: &'static str,
-    pub value: &'static str,
+    pub value: CowStr,
+}
+
+// Options the server answers an OPTIONS request with (SUPPORTED)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerCapabilities {
+    pub cql_versions: Vec<String>,
+    pub compression: Vec<String>,
+    // Only sent by Cassandra 3.0 onwards
+    pub protocol_versions: Vec<u8>,
+    // Every option, including the ones above
+    pub options: BTreeMap<String, Vec<String>>,
+}
+
+impl ServerCapabilities {
+    pub fn new(options: BTreeMap<String, Vec<String>>) -> ServerCapabilities {
+        let get = |key: &str| options.get(key).cloned().unwrap_or(vec![]);
+        // Protocol versions are sent as "3/v3", "5/v5-beta"...
+        let protocol_versions = get("PROTOCOL_VERSIONS").iter()
+            .filter_map(|v| v.split('/').next().and_then(|n| n.parse().ok()))
+            .collect();
+        ServerCapabilities {
+            cql_versions: get("CQL_VERSION"),
+            compression: get("COMPRESSION"),
+            protocol_versions: protocol_versions,
+            options: options,
+        }
+    }
+
+    pub fn supports_compression(&self, compression: Compression) -> bool {
+        compression == Compression::None || self.compression.iter().any(|c| c == compression.as_str())
+    }
+
+    pub fn supports_protocol_version(&self, version: u8) -> bool {
+        self.protocol_versions.is_empty() || self.protocol_versions.contains(&version)
+    }
+
+    // Highest CQL version the server speaks
+    pub fn cql_version(&self) -> Option<&str> {
+        self.cql_versions.iter()
+            .max_by_key(|v| v.split('.').map(|n| n.parse::<u32>().unwrap_or(0)).collect::<Vec<u32>>())
+            .map(|v| v.as_str())
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -257,6 +356,10 @@ pub type CQLMap = Vec<Pair<CqlValue, CqlValue>>;
 
 pub type CQLSet = Vec<CqlValue>;
 
+pub type CQLTuple = Vec<CqlValue>;
+// Fields of a user defined type, by name
+pub type CQLUdt = Vec<(CowStr, CqlValue)>;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum CqlValue {
     CqlASCII(Option<CowStr>),
@@ -276,11 +379,28 @@ pub enum CqlValue {
     CqlTimestamp(Option<u64>),
     CqlUuid(Option<Uuid>),
     CqlTimeUuid(Option<Uuid>),
+    CqlTuple(Option<CQLTuple>),
+    CqlUdt(Option<CQLUdt>),
     CqlVarchar(Option<CowStr>),
     CqlVarint(Option<num::BigInt>),
+    CqlRaw(Option<Vec<u8>>),
     CqlUnknown,
 }
 
+impl CqlValue {
+    pub fn is_null(&self) -> bool {
+        match *self {
+            CqlValue::CqlASCII(None) | CqlValue::CqlBigInt(None) | CqlValue::CqlBlob(None) |
+            CqlValue::CqlBoolean(None) | CqlValue::CqlCounter(None) | CqlValue::CqlDecimal(None) |
+            CqlValue::CqlDouble(None) | CqlValue::CqlFloat(None) | CqlValue::CqlInet(None) |
+            CqlValue::CqlInt(None) | CqlValue::CqlList(None) | CqlValue::CqlMap(None) |
+            CqlValue::CqlSet(None) | CqlValue::CqlText(None) | CqlValue::CqlTimestamp(None) |
+            CqlValue::CqlUuid(None) | CqlValue::CqlTimeUuid(None) | CqlValue::CqlTuple(None) |
+            CqlValue::CqlUdt(None) | CqlValue::CqlVarchar(None) | CqlValue::CqlVarint(None) | CqlValue::CqlRaw(None) => true,
+            _ => false
+        }
+    }
+}
 
 #[derive(Debug, PartialEq)]
 pub struct CqlResponse {
@@ -289,6 +409,8 @@ pub struct CqlResponse {
     pub stream: i16,
     pub opcode: OpcodeResponse,
     pub body: CqlResponseBody,
+    // Tracing session of a request sent with the tracing flag, see Cluster::get_query_trace
+    pub tracing_id: Option<Uuid>,
 }
 
 impl CqlResponse{
@@ -306,6 +428,7 @@ pub enum CqlResponseBody {
     ResponseAuthChallenge(Vec<u8>),
     ResponseAuthSuccess(Vec<u8>),
     ResponseEvent(CqlEvent),
+    ResponseSupported(ServerCapabilities),
 
     ResultVoid,
     ResultRows(CqlRows),
@@ -317,7 +440,7 @@ pub enum CqlResponseBody {
     ResponseEmpty,
 }
 
-#[derive(Debug,PartialEq)]
+#[derive(Debug,Clone,PartialEq)]
 pub struct CqlPreparedStat {
     pub id: Vec<u8>,
     pub meta: CqlMetadata,
@@ -333,13 +456,21 @@ pub enum Query {
 
 #[derive(Debug,PartialEq)]
 pub struct CqlRows {
-    pub metadata: CqlMetadata,
+    pub metadata: Arc<CqlMetadata>,
     pub rows: Vec<CqlRow>,
 }
 
-#[derive(Debug,Clone,PartialEq)]
+#[derive(Clone,PartialEq)]
 pub struct CqlRow {
     pub cols: Vec<CqlValue>,
+    // Shared with the rest of rows, to look up columns by name
+    pub metadata: Arc<CqlMetadata>,
+}
+
+impl fmt::Debug for CqlRow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CqlRow {{ cols: {:?} }}", self.cols)
+    }
 }
 
 
@@ -348,7 +479,7 @@ pub struct CqlTableDesc {
     pub tablename: String
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CqlMetadata {
     pub flags: u32,
     pub column_count: u32,
@@ -357,15 +488,99 @@ pub struct CqlMetadata {
     pub row_metadata: Vec<CqlColMetadata>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct CqlColMetadata {
     pub keyspace: CowStr,
     pub table: CowStr,
     pub col_name: CowStr,
-    pub custom_type: Option<CowStr>,
-    pub col_type: CqlValueType,
-    pub col_type_aux1: CqlValueType,
-    pub col_type_aux2: CqlValueType
+    pub col_type: CqlColType
+}
+
+// Column type as sent by the server in an [option], nested as deep as needed
+// (e.g. list<frozen<map<text,int>>>)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CqlColType {
+    pub kind: CqlValueType,
+    // Java class name of a custom type, keyspace.name of a user defined type
+    pub custom: Option<CowStr>,
+    // Element type of a list or set, key and value types of a map,
+    // component types of a tuple, field types of a user defined type
+    pub params: Vec<CqlColType>,
+    // Field names of a user defined type, in the same order as params
+    pub fields: Vec<CowStr>
+}
+
+impl CqlColType {
+    pub fn new(kind: CqlValueType) -> CqlColType {
+        CqlColType {
+            kind: kind,
+            custom: None,
+            params: vec![],
+            fields: vec![]
+        }
+    }
+
+    pub fn with_params(kind: CqlValueType, params: Vec<CqlColType>) -> CqlColType {
+        CqlColType {
+            kind: kind,
+            custom: None,
+            params: params,
+            fields: vec![]
+        }
+    }
+}
+
+// CQL name of the type, e.g. map<varchar, frozen<list<int>>>
+impl fmt::Display for CqlColType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self.kind {
+            CqlValueType::ColumnCustom => match self.custom {
+                Some(ref class) => return write!(f, "'{}'", class),
+                None => "custom"
+            },
+            CqlValueType::ColumnUdt => match self.custom {
+                Some(ref name) => return write!(f, "{}", name),
+                None => "udt"
+            },
+            CqlValueType::ColumnASCII => "ascii",
+            CqlValueType::ColumnBigInt => "bigint",
+            CqlValueType::ColumnBlob => "blob",
+            CqlValueType::ColumnBoolean => "boolean",
+            CqlValueType::ColumnCounter => "counter",
+            CqlValueType::ColumnDecimal => "decimal",
+            CqlValueType::ColumnDouble => "double",
+            CqlValueType::ColumnFloat => "float",
+            CqlValueType::ColumnInt => "int",
+            CqlValueType::ColumnText => "text",
+            CqlValueType::ColumnTimestamp => "timestamp",
+            CqlValueType::ColumnUuid => "uuid",
+            CqlValueType::ColumnVarChar => "varchar",
+            CqlValueType::ColumnVarint => "varint",
+            CqlValueType::ColumnTimeUuid => "timeuuid",
+            CqlValueType::ColumnInet => "inet",
+            CqlValueType::ColumnList => "list",
+            CqlValueType::ColumnMap => "map",
+            CqlValueType::ColumnSet => "set",
+            CqlValueType::ColumnTuple => "tuple",
+            CqlValueType::ColumnUnknown => "unknown"
+        };
+        try!(write!(f, "{}", name));
+        if !self.params.is_empty() {
+            try!(write!(f, "<"));
+            for (i, param) in self.params.iter().enumerate() {
+                if i > 0 {
+                    try!(write!(f, ", "));
+                }
+                if param.params.is_empty() {
+                    try!(write!(f, "{}", param));
+                } else {
+                    try!(write!(f, "frozen<{}>", param));
+                }
+            }
+            try!(write!(f, ">"));
+        }
+        Ok(())
+    }
 }
 
 
@@ -377,6 +592,29 @@ pub enum CqlEvent {
     UnknownEvent
 }
 
+// Messages to the cluster's event handler loop
+#[derive(Debug)]
+pub enum EventMsg {
+    Event(CqlEvent),
+    // Nodes found by the control connection, which is connected to `control`, and
+    // the schema or why it couldn't be read
+    Refresh{
+        control: SocketAddr,
+        peers: Vec<IpAddr>,
+        schema: Result<SchemaMetadata, String>
+    },
+    // Outcome of a connection attempt started by the event handler
+    NodeUp(SocketAddr),
+    NodeFailed{
+        address: SocketAddr,
+        attempt: u32,
+        error: String
+    },
+    // Published to subscribers as is
+    Publish(ClusterEvent),
+    Shutdown
+}
+
 #[derive(Debug,PartialEq)]
 pub enum TopologyChangeType{
     NewNode,
@@ -413,7 +651,7 @@ impl StatusChangeType{
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum SchemaChangeType{
     Created,
     Updated,
@@ -432,11 +670,27 @@ impl SchemaChangeType{
     }
 }
 
-#[derive(Debug, PartialEq)]
+// What changed: the keyspace, and the name of the table, type, function or aggregate
+// in it. Functions and aggregates also have the types of their arguments.
+#[derive(Debug, Clone, PartialEq)]
 pub enum SchemaChangeOptions{
     Keyspace(CowStr),
     Table(CowStr,CowStr),
-    Type(CowStr,CowStr)
+    Type(CowStr,CowStr),
+    Function(CowStr,CowStr,Vec<CowStr>),
+    Aggregate(CowStr,CowStr,Vec<CowStr>)
+}
+
+impl SchemaChangeOptions{
+    pub fn keyspace(&self) -> &str {
+        match *self {
+            SchemaChangeOptions::Keyspace(ref keyspace) => keyspace,
+            SchemaChangeOptions::Table(ref keyspace,_) => keyspace,
+            SchemaChangeOptions::Type(ref keyspace,_) => keyspace,
+            SchemaChangeOptions::Function(ref keyspace,_,_) => keyspace,
+            SchemaChangeOptions::Aggregate(ref keyspace,_,_) => keyspace
+        }
+    }
 }
 
 //------------------------------------------------------------
@@ -462,12 +716,37 @@ pub const SCHEMA_CHANGE_TYPE_DROPPED:  &'static str = "DROPPED";
 pub const SCHEMA_CHANGE_TARGET_KEYSPACE:  &'static str = "KEYSPACE";
 pub const SCHEMA_CHANGE_TARGET_TABLE:  &'static str = "TABLE";
 pub const SCHEMA_CHANGE_TARGET_TYPE:  &'static str = "TYPE";
+pub const SCHEMA_CHANGE_TARGET_FUNCTION:  &'static str = "FUNCTION";
+pub const SCHEMA_CHANGE_TARGET_AGGREGATE:  &'static str = "AGGREGATE";
 
 
 pub static CQL_VERSION_STRINGS:  [&'static str; 3] = ["3.0.0", "3.0.0", "3.0.0"];
 pub static CQL_MAX_SUPPORTED_VERSION: u8 = 0x03;
 pub static CQL_DEFAULT_PORT: u16 = 9042;
 
+// Frame header flags
+pub const CQL_FLAG_COMPRESSION: u8 = 0x01;
+pub const CQL_FLAG_TRACING: u8 = 0x02;
+
+// Query flags of QUERY and EXECUTE requests
+pub const CQL_QUERY_FLAG_VALUES: u8 = 0x01;
+pub const CQL_QUERY_FLAG_SKIP_METADATA: u8 = 0x02;
+pub const CQL_QUERY_FLAG_PAGE_SIZE: u8 = 0x04;
+pub const CQL_QUERY_FLAG_PAGING_STATE: u8 = 0x08;
+pub const CQL_QUERY_FLAG_SERIAL_CONSISTENCY: u8 = 0x10;
+pub const CQL_QUERY_FLAG_DEFAULT_TIMESTAMP: u8 = 0x20;
+pub const CQL_QUERY_FLAG_NAMES_FOR_VALUES: u8 = 0x40;
+
+// Error codes of ERROR responses
+pub const CQL_ERROR_BAD_CREDENTIALS: u32 = 0x0100;
+pub const CQL_ERROR_UNAVAILABLE: u32 = 0x1000;
+pub const CQL_ERROR_OVERLOADED: u32 = 0x1001;
+pub const CQL_ERROR_IS_BOOTSTRAPPING: u32 = 0x1002;
+pub const CQL_ERROR_WRITE_TIMEOUT: u32 = 0x1100;
+pub const CQL_ERROR_READ_TIMEOUT: u32 = 0x1200;
+// Sent when executing a statement the node doesn't have prepared
+pub const CQL_ERROR_UNPREPARED: u32 = 0x2500;
+
 //Max stream id is 2^7-1. 
 //Stream can go from 0 to 127. 
 //Maximum of 128 requests simulatenous requests
@@ -482,4 +761,5 @@ pub const CQL_MAX_STREAM_ID_V3 : i16 = 32767;
 
 
 pub type CassFuture = Future<RCResult<CqlResponse>,()>;
+pub type PreparedFuture = Future<RCResult<CqlPreparedStat>,()>;
 
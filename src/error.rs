@@ -18,7 +18,12 @@ pub enum RCErrorType {
     GenericError,
     IOError,
     EventLoopError,
-    ClusterError
+    ClusterError,
+    ConversionError,
+    CompressionError,
+    OverloadedError,
+    AuthenticationError,
+    TimeoutError
 }
 
 #[derive(Debug,Clone)]
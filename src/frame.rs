@@ -0,0 +1,56 @@
+// Reassembles CQL frames out of the bytes read from a socket. A read can end in the
+// middle of a frame or hold several of them, so bytes are buffered until the length
+// in the header is satisfied and whatever follows is kept for the next frame.
+#[derive(Debug)]
+pub struct FrameBuffer {
+    data: Vec<u8>
+}
+
+impl FrameBuffer {
+    pub fn new() -> FrameBuffer {
+        FrameBuffer {
+            data: Vec::new()
+        }
+    }
+
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    // Length of the first frame, header included, once it is complete
+    fn frame_length(&self, version: u8) -> Option<usize> {
+        let header_len = if version >= 3 { 9 } else { 8 };
+        if self.data.len() < header_len {
+            return None;
+        }
+        let body_len = self.data[header_len-4 .. header_len].iter()
+                                                          .fold(0usize, |len, b| (len << 8) | *b as usize);
+        let len = header_len + body_len;
+        if self.data.len() >= len { Some(len) } else { None }
+    }
+
+    // Takes the first complete frame out of the buffer
+    pub fn next_frame(&mut self, version: u8) -> Option<Vec<u8>> {
+        self.frame_length(version).map(|len| {
+            let rest = self.data.split_off(len);
+            ::std::mem::replace(&mut self.data, rest)
+        })
+    }
+}
+
+// Stream id in a frame's header, a byte before v3 and two since
+pub fn frame_stream(frame: &[u8], version: u8) -> i16 {
+    if version >= 3 {
+        ((frame[2] as i16) << 8) | frame[3] as i16
+    } else {
+        frame[2] as i8 as i16
+    }
+}
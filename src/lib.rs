@@ -16,18 +16,50 @@ pub use def::Consistency;
 pub use def::BatchType;
 pub use def::CqlValue;
 pub use def::CqlValue::*;
+pub use def::CqlValueType;
+pub use def::CqlColType;
 pub use def::CqlEventType;
 pub use def::CQLList;
 pub use def::CQLMap;
 pub use def::CQLSet;
+pub use def::CQLTuple;
+pub use def::CQLUdt;
+pub use def::Pair;
 pub use def::Query::QueryStr;
 pub use def::Query::QueryPrepared;
 pub use def::OpcodeResponse;
 pub use def::CqlResponseBody;
+pub use def::CqlRows;
+pub use def::CqlRow;
+pub use def::CqlMetadata;
+pub use def::CqlColMetadata;
+pub use def::CqlPreparedStat;
 pub use error::*;
 pub use error::RCErrorType::*;
 pub use reader::CqlReader;
+pub use serialize::CqlSerializable;
+pub use def::CqlRequest;
 pub use def::CassFuture;
+pub use def::CqlQueryParams;
+pub use def::Compression;
+pub use def::ServerCapabilities;
+pub use frame::FrameBuffer;
+pub use stream_id::StreamIdAllocator;
+pub use request_limits::{RequestLimits, OverloadPolicy};
+pub use retry::{RetryPolicy, RetryDecision, DefaultRetryPolicy, FallthroughRetryPolicy};
+pub use reconnection::{ReconnectionPolicy, ConstantReconnectionPolicy, ExponentialReconnectionPolicy};
+pub use cluster_builder::{ClusterBuilder, ClusterConfig};
+pub use schema::{SchemaMetadata, KeyspaceMetadata};
+pub use cluster_events::{ClusterEvent, ClusterListener};
+pub use def::{CqlEvent, SchemaChangeType, SchemaChangeOptions, TopologyChangeType, StatusChangeType};
+pub use compression::{available_compressions, compress, decompress};
+#[cfg(feature = "tls")]
+pub use tls::rustls;
+pub use statement::{Statement, StatementQuery};
+pub use query_trace::{QueryTrace, TraceEvent, parse_trace};
+pub use timestamp::{TimestampGenerator, ServerSideTimestampGenerator, MonotonicTimestampGenerator};
+pub use def::PreparedFuture;
+pub use convert::{FromCql, ToCql, ToCqlValues, FromRow, LwtResult, from_udt_field};
 use def::CqlResponse;
 pub use def::CqlBytesSize;
 pub use def::CqlBytesSize::*;
@@ -129,5 +161,21 @@ mod connection_pool;
 mod node;
 mod load_balancing;
 mod util;
+mod convert;
+mod prepared_cache;
+mod statement;
+mod timestamp;
+mod query_trace;
+mod compression;
+mod tls;
+mod frame;
+mod stream_id;
+mod request_limits;
+mod retry;
+mod reconnection;
+mod cluster_builder;
+mod control_connection;
+mod schema;
+mod cluster_events;
 mod error;
 pub mod cluster;
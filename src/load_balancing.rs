@@ -45,6 +45,7 @@ impl LoadBalancing for LatencyAware {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BalancerType{
     RoundRobin,
     LatencyAware
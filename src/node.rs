@@ -1,11 +1,12 @@
 
 use eventual::{Future, Async, Complete};
-use mio::{EventLoop,Sender};
+use mio::{EventLoop,Sender,NotifyError};
 use std::net::SocketAddr;
 use std::error::Error;
 use std::thread;
 use std::sync::mpsc::channel;
 use std::sync::Arc;
+use std::borrow::Cow;
 use std::time::{Instant,Duration};
 
 use def::*;
@@ -14,10 +15,12 @@ use def::CqlRequestBody::*;
 use error::RCErrorType::*;
 use def::CqlResponseBody::*;
 use def::CqlValue::*;
-use connection::CqlMsg;
+use connection::{CqlMsg, startup_request};
+use statement::Statement;
 use connection_pool::ConnectionPool;
 use error::*;
 
+#[derive(Clone)]
 pub struct Node {
     channel_cpool: Sender<CqlMsg>, 
     pub version: u8,
@@ -26,11 +29,11 @@ pub struct Node {
 
 impl Node{
     
-    pub fn new(address: SocketAddr,channel_cpool: Sender<CqlMsg>) -> Node {
+    pub fn new(address: SocketAddr,channel_cpool: Sender<CqlMsg>,version: u8) -> Node {
 
         Node{
             channel_cpool: channel_cpool,
-            version: CQL_MAX_SUPPORTED_VERSION,
+            version: version,
             address: address
         }
     }
@@ -68,13 +71,11 @@ impl Node{
     }
 
     pub fn exec_query(& self, query_str: &str, con: Consistency) -> CassFuture {
-        let q = CqlRequest {
-            version: self.version,
-            flags: 0x00,
-            stream: 0x01,
-            opcode: OpcodeQuery,
-            body: RequestQuery(String::from(query_str), con, 0)};
-        self.send_message(q)
+        self.execute(&Statement::query(query_str).consistency(con))
+    }
+
+    pub fn execute(&self, statement: &Statement) -> CassFuture {
+        self.send_message(statement.to_request(self.version))
     }
     
     pub fn get_peers(&self) -> CassFuture{
@@ -85,24 +86,13 @@ impl Node{
         self.exec_query(query,Consistency::One)
     }
 
-    pub fn exec_prepared(&self, preps: &Vec<u8>, params: &Vec<CqlValue>, con: Consistency) -> CassFuture{
-        let q = CqlRequest {
-            version: self.version,
-            flags: 0x00,
-            stream: 0x01,
-            opcode: OpcodeExecute,
-            body: RequestExec(preps.clone(), params.clone(), con, 0x01),
-        };
-        self.send_message(q)
-    }
-    
-    pub fn exec_batch(&self, q_type: BatchType, q_vec: Vec<Query>, con: Consistency) -> CassFuture {
+    pub fn exec_batch(&self, q_type: BatchType, q_vec: Vec<Query>, params: CqlQueryParams) -> CassFuture {
         let q = CqlRequest {
             version: self.version,
             flags: 0x00,
             stream: 0x01,
             opcode: OpcodeBatch,
-            body: RequestBatch(q_vec, q_type, con, 0)
+            body: RequestBatch(q_vec, q_type, params)
         };
 
         /* Code to debug batch statements. Write to file the serialization of the request
@@ -120,7 +110,7 @@ impl Node{
     }
 
 
-    pub fn prepared_statement(&self, query_str: &str) -> RCResult<CqlPreparedStat> {
+    pub fn prepare(&self, query_str: &str) -> CassFuture {
         let q = CqlRequest {
             version: self.version,
             flags: 0x00,
@@ -128,32 +118,33 @@ impl Node{
             opcode: OpcodePrepare,
             body: RequestPrepare(query_str.to_string()),
         };
-
-        let future = self.send_message(q);
-        let mut cql_response = future.await()
-                                     .ok().expect("Couldn't recieve future")
-                                     .ok().expect("Couldn't get CQL response");
-        match cql_response.body {
-            ResultPrepared(preps) => {
-                Ok(preps)
-            },
-            _ => Err(RCError::new("Response does not contain prepared statement", ReadError))
-        }
+        self.send_message(q)
     }
 
     fn send_message(&self,request: CqlRequest) -> CassFuture{
         let (tx, future) = Future::<RCResult<CqlResponse>, ()>::pair();
-        self.channel_cpool.send(CqlMsg::Request{
-                                request: request,
-                                tx: tx,
-                                address: self.address});
+        self.send(CqlMsg::Request{
+                  request: request,
+                  tx: tx,
+                  address: self.address});
         future
     }
 
+    // Messages the pool can't take are completed with an error instead of being dropped
+    fn send(&self,msg: CqlMsg){
+        match self.channel_cpool.send(msg) {
+            Ok(_) => (),
+            Err(NotifyError::Closed(Some(msg))) => msg.complete(Err(RCError::new("Cluster is shut down", ClusterError))),
+            Err(NotifyError::Full(msg)) => msg.complete(Err(RCError::new("Too many requests queued", OverloadedError))),
+            Err(_) => ()
+        }
+    }
+
     pub fn send_register(&self) -> CassFuture{
         //println!("Node::send_register");
         let params = vec![ CqlVarchar( Some(CqlEventType::StatusChange.get_str())),
-                           CqlVarchar( Some(CqlEventType::TopologyChange.get_str() ))
+                           CqlVarchar( Some(CqlEventType::TopologyChange.get_str() )),
+                           CqlVarchar( Some(CqlEventType::SchemaChange.get_str() ))
         ];
         let msg_register = CqlRequest {
             version: self.version,
@@ -167,20 +158,10 @@ impl Node{
 
     pub fn connect(&self) -> CassFuture{
         let (tx, future) = Future::<RCResult<CqlResponse>, ()>::pair();
-        let body = CqlStringMap {
-            pairs:vec![CqlPair{key: "CQL_VERSION", value: CQL_VERSION_STRINGS[(self.version-1) as usize]}],
-        };
-        let msg_startup = CqlRequest {
-            version: self.version,
-            flags: 0x00,
-            stream: 0x01,
-            opcode: OpcodeStartup,
-            body: RequestStartup(body),
-        };
-        self.channel_cpool.send(CqlMsg::Connect{
-                                request: msg_startup,
-                                tx: tx,
-                                address: self.address});
+        self.send(CqlMsg::Connect{
+                  request: startup_request(self.version),
+                  tx: tx,
+                  address: self.address});
         future
     }
 
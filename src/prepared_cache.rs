@@ -0,0 +1,72 @@
+use std::collections::BTreeMap;
+
+use def::{CqlPreparedStat, CqlMetadata};
+
+// Prepared statements by query string, so they can be prepared again on nodes
+// that lost them (e.g. after a restart) or that joined the cluster later
+pub struct PreparedCache {
+    by_query: BTreeMap<String, CqlPreparedStat>,
+    // Prepared id -> query string
+    queries: BTreeMap<Vec<u8>, String>
+}
+
+impl PreparedCache {
+    pub fn new() -> PreparedCache {
+        PreparedCache {
+            by_query: BTreeMap::new(),
+            queries: BTreeMap::new()
+        }
+    }
+
+    pub fn get(&self, query: &str) -> Option<&CqlPreparedStat> {
+        self.by_query.get(query)
+    }
+
+    pub fn query_of(&self, id: &Vec<u8>) -> Option<&String> {
+        self.queries.get(id)
+    }
+
+    pub fn insert(&mut self, query: String, preps: CqlPreparedStat) {
+        // The id only changes if the schema of the table does, the old one isn't kept
+        if let Some(old) = self.by_query.get(&query) {
+            self.queries.remove(&old.id);
+        }
+        self.queries.insert(preps.id.clone(), query.clone());
+        self.by_query.insert(query, preps);
+    }
+
+    // Forgets the statements that use the table, or any table of the keyspace if it's None, so the
+    // next Cluster::prepare of their query asks the nodes again. Nothing is prepared here, and the
+    // statements callers already hold keep their metadata. Their ids are forgotten too, so if a node
+    // answers Unprepared to one of them it isn't prepared again: its query has to be prepared anew.
+    pub fn invalidate(&mut self, keyspace: &str, table: Option<&str>) -> usize {
+        let queries: Vec<String> = self.by_query.iter()
+                                       .filter(|&(_, preps)| uses_table(preps, keyspace, table))
+                                       .map(|(query, _)| query.clone())
+                                       .collect();
+        for query in queries.iter() {
+            if let Some(preps) = self.by_query.remove(query) {
+                self.queries.remove(&preps.id);
+            }
+        }
+        queries.len()
+    }
+
+    pub fn queries(&self) -> Vec<String> {
+        self.by_query.keys().cloned().collect()
+    }
+}
+
+fn uses_table(preps: &CqlPreparedStat, keyspace: &str, table: Option<&str>) -> bool {
+    let matches = |meta: &CqlMetadata| {
+        let tables = Some((&meta.keyspace, &meta.table)).into_iter()
+                         .chain(meta.row_metadata.iter().map(|col| (&col.keyspace, &col.table)));
+        for (ks, t) in tables {
+            if ks == keyspace && table.map(|table| t == table).unwrap_or(true) {
+                return true;
+            }
+        }
+        false
+    };
+    matches(&preps.meta) || preps.meta_result.as_ref().map(|meta| matches(meta)).unwrap_or(false)
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use uuid::Uuid;
+
+use def::*;
+use convert::FromRow;
+use error::*;
+
+pub const TRACE_SESSION_QUERY: &'static str = "SELECT session_id, coordinator, duration, parameters, request, started_at FROM system_traces.sessions WHERE session_id = ?";
+pub const TRACE_EVENTS_QUERY: &'static str = "SELECT event_id, activity, source, source_elapsed, thread FROM system_traces.events WHERE session_id = ?";
+
+// Trace of a request sent with tracing enabled, as stored in system_traces
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueryTrace {
+    pub session_id: Uuid,
+    pub coordinator: IpAddr,
+    // Microseconds
+    pub duration: i32,
+    pub parameters: HashMap<String, String>,
+    pub request: String,
+    // Milliseconds since epoch
+    pub started_at: u64,
+    pub events: Vec<TraceEvent>
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent {
+    pub event_id: Uuid,
+    pub activity: String,
+    pub source: IpAddr,
+    // Microseconds since the request started on the source node
+    pub source_elapsed: i32,
+    pub thread: String
+}
+
+impl FromRow for TraceEvent {
+    fn from_row(row: &CqlRow) -> RCResult<TraceEvent> {
+        Ok(TraceEvent {
+            event_id: try!(row.get_by_name("event_id")),
+            activity: try!(row.get_opt_by_name("activity")).unwrap_or_default(),
+            source: try!(row.get_by_name("source")),
+            source_elapsed: try!(row.get_opt_by_name("source_elapsed")).unwrap_or_default(),
+            thread: try!(row.get_opt_by_name("thread")).unwrap_or_default()
+        })
+    }
+}
+
+// The session row is written when the request starts and its duration when it completes,
+// so None means the trace is not complete yet
+pub fn parse_trace(session: &CqlRows, events: &CqlRows) -> RCResult<Option<QueryTrace>> {
+    let row = match session.rows.first() {
+        Some(row) => row,
+        None => return Ok(None)
+    };
+    let duration = match try!(row.get_opt_by_name::<i32>("duration")) {
+        Some(duration) => duration,
+        None => return Ok(None)
+    };
+    Ok(Some(QueryTrace {
+        session_id: try!(row.get_by_name("session_id")),
+        coordinator: try!(row.get_by_name("coordinator")),
+        duration: duration,
+        parameters: try!(row.get_opt_by_name("parameters")).unwrap_or_default(),
+        request: try!(row.get_opt_by_name("request")).unwrap_or_default(),
+        started_at: try!(row.get_opt_by_name("started_at")).unwrap_or_default(),
+        events: try!(events.parse())
+    }))
+}
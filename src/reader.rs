@@ -19,19 +19,24 @@ use std::borrow::{Cow, ToOwned};
 use std::io::{Read, Write, Cursor};
 use self::byteorder::{ReadBytesExt, BigEndian, LittleEndian};
 use std::mem::size_of;
+use std::sync::Arc;
+use std::collections::BTreeMap;
 use std::path::Path;
 use std::error::Error;
 use ep::FromPrimitive;
 use error::*;
+use compression;
 use self::num::bigint::BigInt;
 
 
 pub trait CqlReader {
     fn read_cql_bytes_with_length(&mut self, val_type: CqlBytesSize) -> RCResult<Vec<u8>>;
+    fn read_cql_bytes_exact(&mut self, len: usize) -> RCResult<Vec<u8>>;
     fn read_cql_bytes_length(&mut self, val_type: CqlBytesSize) -> RCResult<i32>;
     fn read_cql_bytes_length_fixed(&mut self, val_type: CqlBytesSize, length: i32) -> RCResult<i32>;
 
     fn read_cql_str(&mut self, val_type: CqlBytesSize) -> RCResult<Option<CowStr>>;
+    fn read_cql_string_multimap(&mut self) -> RCResult<BTreeMap<String, Vec<String>>>;
     fn read_cql_f32(&mut self, val_type: CqlBytesSize) -> RCResult<Option<f32>>;
     fn read_cql_f64(&mut self, val_type: CqlBytesSize) -> RCResult<Option<f64>>;
     fn read_cql_i32(&mut self, val_type: CqlBytesSize) -> RCResult<Option<i32>>;
@@ -42,21 +47,26 @@ pub trait CqlReader {
     fn read_cql_uuid(&mut self, val_type: CqlBytesSize) -> RCResult<Option<Uuid>>;
     fn read_cql_inet_with_port(&mut self, val_type: CqlBytesSize) -> RCResult<Option<SocketAddr>>;
     fn read_cql_inet_no_port(&mut self, val_type: CqlBytesSize) -> RCResult<Option<IpAddr>>;
-    fn read_cql_event(&mut self, val_type: CqlBytesSize) -> RCResult<CqlEvent>;
+    fn read_cql_event(&mut self, val_type: CqlBytesSize, version: u8) -> RCResult<CqlEvent>;
+    fn read_cql_string_list(&mut self) -> RCResult<Vec<CowStr>>;
     fn read_cql_varint(&mut self, val_type: CqlBytesSize)  -> RCResult<Option<BigInt>>;
 
-    fn read_cql_list(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLList>>;
-    fn read_cql_set(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLSet>>;
-    fn read_cql_map(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLMap>>;
+    fn read_cql_list(&mut self, elem_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLList>>;
+    fn read_cql_set(&mut self, elem_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLSet>>;
+    fn read_cql_map(&mut self, key_type: &CqlColType, value_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLMap>>;
+    fn read_cql_tuple(&mut self, types: &Vec<CqlColType>, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLTuple>>;
+    fn read_cql_collection_bytes(&mut self, value_size: CqlBytesSize) -> RCResult<Option<Cursor<Vec<u8>>>>;
 
+    fn read_cql_col_type(&mut self) -> RCResult<CqlColType>;
     fn read_cql_metadata(&mut self) -> RCResult<CqlMetadata>;
     fn read_cql_frame_header(&mut self, version: u8) -> RCResult<CqlFrameHeader>;
     fn read_cql_response(&mut self, version: u8) -> RCResult<CqlResponse>;
+    fn read_cql_response_compressed(&mut self, version: u8, compression: Compression) -> RCResult<CqlResponse>;
     fn read_cql_rows(&mut self, collection_size: CqlBytesSize) -> RCResult<CqlRows>;
 
     fn read_cql_skip(&mut self, val_type: CqlBytesSize) -> RCResult<()>;
 
-    fn read_cql_value(&mut self, col_meta: &CqlColMetadata, collection_size: CqlBytesSize) -> RCResult<CqlValue>;
+    fn read_cql_value(&mut self, col_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<CqlValue>;
     fn read_cql_value_single(&mut self, col_type: &CqlValueType, value_size: CqlBytesSize) -> RCResult<CqlValue>;
 
 
@@ -69,27 +79,30 @@ impl<T: Read> CqlReader for T {
         if len < 0 {
             Ok(vec![])
         } else {
-            let mut buf = Vec::with_capacity(len as usize);
-            try_io!(std::io::copy(&mut self.take(len as u64), &mut buf), "Error at read_exact");
-            Ok(buf)
+            self.read_cql_bytes_exact(len as usize)
         }
     }
 
+    // The length comes from the wire, so the buffer is not preallocated with it
+    fn read_cql_bytes_exact(&mut self, len: usize) -> RCResult<Vec<u8>> {
+        let mut buf = Vec::new();
+        try_io!(std::io::copy(&mut self.take(len as u64), &mut buf), "Error at read_exact");
+        if buf.len() != len {
+            return Err(RCError::new(format!("Unexpected EOF, expected {} bytes but read {}", len, buf.len()), RCErrorType::ReadError));
+        }
+        Ok(buf)
+    }
+
     fn read_cql_bytes_length(&mut self, val_type: CqlBytesSize) -> RCResult<i32> {
         match val_type {
             CqlBytesSize::Cqli32 => Ok(try_bo!(self.read_i32::<BigEndian>(), "Error reading bytes length")),
             CqlBytesSize::Cqli16 => Ok(try_bo!(self.read_i16::<BigEndian>(), "Error reading bytes length") as i32),
-            CqlBytesSize::Cqli8  => {
-                let size = 1;
-                let mut buf = Vec::with_capacity(size);
-                try_io!(std::io::copy(&mut self.take(size as u64), &mut buf), "Error at read_exact");
-                Ok(buf[0] as i32)
-            },
+            CqlBytesSize::Cqli8  => Ok(try_bo!(self.read_u8(), "Error reading bytes length") as i32),
         }       
     }
 
     fn read_cql_bytes_length_fixed(&mut self, val_type: CqlBytesSize, length: i32) -> RCResult<i32> {
-        let len = self.read_cql_bytes_length(val_type).unwrap();
+        let len = try_rc!(self.read_cql_bytes_length(val_type), "Error reading bytes length");
         if len != -1 && len != length {
             Err(RCError::new(format!("Error reading bytes, length ({}) different than expected ({})", len, length), RCErrorType::ReadError))
         }  else {
@@ -102,10 +115,8 @@ impl<T: Read> CqlReader for T {
         if len < 0 {
             return Ok(None);
         } else {
-            let mut buf = Vec::with_capacity(len as usize);
-            try_io!(std::io::copy(&mut self.take(len as u64), &mut buf), "Error at read_exact");
-            let vec_u8 = buf;
-            
+            let vec_u8 = try_rc!(self.read_cql_bytes_exact(len as usize), "Error reading string data");
+
             match std::str::from_utf8(&vec_u8) {
                 Ok(s) => Ok(Some(Cow::Owned(s.to_owned()))),
                 Err(_) => Err(RCError::new("Error reading string, invalid utf8 sequence", RCErrorType::ReadError))
@@ -113,6 +124,21 @@ impl<T: Read> CqlReader for T {
         }
     }
 
+    fn read_cql_string_multimap(&mut self) -> RCResult<BTreeMap<String, Vec<String>>> {
+        let mut map = BTreeMap::new();
+        let len = try_bo!(self.read_u16::<BigEndian>(), "Error reading string multimap (length)");
+        for _ in 0 .. len {
+            let key = try_rc_noption!(self.read_cql_str(CqlBytesSize::Cqli16), "Error reading string multimap (key)");
+            let count = try_bo!(self.read_u16::<BigEndian>(), "Error reading string multimap (values length)");
+            let mut values = Vec::with_capacity(count as usize);
+            for _ in 0 .. count {
+                values.push(try_rc_noption!(self.read_cql_str(CqlBytesSize::Cqli16), "Error reading string multimap (value)").into_owned());
+            }
+            map.insert(key.into_owned(), values);
+        }
+        Ok(map)
+    }
+
     fn read_cql_f32(&mut self, val_type: CqlBytesSize) -> RCResult<Option<f32>> {
         try_rc_length!(self.read_cql_bytes_length_fixed(val_type, size_of::<f32>() as i32), "Error reading bytes (float) length");
         Ok(Some(try_bo!(self.read_f32::<BigEndian>(), "Error reading float (float)")))  
@@ -169,24 +195,7 @@ impl<T: Read> CqlReader for T {
     }
 
     fn read_cql_inet_with_port(&mut self, val_type: CqlBytesSize) -> RCResult<Option<SocketAddr>> {
-        let vec = try_rc!(self.read_cql_bytes_with_length(val_type), "Error reading value data");
-        let ip =
-            if vec.len() == 0 {
-                None
-            } 
-            else if vec.len() == 4 {
-                Some(IpAddr::V4(Ipv4Addr::new(vec[0], vec[1], vec[2], vec[3])))
-            } 
-            else {
-                 Some(IpAddr::V6(Ipv6Addr::new(vec[1] as u16 + ((vec[0] as u16) << 8),
-                  vec[3] as u16 + ((vec[2] as u16) << 8),
-                  vec[5] as u16 + ((vec[4] as u16) << 8),
-                  vec[7] as u16 + ((vec[6] as u16) << 8),
-                  vec[9] as u16 + ((vec[8] as u16) << 8),
-                  vec[11] as u16 + ((vec[10] as u16) << 8),
-                  vec[13] as u16 + ((vec[12] as u16) << 8),
-                  vec[15] as u16 + ((vec[14] as u16) << 8)))) 
-            };
+        let ip = try_rc!(self.read_cql_inet_no_port(val_type), "Error reading value data");
         let port = try_bo!(self.read_i32::<BigEndian>(), "Error reading port");
 
         match ip {
@@ -202,7 +211,7 @@ impl<T: Read> CqlReader for T {
             Ok(None)
         } else if vec.len() == 4 {
             Ok(Some(IpAddr::V4(Ipv4Addr::new(vec[0], vec[1], vec[2], vec[3]))))
-        } else {
+        } else if vec.len() == 16 {
             Ok(Some(IpAddr::V6(Ipv6Addr::new(vec[1] as u16 + ((vec[0] as u16) << 8),
               vec[3] as u16 + ((vec[2] as u16) << 8),
               vec[5] as u16 + ((vec[4] as u16) << 8),
@@ -211,23 +220,37 @@ impl<T: Read> CqlReader for T {
               vec[11] as u16 + ((vec[10] as u16) << 8),
               vec[13] as u16 + ((vec[12] as u16) << 8),
               vec[15] as u16 + ((vec[14] as u16) << 8)))))     
+        } else {
+            Err(RCError::new(format!("Invalid inet length: {}", vec.len()), RCErrorType::ReadError))
         }
     }
 
-    fn read_cql_list(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLList>> {
-        try_bo!(self.read_i32::<BigEndian>(), "Error reading list size");
-        let len = try_unwrap!(self.read_cql_bytes_length(value_size));
+    fn read_cql_list(&mut self, elem_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLList>> {
+        let mut reader = match try_rc!(self.read_cql_collection_bytes(value_size), "Error reading list size") {
+            Some(reader) => reader,
+            None => return Ok(None)
+        };
+        let len = try_rc!(reader.read_cql_bytes_length(collection_size), "Error reading list length");
 
         let mut list: CQLList = vec![];
         for _ in 0 .. len {
-            let col = try_rc!(self.read_cql_value_single(&col_meta.col_type_aux1, value_size), 
+            let col = try_rc!(reader.read_cql_value(elem_type, collection_size, collection_size), 
                             "Error reading list value");
             list.push(col);
         }
         Ok(Some(list))
     }
 
-    fn read_cql_event(&mut self, val_type: CqlBytesSize) -> RCResult<CqlEvent> {
+    fn read_cql_string_list(&mut self) -> RCResult<Vec<CowStr>> {
+        let len = try_bo!(self.read_u16::<BigEndian>(), "Error reading string list (length)");
+        let mut list = Vec::with_capacity(len as usize);
+        for _ in 0 .. len {
+            list.push(try_rc_noption!(self.read_cql_str(CqlBytesSize::Cqli16), "Error reading string list (value)"));
+        }
+        Ok(list)
+    }
+
+    fn read_cql_event(&mut self, val_type: CqlBytesSize, version: u8) -> RCResult<CqlEvent> {
 
         let event_type = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), "Error reading event type (str)"));
         
@@ -259,39 +282,47 @@ impl<T: Read> CqlReader for T {
             },
             CqlEventType::SchemaChange =>{
                 let msg = error_msg.to_string() +"CqlEventType::SchemaChange : "; 
-                let change_type = try_rc!(self.read_cql_str(val_type), msg+"change_type (str)");
-                let target = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg+" target (str)"));
+                let change_type = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+"change_type (str)"));
+
+                // v1 and v2 only tell the keyspace and table, which is empty for keyspace changes
+                if version < 3 {
+                    let keyspace = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+" keyspace (str)"));
+                    let table = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg+" table (str)"));
+                    let options = if table.is_empty() {
+                        SchemaChangeOptions::Keyspace(keyspace)
+                    } else {
+                        SchemaChangeOptions::Table(keyspace,table)
+                    };
+                    return Ok(CqlEvent::SchemaChange(SchemaChangeType::from_str(&change_type), options));
+                }
 
+                let target = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+" target (str)"));
+                let keyspace = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+" keyspace (str)"));
                 let options =
-                    match target{
-                        Cow::Borrowed(SCHEMA_CHANGE_TARGET_KEYSPACE) => {
-                            let option =    try_unwrap_op!(
-                                            try_rc!(self.read_cql_str(val_type), 
-                                            msg+" options (str)"));
-                            Ok(SchemaChangeOptions::Keyspace(option))
+                    match &*target {
+                        SCHEMA_CHANGE_TARGET_KEYSPACE => SchemaChangeOptions::Keyspace(keyspace),
+                        SCHEMA_CHANGE_TARGET_TABLE => {
+                            let table = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg+" table (str)"));
+                            SchemaChangeOptions::Table(keyspace,table)
+                        },
+                        SCHEMA_CHANGE_TARGET_TYPE => {
+                            let type_name = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg+" type (str)"));
+                            SchemaChangeOptions::Type(keyspace,type_name)
                         },
-                        Cow::Borrowed(SCHEMA_CHANGE_TARGET_TABLE) => {
-                            let option1 =   try_unwrap_op!(
-                                            try_rc!(self.read_cql_str(val_type), 
-                                            msg+" option1 (str)"));
-                            let option2 = try_rc!(self.read_cql_str(val_type), msg+" option2 (str)").unwrap();
-                            Ok(SchemaChangeOptions::Table(option1,option2))
+                        SCHEMA_CHANGE_TARGET_FUNCTION => {
+                            let name = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+" function (str)"));
+                            let args = try_rc!(self.read_cql_string_list(), msg+" function arguments (string list)");
+                            SchemaChangeOptions::Function(keyspace,name,args)
                         },
-                        Cow::Borrowed(SCHEMA_CHANGE_TARGET_TYPE) => {
-                            let option1 =   try_unwrap_op!(
-                                            try_rc!(self.read_cql_str(val_type), 
-                                            msg+" option1 (str)"));
-                            let option2 =   try_unwrap_op!(
-                                            try_rc!(self.read_cql_str(val_type), 
-                                            msg+" option2 (str)"));
-                            Ok(SchemaChangeOptions::Type(option1,option2))
+                        SCHEMA_CHANGE_TARGET_AGGREGATE => {
+                            let name = try_unwrap_op!(try_rc!(self.read_cql_str(val_type), msg.clone()+" aggregate (str)"));
+                            let args = try_rc!(self.read_cql_string_list(), msg+" aggregate arguments (string list)");
+                            SchemaChangeOptions::Aggregate(keyspace,name,args)
                         },
-                        _ => Err(RCError::new("Unknown schema change type: ", ReadError))
+                        _ => return Err(RCError::new(format!("Unknown schema change target: {}", target), ReadError))
                     };
 
-                Ok(CqlEvent::SchemaChange(  SchemaChangeType::from_str(
-                                            &change_type.unwrap().to_string()),
-                                            options.unwrap()))
+                Ok(CqlEvent::SchemaChange(SchemaChangeType::from_str(&change_type), options))
             },
             _=> Err(RCError::new("Unknown EventType", ReadError))
         }
@@ -299,36 +330,76 @@ impl<T: Read> CqlReader for T {
 
 
 
-    fn read_cql_set(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLSet>> {
-        try_bo!(self.read_i32::<BigEndian>(), "Error reading set size");
-        let len = self.read_cql_bytes_length(value_size).unwrap();
+    fn read_cql_set(&mut self, elem_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLSet>> {
+        let mut reader = match try_rc!(self.read_cql_collection_bytes(value_size), "Error reading set size") {
+            Some(reader) => reader,
+            None => return Ok(None)
+        };
+        let len = try_rc!(reader.read_cql_bytes_length(collection_size), "Error reading set length");
 
         let mut set: CQLSet = vec![];
         for _ in 0 .. len {
-            let col = try_rc!(self.read_cql_value_single(&col_meta.col_type_aux1, value_size), "Error reading set value");
+            let col = try_rc!(reader.read_cql_value(elem_type, collection_size, collection_size), "Error reading set value");
             set.push(col);
         }
         Ok(Some(set))
     }
 
-    fn read_cql_map(&mut self, col_meta: &CqlColMetadata, value_size: CqlBytesSize) -> RCResult<Option<CQLMap>> {
-        try_bo!(self.read_i32::<BigEndian>(), "Error reading map size");
-        let len = self.read_cql_bytes_length(value_size).unwrap();
+    fn read_cql_map(&mut self, key_type: &CqlColType, value_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLMap>> {
+        let mut reader = match try_rc!(self.read_cql_collection_bytes(value_size), "Error reading map size") {
+            Some(reader) => reader,
+            None => return Ok(None)
+        };
+        let len = try_rc!(reader.read_cql_bytes_length(collection_size), "Error reading map length");
 
         let mut map: CQLMap = vec![];
         for _ in 0 .. len {
-            let key = try_rc!(self.read_cql_value_single(&col_meta.col_type_aux1, value_size), "Error reading map key");
-            let value = try_rc!(self.read_cql_value_single(&col_meta.col_type_aux2, value_size), "Error reading map value");
+            let key = try_rc!(reader.read_cql_value(key_type, collection_size, collection_size), "Error reading map key");
+            let value = try_rc!(reader.read_cql_value(value_type, collection_size, collection_size), "Error reading map value");
             map.push(Pair { key: key, value: value});
         }
         Ok(Some(map))
     }
 
+    fn read_cql_tuple(&mut self, types: &Vec<CqlColType>, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<Option<CQLTuple>> {
+        let mut reader = match try_rc!(self.read_cql_collection_bytes(value_size), "Error reading tuple size") {
+            Some(reader) => reader,
+            None => return Ok(None)
+        };
+        let len = reader.get_ref().len() as u64;
+
+        // Components are always [bytes], and a tuple may hold fewer components than declared
+        let mut tuple: CQLTuple = vec![];
+        for col_type in types.iter() {
+            if reader.position() >= len {
+                break;
+            }
+            let col = try_rc!(reader.read_cql_value(col_type, CqlBytesSize::Cqli32, collection_size), "Error reading tuple value");
+            tuple.push(col);
+        }
+        Ok(Some(tuple))
+    }
+
+    // Reads the whole value of a collection or tuple, so nested values
+    // can't go past its boundaries. None when the value is null.
+    fn read_cql_collection_bytes(&mut self, value_size: CqlBytesSize) -> RCResult<Option<Cursor<Vec<u8>>>> {
+        let len = try_rc!(self.read_cql_bytes_length(value_size), "Error reading collection size");
+        if len < 0 {
+            return Ok(None);
+        }
+        let buf = try_rc!(self.read_cql_bytes_exact(len as usize), "Error reading collection data");
+        Ok(Some(Cursor::new(buf)))
+    }
+
     fn read_cql_skip(&mut self, val_type: CqlBytesSize) -> RCResult<()> {
         try_rc!(self.read_cql_bytes_with_length(val_type), "Error reading value data");
         Ok(())     
     }
 
+    fn read_cql_col_type(&mut self) -> RCResult<CqlColType> {
+        read_col_type(self, 0)
+    }
+
     fn read_cql_metadata(&mut self) -> RCResult<CqlMetadata> {
         let flags = try_bo!(self.read_u32::<BigEndian>(), "Error reading flags");
         let column_count = try_bo!(self.read_u32::<BigEndian>(), "Error reading column count");
@@ -353,39 +424,12 @@ impl<T: Read> CqlReader for T {
             };
 
             let col_name = try_rc_noption!(self.read_cql_str(CqlBytesSize::Cqli16), "Error reading column name");
-            let type_key = try_bo!(self.read_u16::<BigEndian>(), "Error reading type key");
-
-            let custom_type = 
-                if type_key == CqlValueType::ColumnCustom as u16{
-                    let custom_type = try_rc_noption!(self.read_cql_str(CqlBytesSize::Cqli16), "Error reading custom type");
-                    Some(custom_type)
-                }
-                else{
-                    None
-                };
-
-            let type_aux1 =
-            if type_key >= CqlValueType::ColumnList as u16 {
-                let ctype = try_bo!(self.read_u16::<BigEndian>(), "Error reading list/set/map type");
-                cql_column_type(ctype)
-            } else {
-                CqlValueType::ColumnUnknown
-            };
-            let type_aux2 =
-            if type_key == CqlValueType::ColumnMap as u16 {
-                let ctype = try_bo!(self.read_u16::<BigEndian>(), "Error reading map type value");
-                cql_column_type(ctype)
-            } else {
-                CqlValueType::ColumnUnknown
-            };
+            let col_type = try_rc!(self.read_cql_col_type(), "Error reading column type");
             let cql_col_metadata = CqlColMetadata {
                 keyspace: keyspace,
                 table: table,
                 col_name: col_name,
-                custom_type: custom_type,
-                col_type: cql_column_type(type_key),
-                col_type_aux1: type_aux1,
-                col_type_aux2: type_aux2
+                col_type: col_type
             };
             row_metadata.push(cql_col_metadata);
         }
@@ -399,12 +443,19 @@ impl<T: Read> CqlReader for T {
         })
     }
 
-    fn read_cql_value(&mut self, col_meta: &CqlColMetadata, collection_size: CqlBytesSize) -> RCResult<CqlValue> {
-        match col_meta.col_type {
-            ColumnList => { Ok(CqlList(try_rc!(self.read_cql_list(col_meta, collection_size), "Error reading column value (list)"))) },
-            ColumnMap => { Ok(CqlMap(try_rc!(self.read_cql_map(col_meta, collection_size), "Error reading column value (map)"))) },
-            ColumnSet => { Ok(CqlSet(try_rc!(self.read_cql_set(col_meta, collection_size), "Error reading column value (set)"))) },
-            _ => self.read_cql_value_single(&col_meta.col_type, CqlBytesSize::Cqli32)
+    fn read_cql_value(&mut self, col_type: &CqlColType, value_size: CqlBytesSize, collection_size: CqlBytesSize) -> RCResult<CqlValue> {
+        let params = &col_type.params;
+        match col_type.kind {
+            ColumnList if params.len() == 1 => { Ok(CqlList(try_rc!(self.read_cql_list(&params[0], value_size, collection_size), "Error reading column value (list)"))) },
+            ColumnMap if params.len() == 2 => { Ok(CqlMap(try_rc!(self.read_cql_map(&params[0], &params[1], value_size, collection_size), "Error reading column value (map)"))) },
+            ColumnSet if params.len() == 1 => { Ok(CqlSet(try_rc!(self.read_cql_set(&params[0], value_size, collection_size), "Error reading column value (set)"))) },
+            ColumnTuple => { Ok(CqlTuple(try_rc!(self.read_cql_tuple(params, value_size, collection_size), "Error reading column value (tuple)"))) },
+            // A UDT value is encoded like a tuple of its fields
+            ColumnUdt if params.len() == col_type.fields.len() => {
+                let values = try_rc!(self.read_cql_tuple(params, value_size, collection_size), "Error reading column value (udt)");
+                Ok(CqlUdt(values.map(|values| col_type.fields.iter().cloned().zip(values.into_iter()).collect())))
+            },
+            _ => self.read_cql_value_single(&col_type.kind, value_size)
         }
     }
 
@@ -440,7 +491,8 @@ impl<T: Read> CqlReader for T {
             },
             ColumnTimeUuid => Ok(CqlTimeUuid(try_rc!(self.read_cql_uuid(val_type), "Error reading column value (timeuuid)"))),
             ColumnInet => Ok(CqlInet(try_rc!(self.read_cql_inet_no_port(val_type), "Error reading column value (inet)"))),
-            CqlValueType::ColumnUnknown => panic!("Unknown column type !"),
+            // Types this library doesn't know yet (sent by newer servers) are kept as raw bytes
+            CqlValueType::ColumnUnknown => Ok(CqlRaw(Some(try_rc!(self.read_cql_bytes_with_length(val_type), "Error reading column value (unknown)")))),
             _ => Err(RCError::new("Trying to read a non-single value type", ReadError))
         }
     }
@@ -482,13 +534,20 @@ impl<T: Read> CqlReader for T {
 
 
     fn read_cql_rows(&mut self, collection_size: CqlBytesSize) -> RCResult<CqlRows> {
-        let metadata = try_rc!(self.read_cql_metadata(), "Error reading metadata");
+        let metadata = Arc::new(try_rc!(self.read_cql_metadata(), "Error reading metadata"));
         let rows_count = try_bo!(self.read_u32::<BigEndian>(), "Error reading metadata");
         let mut rows:Vec<CqlRow> = vec![];
+        // Rows without columns take no bytes, don't trust their count
+        if metadata.row_metadata.is_empty() {
+            return Ok(CqlRows {
+                metadata: metadata,
+                rows: rows,
+            })
+        }
         for _ in 0u32..rows_count {
-            let mut row = CqlRow{ cols: vec![] };
+            let mut row = CqlRow{ cols: vec![], metadata: metadata.clone() };
             for meta in metadata.row_metadata.iter() {
-                let col = try_rc!(self.read_cql_value(meta, collection_size), "Error reading column value");
+                let col = try_rc!(self.read_cql_value(&meta.col_type, CqlBytesSize::Cqli32, collection_size), "Error reading column value");
                 row.cols.push(col);
             }
             rows.push(row);
@@ -503,7 +562,7 @@ impl<T: Read> CqlReader for T {
     fn read_cql_frame_header(&mut self, version: u8) -> RCResult<CqlFrameHeader> {
         if version >= 3 {
             let mut header_data = [0; 5];
-            try_rc!(self.take(5).read(&mut header_data), "Error reading response header");
+            try_io!(self.read_exact(&mut header_data), "Error reading response header");
            
             let version_header = header_data[0];
             let flags = header_data[1];
@@ -517,7 +576,7 @@ impl<T: Read> CqlReader for T {
             })
         } else {
             let mut header_data = [0; 4];
-            try_rc!(self.take(4).read(&mut header_data), "Error reading response header");
+            try_io!(self.read_exact(&mut header_data), "Error reading response header");
            
             let version_header = header_data[0];
             let flags = header_data[1];
@@ -533,9 +592,16 @@ impl<T: Read> CqlReader for T {
     }
 
     fn read_cql_response(&mut self, version: u8) -> RCResult<CqlResponse> {
+        self.read_cql_response_compressed(version, Compression::None)
+    }
+
+    fn read_cql_response_compressed(&mut self, version: u8, compression: Compression) -> RCResult<CqlResponse> {
         let header = try_rc!(self.read_cql_frame_header(version), "Error reading CQL frame header");
 
-        let body_data = try_rc!(self.read_cql_bytes_with_length(CqlBytesSize::Cqli32), "Error reading body response");
+        let mut body_data = try_rc!(self.read_cql_bytes_with_length(CqlBytesSize::Cqli32), "Error reading body response");
+        if header.flags & CQL_FLAG_COMPRESSION != 0 {
+            body_data = try!(compression::decompress(compression, &body_data));
+        }
         // Code to debug response result. It writes the response's body to a file for inspecting.
         //let path = Path::new("body_data.bin");
         //let display = path.display();
@@ -556,6 +622,13 @@ impl<T: Read> CqlReader for T {
         //
 
         let mut reader = std::io::BufReader::new(Cursor::new(body_data));
+        // Traced requests get the tracing session id before the body
+        let tracing_id = if header.flags & CQL_FLAG_TRACING != 0 {
+            let bytes = try_rc!(reader.read_cql_bytes_exact(16), "Error reading tracing id");
+            Uuid::from_bytes(&bytes)
+        } else {
+            None
+        };
         let opcode = opcode_response(header.opcode);
         let body = match opcode {
             OpcodeReady => ResponseReady,
@@ -607,8 +680,11 @@ impl<T: Read> CqlReader for T {
             OpcodeAuthSuccess => {
                 ResponseAuthSuccess(try_rc!(reader.read_cql_bytes_with_length(CqlBytesSize::Cqli16), "Error reading ResponseAuthSuccess"))
             }
+            OpcodeSupported => {
+                ResponseSupported(ServerCapabilities::new(try_rc!(reader.read_cql_string_multimap(), "Error reading ResponseSupported")))
+            }
             OpcodeEvent => {
-                ResponseEvent(try_rc!(reader.read_cql_event(CqlBytesSize::Cqli16), "Error reading ResponseEvent"))
+                ResponseEvent(try_rc!(reader.read_cql_event(CqlBytesSize::Cqli16, version), "Error reading ResponseEvent"))
             }
             _ => {
                 ResultUnknown
@@ -621,13 +697,50 @@ impl<T: Read> CqlReader for T {
             stream: header.stream,
             opcode: opcode,
             body: body,
+            tracing_id: tracing_id,
         })
     }
 
 }
 
+// Nested types are read recursively, so a malformed frame could go deep enough
+// to overflow the stack
+const CQL_MAX_TYPE_DEPTH: usize = 64;
 
-
-
-
-
+fn read_col_type<R: Read>(reader: &mut R, depth: usize) -> RCResult<CqlColType> {
+    if depth > CQL_MAX_TYPE_DEPTH {
+        return Err(RCError::new("Error reading column type, too many nested types", ReadError));
+    }
+    let type_key = try_bo!(reader.read_u16::<BigEndian>(), "Error reading type key");
+    let mut col_type = CqlColType::new(cql_column_type(type_key));
+    match col_type.kind {
+        ColumnCustom => {
+            col_type.custom = Some(try_rc_noption!(reader.read_cql_str(CqlBytesSize::Cqli16), "Error reading custom type"));
+        },
+        ColumnList | ColumnSet => {
+            col_type.params.push(try_rc!(read_col_type(reader, depth + 1), "Error reading list/set type"));
+        },
+        ColumnMap => {
+            col_type.params.push(try_rc!(read_col_type(reader, depth + 1), "Error reading map type key"));
+            col_type.params.push(try_rc!(read_col_type(reader, depth + 1), "Error reading map type value"));
+        },
+        ColumnUdt => {
+            let ks = try_rc_noption!(reader.read_cql_str(CqlBytesSize::Cqli16), "Error reading udt keyspace");
+            let name = try_rc_noption!(reader.read_cql_str(CqlBytesSize::Cqli16), "Error reading udt name");
+            col_type.custom = Some(Cow::Owned(format!("{}.{}", ks, name)));
+            let count = try_bo!(reader.read_u16::<BigEndian>(), "Error reading udt fields count");
+            for _ in 0 .. count {
+                col_type.fields.push(try_rc_noption!(reader.read_cql_str(CqlBytesSize::Cqli16), "Error reading udt field name"));
+                col_type.params.push(try_rc!(read_col_type(reader, depth + 1), "Error reading udt field type"));
+            }
+        },
+        ColumnTuple => {
+            let count = try_bo!(reader.read_u16::<BigEndian>(), "Error reading tuple types count");
+            for _ in 0 .. count {
+                col_type.params.push(try_rc!(read_col_type(reader, depth + 1), "Error reading tuple type"));
+            }
+        },
+        _ => ()
+    }
+    Ok(col_type)
+}
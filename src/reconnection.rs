@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+// How long to wait before each attempt to reconnect to a node that went down.
+// `attempt` starts at 0, None gives up.
+pub trait ReconnectionPolicy: Send + Sync {
+    fn next_delay(&self, attempt: u32) -> Option<Duration>;
+}
+
+pub struct ConstantReconnectionPolicy {
+    delay: Duration,
+    max_attempts: Option<u32>
+}
+
+impl ConstantReconnectionPolicy {
+    pub fn new(delay: Duration, max_attempts: Option<u32>) -> ConstantReconnectionPolicy {
+        ConstantReconnectionPolicy {
+            delay: delay,
+            max_attempts: max_attempts
+        }
+    }
+}
+
+impl ReconnectionPolicy for ConstantReconnectionPolicy {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        match self.max_attempts {
+            Some(max) if attempt >= max => None,
+            _ => Some(self.delay)
+        }
+    }
+}
+
+// Doubles the delay on every attempt, from base_delay up to max_delay, and never gives up
+pub struct ExponentialReconnectionPolicy {
+    base_delay: Duration,
+    max_delay: Duration
+}
+
+impl ExponentialReconnectionPolicy {
+    pub fn new(base_delay: Duration, max_delay: Duration) -> ExponentialReconnectionPolicy {
+        ExponentialReconnectionPolicy {
+            base_delay: base_delay,
+            max_delay: max_delay
+        }
+    }
+}
+
+impl ReconnectionPolicy for ExponentialReconnectionPolicy {
+    fn next_delay(&self, attempt: u32) -> Option<Duration> {
+        // Past 2^31 the delay is way over any sensible maximum
+        let factor = 1u32.checked_shl(attempt.min(31)).unwrap_or(u32::max_value());
+        let delay = self.base_delay.checked_mul(factor).unwrap_or(self.max_delay);
+        Some(if delay > self.max_delay { self.max_delay } else { delay })
+    }
+}
@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// What happens to a request once the in-flight limits are reached
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OverloadPolicy {
+    // Queue it until a slot is free, without bound
+    Wait,
+    // Fail it right away with an OverloadedError
+    Fail,
+    // Queue it while there are fewer than this many queued requests, fail it otherwise
+    Queue(usize),
+}
+
+#[derive(Debug, Clone)]
+pub struct RequestLimits {
+    pub max_in_flight_per_host: usize,
+    pub max_in_flight: usize,
+    pub policy: OverloadPolicy,
+}
+
+impl RequestLimits {
+    pub fn new() -> RequestLimits {
+        RequestLimits {
+            // Connections are also limited by their stream ids
+            max_in_flight_per_host: 32768,
+            max_in_flight: usize::max_value(),
+            policy: OverloadPolicy::Queue(65_536),
+        }
+    }
+
+    pub fn max_in_flight_per_host(mut self, max: usize) -> RequestLimits {
+        self.max_in_flight_per_host = max;
+        self
+    }
+
+    pub fn max_in_flight(mut self, max: usize) -> RequestLimits {
+        self.max_in_flight = max;
+        self
+    }
+
+    pub fn policy(mut self, policy: OverloadPolicy) -> RequestLimits {
+        self.policy = policy;
+        self
+    }
+}
+
+// Updated by the connection pool, readable from any thread
+#[derive(Debug)]
+pub struct RequestStats {
+    in_flight: AtomicUsize,
+    queued: AtomicUsize,
+}
+
+impl RequestStats {
+    pub fn new() -> RequestStats {
+        RequestStats {
+            in_flight: AtomicUsize::new(0),
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    // Requests holding a stream id, sent or about to be
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    // Requests waiting for the in-flight count to go down
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn set(&self, in_flight: usize, queued: usize) {
+        self.in_flight.store(in_flight, Ordering::Relaxed);
+        self.queued.store(queued, Ordering::Relaxed);
+    }
+}
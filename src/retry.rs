@@ -0,0 +1,52 @@
+use def::*;
+use error::RCError;
+use statement::Statement;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RetryDecision {
+    Retry,
+    Rethrow
+}
+
+// Decides whether a failed statement is executed again. `attempt` counts the
+// retries already done, starting at 0.
+pub trait RetryPolicy: Send + Sync {
+    // The node answered with an ERROR, `code` is one of CQL_ERROR_*
+    fn on_server_error(&self, statement: &Statement, code: u32, attempt: u32) -> RetryDecision;
+    // The request failed before getting an answer (connection lost, overloaded client...)
+    fn on_client_error(&self, statement: &Statement, error: &RCError, attempt: u32) -> RetryDecision;
+}
+
+// Retries once the errors that are likely to go away, writes only if the statement is
+// idempotent since they may have been applied
+pub struct DefaultRetryPolicy;
+
+impl RetryPolicy for DefaultRetryPolicy {
+    fn on_server_error(&self, statement: &Statement, code: u32, attempt: u32) -> RetryDecision {
+        if attempt > 0 {
+            return RetryDecision::Rethrow;
+        }
+        match code {
+            CQL_ERROR_READ_TIMEOUT | CQL_ERROR_UNAVAILABLE | CQL_ERROR_OVERLOADED | CQL_ERROR_IS_BOOTSTRAPPING => RetryDecision::Retry,
+            CQL_ERROR_WRITE_TIMEOUT if statement.idempotent => RetryDecision::Retry,
+            _ => RetryDecision::Rethrow
+        }
+    }
+
+    fn on_client_error(&self, statement: &Statement, _: &RCError, attempt: u32) -> RetryDecision {
+        if attempt == 0 && statement.idempotent { RetryDecision::Retry } else { RetryDecision::Rethrow }
+    }
+}
+
+// Never retries, errors go straight to the caller
+pub struct FallthroughRetryPolicy;
+
+impl RetryPolicy for FallthroughRetryPolicy {
+    fn on_server_error(&self, _: &Statement, _: u32, _: u32) -> RetryDecision {
+        RetryDecision::Rethrow
+    }
+
+    fn on_client_error(&self, _: &Statement, _: &RCError, _: u32) -> RetryDecision {
+        RetryDecision::Rethrow
+    }
+}
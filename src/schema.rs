@@ -0,0 +1,99 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use def::*;
+use error::*;
+
+// Names of what a keyspace holds. Functions and aggregates are kept as name(argument types)
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyspaceMetadata {
+    pub name: String,
+    pub tables: BTreeSet<String>,
+    pub types: BTreeSet<String>,
+    pub functions: BTreeSet<String>,
+    pub aggregates: BTreeSet<String>
+}
+
+impl KeyspaceMetadata {
+    pub fn new(name: &str) -> KeyspaceMetadata {
+        KeyspaceMetadata {
+            name: name.to_string(),
+            tables: BTreeSet::new(),
+            types: BTreeSet::new(),
+            functions: BTreeSet::new(),
+            aggregates: BTreeSet::new()
+        }
+    }
+}
+
+// Schema of the cluster, loaded by the control connection and kept up to date with SCHEMA_CHANGE events
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaMetadata {
+    pub keyspaces: BTreeMap<String, KeyspaceMetadata>,
+    // The last refresh couldn't read it, it may miss changes until a refresh succeeds
+    pub stale: bool
+}
+
+impl SchemaMetadata {
+    pub fn new() -> SchemaMetadata {
+        SchemaMetadata {
+            keyspaces: BTreeMap::new(),
+            stale: false
+        }
+    }
+
+    // Rows of (keyspace_name), (keyspace_name, table_name) and (keyspace_name, type_name)
+    pub fn from_rows(keyspaces: &CqlRows, tables: &CqlRows, types: &CqlRows) -> RCResult<SchemaMetadata> {
+        let mut schema = SchemaMetadata::new();
+        for row in keyspaces.rows.iter() {
+            let name: String = try!(row.get(0));
+            schema.keyspace_mut(&name);
+        }
+        for row in tables.rows.iter() {
+            let keyspace: String = try!(row.get(0));
+            schema.keyspace_mut(&keyspace).tables.insert(try!(row.get(1)));
+        }
+        for row in types.rows.iter() {
+            let keyspace: String = try!(row.get(0));
+            schema.keyspace_mut(&keyspace).types.insert(try!(row.get(1)));
+        }
+        Ok(schema)
+    }
+
+    pub fn keyspace(&self, name: &str) -> Option<&KeyspaceMetadata> {
+        self.keyspaces.get(name)
+    }
+
+    fn keyspace_mut(&mut self, name: &str) -> &mut KeyspaceMetadata {
+        self.keyspaces.entry(name.to_string()).or_insert_with(|| KeyspaceMetadata::new(name))
+    }
+
+    pub fn apply(&mut self, change: SchemaChangeType, options: &SchemaChangeOptions) {
+        let dropped = change == SchemaChangeType::Dropped;
+        if let SchemaChangeOptions::Keyspace(ref keyspace) = *options {
+            if dropped {
+                self.keyspaces.remove(&keyspace.to_string());
+            } else {
+                self.keyspace_mut(keyspace);
+            }
+            return;
+        }
+        let keyspace = self.keyspace_mut(options.keyspace());
+        let (names, name) = match *options {
+            SchemaChangeOptions::Table(_, ref table) => (&mut keyspace.tables, table.to_string()),
+            SchemaChangeOptions::Type(_, ref type_name) => (&mut keyspace.types, type_name.to_string()),
+            SchemaChangeOptions::Function(_, ref name, ref args) => (&mut keyspace.functions, signature(name, args)),
+            SchemaChangeOptions::Aggregate(_, ref name, ref args) => (&mut keyspace.aggregates, signature(name, args)),
+            SchemaChangeOptions::Keyspace(_) => return
+        };
+        if dropped {
+            names.remove(&name);
+        } else {
+            names.insert(name);
+        }
+    }
+}
+
+fn signature(name: &str, args: &Vec<CowStr>) -> String {
+    let args: Vec<&str> = args.iter().map(|arg| &**arg).collect();
+    format!("{}({})", name, args.join(","))
+}
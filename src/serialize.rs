@@ -13,6 +13,7 @@ use error::RCErrorType::*;
 use def::Query::*;
 use def::CqlValue::*;
 use error::*;
+use compression;
 
 pub trait CqlSerializable {
     fn len(& self, version: u8) -> usize;
@@ -25,7 +26,7 @@ macro_rules! write_size(
         match $bytes_size {
             Cqli16 => try_bo!($buf.write_i16::<BigEndian>($size as i16), "Error serializing CqlValue (length of [short bytes])"),
             Cqli32 => try_bo!($buf.write_i32::<BigEndian>($size as i32), "Error serializing CqlValue (length of [bytes])"),
-            _ => unimplemented!()
+            Cqli8 => try_bo!($buf.write_u8($size as u8), "Error serializing CqlValue (length of [byte])")
         }
     }
 );
@@ -84,6 +85,28 @@ fn serialize_header<T: std::io::Write>(buf: &mut T, version: &u8, flags: &u8, st
     Ok(())
 }
 
+impl CqlRequest {
+    // Serializes the request compressing its body. STARTUP is never compressed since it
+    // is the message that negotiates the algorithm, and empty bodies are left alone.
+    pub fn serialize_compressed<T: std::io::Write>(&self, buf: &mut T, version: u8, compression: Compression) -> RCResult<()> {
+        if compression == Compression::None || self.opcode == OpcodeRequest::OpcodeStartup {
+            return self.serialize(buf, version);
+        }
+        let mut frame = Vec::with_capacity(self.len(version) + 1);
+        try_rc!(self.serialize(&mut frame, version), "Error serializing CqlRequest");
+        let header_len = if version >= 3 { 9 } else { 8 };
+        if frame.len() <= header_len {
+            try_io!(buf.write(&frame), "Error serializing CqlRequest (body)");
+            return Ok(());
+        }
+        let body = try!(compression::compress(compression, &frame[header_len..]));
+        try_rc!(serialize_header(buf, &version, &(self.flags | CQL_FLAG_COMPRESSION), &self.stream, &(self.opcode as u8), &(body.len() as u32)),
+            "Error serializing CqlRequest (header)");
+        try_io!(buf.write(&body), "Error serializing CqlRequest (body)");
+        Ok(())
+    }
+}
+
 impl CqlSerializable for CqlRequest {
     fn serialize_size<T: std::io::Write>(& self, buf: &mut T, bytes_size: CqlBytesSize, version: u8) -> RCResult<()> {
         Err(RCError::new("Cannot serialize Request without Client context", WriteError))
@@ -94,48 +117,53 @@ impl CqlSerializable for CqlRequest {
         let ocode = self.opcode as u8;
         serialize_header(buf, &version, &self.flags, &self.stream, &ocode, &len);
         match self.body {
-            RequestExec(ref preps, ref params, ref cons, flags) => {
+            RequestExec(ref preps, ref params) => {
                 try_bo!(buf.write_i16::<BigEndian>(preps.len() as i16), "Error serializing EXEC request (id length)");
                 try_io!(buf.write(&preps), "Error serializing EXEC request (id)");
                 if version >= 2 {
-                    try_bo!(buf.write_u16::<BigEndian>(*cons as u16), "Error serializing CqlRequest (query consistency)");
-                    try_bo!(buf.write_u8(flags), "Error serializing CqlRequest (query flags)");
-
-                    try_bo!(buf.write_i16::<BigEndian>(params.len() as i16), "Error serializing EXEC request (params length)");                
-                    for v in params.iter() {
-                        v.serialize_size(buf, Cqli32, version);
-                    }
+                    params.serialize(buf, version)
                 } else {
-                    try_bo!(buf.write_i16::<BigEndian>(params.len() as i16), "Error serializing EXEC request (params length)");                
-                    for v in params.iter() {
-                        v.serialize_size(buf, Cqli32, version);
+                    try_bo!(buf.write_i16::<BigEndian>(params.values.len() as i16), "Error serializing EXEC request (params length)");
+                    for v in params.values.iter() {
+                        try_rc!(v.serialize_size(buf, Cqli32, version), "Error serializing EXEC request (param)");
                     }
-                    try_bo!(buf.write_u16::<BigEndian>(*cons as u16), "Error serializing CqlRequest (query consistency)");
+                    try_bo!(buf.write_u16::<BigEndian>(params.consistency as u16), "Error serializing CqlRequest (query consistency)");
+                    Ok(())
                 }
-                Ok(())
             },
-            RequestBatch(ref q_vec, ref r_type, ref con, flags) => {
+            RequestBatch(ref q_vec, ref r_type, ref params) => {
                 try_bo!(buf.write_u8(*r_type as u8), "Error serializing BATCH request (request type)");
                 try_bo!(buf.write_u16::<BigEndian>(q_vec.len() as u16), "Error serializing BATCH request (number of requests)");
-                q_vec.iter().all(|r| { r.serialize(buf, version); true });
-                try_bo!(buf.write_u16::<BigEndian>(*con as u16), "Error serializing BATCH request (consistency)");
+                for q in q_vec.iter() {
+                    try_rc!(q.serialize(buf, version), "Error serializing BATCH request (query)");
+                }
+                try_bo!(buf.write_u16::<BigEndian>(params.consistency as u16), "Error serializing BATCH request (consistency)");
                 if version >= 3 {
-                    try_bo!(buf.write_u8(0 as u8), "Error serializing BATCH request (flags)");
+                    let flags = batch_flags(params);
+                    try_bo!(buf.write_u8(flags), "Error serializing BATCH request (flags)");
+                    if let Some(serial_consistency) = params.serial_consistency {
+                        try_bo!(buf.write_u16::<BigEndian>(serial_consistency as u16), "Error serializing BATCH request (serial consistency)");
+                    }
+                    if let Some(timestamp) = params.default_timestamp {
+                        try_bo!(buf.write_i64::<BigEndian>(timestamp), "Error serializing BATCH request (default timestamp)");
+                    }
                 }
                 Ok(())
             },
             RequestStartup(ref map) => {
                 map.serialize(buf, version)
             },
-            RequestQuery(ref query_str, ref consistency, flags) => {
+            RequestQuery(ref query_str, ref params) => {
                 let len_str = query_str.len() as u32;
                 try_bo!(buf.write_u32::<BigEndian>(len_str), "Error serializing CqlRequest (query length)");
                 try_io!(buf.write(query_str.as_bytes()), "Error serializing CqlRequest (query)");
-                try_bo!(buf.write_u16::<BigEndian>(*consistency as u16), "Error serializing CqlRequest (query consistency)");
                 if version >= 2 {
-                    try_bo!(buf.write_u8(flags), "Error serializing CqlRequest (query flags)");
+                    params.serialize(buf, version)
+                } else {
+                    // v1 has no values nor flags
+                    try_bo!(buf.write_u16::<BigEndian>(params.consistency as u16), "Error serializing CqlRequest (query consistency)");
+                    Ok(())
                 }
-                Ok(())
             },
             RequestPrepare(ref query_str) => {
                 let len_str = query_str.len() as u32;
@@ -164,20 +192,25 @@ impl CqlSerializable for CqlRequest {
     fn len(&self, version: u8) -> usize {
         8 + match self.body {
             RequestStartup(ref map) => map.len(version),
-            RequestQuery(ref query_str, _, _) => {
-                let final_bytes = if version >= 2 { 3 } else { 2 };
-                4 + query_str.len() + final_bytes
+            RequestQuery(ref query_str, ref params) => {
+                let params_size = if version >= 2 { params.len(version) } else { 2 };
+                4 + query_str.len() + params_size
             },
             RequestPrepare(ref query_str) => 4 + query_str.len(),
-            RequestExec(ref preps, ref values, _, _) => {
-                let final_bytes = if version >= 2 { 3 } else { 2 };
-                let values_size:usize = values.iter().fold(0, |a, ref b| a + 4 + b.len(version));
-                2 + preps.len() as usize + 2 +  values_size + final_bytes as usize
+            RequestExec(ref preps, ref params) => {
+                let params_size = if version >= 2 {
+                    params.len(version)
+                } else {
+                    2 + params.values.iter().fold(0, |a, ref b| a + 4 + b.len(version)) + 2
+                };
+                2 + preps.len() as usize + params_size
             },
-            RequestBatch(ref q_vec, ref r_type, ref con, flags) => {
+            RequestBatch(ref q_vec, _, ref params) => {
                 let q_vec_size:usize = q_vec.iter().fold(0, |a, ref b| a + b.len(version));
                 if version >= 3 {
-                    3 + q_vec_size + 3
+                    let serial_size = if params.serial_consistency.is_some() { 2 } else { 0 };
+                    let timestamp_size = if params.default_timestamp.is_some() { 8 } else { 0 };
+                    3 + q_vec_size + 3 + serial_size + timestamp_size
                 } else {
                     3 + q_vec_size + 2
                 }
@@ -194,6 +227,94 @@ impl CqlSerializable for CqlRequest {
     }
 }
 
+// Flags of a QUERY or EXECUTE request, only the ones known by the protocol version are set
+pub fn query_flags(params: &CqlQueryParams, version: u8) -> u8 {
+    let mut flags = 0;
+    if !params.values.is_empty() {
+        flags |= CQL_QUERY_FLAG_VALUES;
+    }
+    if params.page_size.is_some() {
+        flags |= CQL_QUERY_FLAG_PAGE_SIZE;
+    }
+    if params.paging_state.is_some() {
+        flags |= CQL_QUERY_FLAG_PAGING_STATE;
+    }
+    if params.serial_consistency.is_some() {
+        flags |= CQL_QUERY_FLAG_SERIAL_CONSISTENCY;
+    }
+    if params.default_timestamp.is_some() && version >= 3 {
+        flags |= CQL_QUERY_FLAG_DEFAULT_TIMESTAMP;
+    }
+    flags
+}
+
+// Flags of a BATCH request (v3), batches have neither values nor paging
+pub fn batch_flags(params: &CqlQueryParams) -> u8 {
+    let mut flags = 0;
+    if params.serial_consistency.is_some() {
+        flags |= CQL_QUERY_FLAG_SERIAL_CONSISTENCY;
+    }
+    if params.default_timestamp.is_some() {
+        flags |= CQL_QUERY_FLAG_DEFAULT_TIMESTAMP;
+    }
+    flags
+}
+
+// <consistency><flags>[<n><value_1>...<value_n>][<result_page_size>][<paging_state>][<serial_consistency>][<timestamp>]
+// as sent from v2. v1 requests are written by CqlRequest.
+impl CqlSerializable for CqlQueryParams {
+    fn serialize_size<T: std::io::Write>(&self, buf: &mut T, bytes_size: CqlBytesSize, version: u8) -> RCResult<()> {
+        self.serialize(buf, version)
+    }
+
+    fn serialize<T: std::io::Write>(&self, buf: &mut T, version: u8) -> RCResult<()> {
+        let flags = query_flags(self, version);
+        try_bo!(buf.write_u16::<BigEndian>(self.consistency as u16), "Error serializing CqlRequest (query consistency)");
+        try_bo!(buf.write_u8(flags), "Error serializing CqlRequest (query flags)");
+        if flags & CQL_QUERY_FLAG_VALUES != 0 {
+            try_bo!(buf.write_i16::<BigEndian>(self.values.len() as i16), "Error serializing CqlRequest (values length)");
+            for v in self.values.iter() {
+                try_rc!(v.serialize_size(buf, Cqli32, version), "Error serializing CqlRequest (value)");
+            }
+        }
+        if let Some(page_size) = self.page_size {
+            try_bo!(buf.write_i32::<BigEndian>(page_size), "Error serializing CqlRequest (page size)");
+        }
+        if let Some(ref paging_state) = self.paging_state {
+            write_size!(buf, paging_state.len(), Cqli32);
+            try_io!(buf.write(paging_state), "Error serializing CqlRequest (paging state)");
+        }
+        if let Some(serial_consistency) = self.serial_consistency {
+            try_bo!(buf.write_u16::<BigEndian>(serial_consistency as u16), "Error serializing CqlRequest (serial consistency)");
+        }
+        if flags & CQL_QUERY_FLAG_DEFAULT_TIMESTAMP != 0 {
+            try_bo!(buf.write_i64::<BigEndian>(self.default_timestamp.unwrap_or(0)), "Error serializing CqlRequest (default timestamp)");
+        }
+        Ok(())
+    }
+
+    fn len(&self, version: u8) -> usize {
+        let flags = query_flags(self, version);
+        let mut len = 3;
+        if flags & CQL_QUERY_FLAG_VALUES != 0 {
+            len += 2 + self.values.iter().fold(0, |a, ref b| a + 4 + b.len(version));
+        }
+        if self.page_size.is_some() {
+            len += 4;
+        }
+        if let Some(ref paging_state) = self.paging_state {
+            len += 4 + paging_state.len();
+        }
+        if self.serial_consistency.is_some() {
+            len += 2;
+        }
+        if flags & CQL_QUERY_FLAG_DEFAULT_TIMESTAMP != 0 {
+            len += 8;
+        }
+        len
+    }
+}
+
 impl CqlSerializable for Query {
     fn serialize_size<T: std::io::Write>(& self, buf: &mut T, bytes_size: CqlBytesSize, version: u8) -> RCResult<()> {
         self.serialize(buf, version)
@@ -243,7 +364,7 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(s.as_bytes()), "Error serializing CqlValue (ascci)");
                     Ok(())
                 }
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlBigInt(ref o) => match *o {
                 Some(ref i) => {
@@ -251,7 +372,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_i64::<BigEndian>(*i), "Error serializing CqlValue (Bigint)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlBlob(ref o) => match *o {
                 Some(ref b) => {
@@ -259,7 +380,7 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(&b), "Error serializing CqlValue (Blob)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlBoolean(ref o) => match *o {
                 Some(ref b) => {
@@ -267,7 +388,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_u8(*b as u8), "Error serializing CqlValue (Boolean)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlCounter(ref o) => match *o {
                 Some(ref c) => {
@@ -275,7 +396,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_i64::<BigEndian>(*c), "Error serializing CqlValue (Counter)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlDecimal(_) => Err(RCError::new("Decimal seralization not implemented", SerializeError)),
             CqlDouble(ref o) => match *o {
@@ -284,7 +405,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_f64::<BigEndian>(*d), "Error serializing CqlValue (Double)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlFloat(ref o) => match *o {
                 Some(ref f) => {
@@ -292,7 +413,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_f32::<BigEndian>(*f), "Error serializing CqlValue (Float)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlInet(ref o) => match *o {
                 Some(ref ip) => match *ip {
@@ -311,7 +432,7 @@ impl CqlSerializable for CqlValue {
                         Ok(())
                     },
                 },
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlInt(ref o) => match *o {
                 Some(ref i) => {
@@ -319,46 +440,40 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_i32::<BigEndian>(*i), "Error serializing CqlValue (Int)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlList(ref o) => match *o {
                 Some(ref v) => {
-                    let len = v.len();
-                    if version >= 3 {
-                        try_bo!(buf.write_i32::<BigEndian>(len as i32), "Error serializing CqlValue (List length)")
-                    } else {
-                        try_bo!(buf.write_i16::<BigEndian>(len as i16), "Error serializing CqlValue (List length)")
+                    write_size!(buf, self.len(version), bytes_size);
+                    try_rc!(serialize_collection_length(buf, v.len(), version), "Error serializing CqlValue (List length)");
+                    for e in v.iter() {
+                        try_rc!(e.serialize_size(buf, collection_bytes_size(version), version), "Error serializing CqlValue (List element)");
                     }
-                    v.iter().map(|e| e.serialize_size(buf, Cqli16, version));
                     Ok(())
                 },
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlMap(ref o) => match *o {
                 Some(ref v) => {
-                    let len = v.len();
-                    if version >= 3 {
-                        try_bo!(buf.write_i32::<BigEndian>(len as i32), "Error serializing CqlValue (List length)")
-                    } else {
-                        try_bo!(buf.write_i16::<BigEndian>(len as i16), "Error serializing CqlValue (List length)")
+                    write_size!(buf, self.len(version), bytes_size);
+                    try_rc!(serialize_collection_length(buf, v.len(), version), "Error serializing CqlValue (Map length)");
+                    for e in v.iter() {
+                        try_rc!(e.serialize_size(buf, collection_bytes_size(version), version), "Error serializing CqlValue (Map element)");
                     }
-                    v.iter().map(|e| e.serialize_size(buf, Cqli16, version));
                     Ok(())
                 },
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlSet(ref o) => match *o {
                 Some(ref v) => {
-                    let len = v.len();
-                    if version >= 3 {
-                        try_bo!(buf.write_i32::<BigEndian>(len as i32), "Error serializing CqlValue (List length)")
-                    } else {
-                        try_bo!(buf.write_i16::<BigEndian>(len as i16), "Error serializing CqlValue (List length)")
+                    write_size!(buf, self.len(version), bytes_size);
+                    try_rc!(serialize_collection_length(buf, v.len(), version), "Error serializing CqlValue (Set length)");
+                    for e in v.iter() {
+                        try_rc!(e.serialize_size(buf, collection_bytes_size(version), version), "Error serializing CqlValue (Set element)");
                     }
-                    v.iter().map(|e| e.serialize_size(buf, Cqli16, version));
                     Ok(())
                 },
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlText(ref o) => match *o {
                 Some(ref s) => {
@@ -366,7 +481,7 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(s.as_bytes()), "Error serializing CqlValue (Text)");
                     Ok(())
                 }
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlTimestamp(ref o) => match *o {
                 Some(ref i) => {
@@ -374,7 +489,7 @@ impl CqlSerializable for CqlValue {
                     try_bo!(buf.write_u64::<BigEndian>(*i), "Error serializing CqlValue (Counter)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlUuid(ref o) => match *o {
                 Some(ref u) => {
@@ -382,7 +497,7 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(u.as_bytes()), "Error serializing CqlValue (Uuid)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
             },
             CqlTimeUuid(ref o) => match *o {
                 Some(ref u) => {
@@ -390,7 +505,29 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(u.as_bytes()), "Error serializing CqlValue (TimeUuid)");
                     Ok(())
                 }
-                None => Ok(())                
+                None => write_null(buf, bytes_size)
+            },
+            CqlTuple(ref o) => match *o {
+                Some(ref v) => {
+                    write_size!(buf, self.len(version), bytes_size);
+                    // Tuple components are always [bytes], whatever the protocol version
+                    for e in v.iter() {
+                        try_rc!(e.serialize_size(buf, Cqli32, version), "Error serializing CqlValue (Tuple element)");
+                    }
+                    Ok(())
+                },
+                None => write_null(buf, bytes_size)
+            },
+            CqlUdt(ref o) => match *o {
+                Some(ref v) => {
+                    write_size!(buf, self.len(version), bytes_size);
+                    // Fields are written in the order given, CqlPreparedStat::bind sorts them
+                    for &(_, ref e) in v.iter() {
+                        try_rc!(e.serialize_size(buf, Cqli32, version), "Error serializing CqlValue (Udt field)");
+                    }
+                    Ok(())
+                },
+                None => write_null(buf, bytes_size)
             },
             CqlVarchar(ref o) => match *o {
                 Some(ref s) => {
@@ -398,9 +535,17 @@ impl CqlSerializable for CqlValue {
                     try_io!(buf.write(s.as_bytes()), "Error serializing CqlValue (Varchar)");
                     Ok(())
                 }
-                None => Ok(())
+                None => write_null(buf, bytes_size)
             },
             CqlVarint(_) => Err(RCError::new("Varint seralization not implemented", SerializeError)),
+            CqlRaw(ref o) => match *o {
+                Some(ref b) => {
+                    write_size!(buf, b.len(), bytes_size);
+                    try_io!(buf.write(&b), "Error serializing CqlValue (Raw)");
+                    Ok(())
+                }
+                None => write_null(buf, bytes_size)
+            },
             _ => Err(RCError::new("Error serializing CqlValue (no", SerializeError))
         }
 
@@ -453,33 +598,16 @@ impl CqlSerializable for CqlValue {
                 None => 0     
             },
             &CqlList(ref o) => match *o {
-                Some(ref v) => {
-                    if v.len() == 0 { 0 }
-                    else {
-                        // Lists contain [short bytes] elements, hence the 2
-                        v.len() * (2 + v[0].len(version))
-                    }                   
-                },
+                Some(ref v) => collection_len(v.iter().map(|e| e.len(version)), version),
                 None => 0
             },
             &CqlMap(ref o) => match *o {
-                Some(ref v) => {
-                    if v.len() == 0 { 0 }
-                    else {
-                        // Maps contain [short bytes] elements, hence the 2
-                        v.len() * (2 + v[0].len(version))
-                    }                   
-                },
+                // Pairs already account for the size of both key and value
+                Some(ref v) => v.iter().fold(collection_size_len(version), |a, ref b| a + b.len(version)),
                 None => 0
             },
             &CqlSet(ref o) => match *o {
-                Some(ref v) => {
-                    if v.len() == 0 { 0 }
-                    else {
-                        // Sets contain [short bytes] elements, hence the 2
-                        v.len() * (2 + v[0].len(version))
-                    }                   
-                },
+                Some(ref v) => collection_len(v.iter().map(|e| e.len(version)), version),
                 None => 0
             },
             &CqlTimestamp(ref o) => match *o {
@@ -494,11 +622,23 @@ impl CqlSerializable for CqlValue {
                 Some(ref u) => u.as_bytes().len(),
                 None => 0     
             },
+            &CqlTuple(ref o) => match *o {
+                Some(ref v) => v.iter().fold(0, |a, ref b| a + 4 + b.len(version)),
+                None => 0
+            },
+            &CqlUdt(ref o) => match *o {
+                Some(ref v) => v.iter().fold(0, |a, &(_, ref b)| a + 4 + b.len(version)),
+                None => 0
+            },
             &CqlVarchar(ref o) => match *o {
                 Some(ref s) => s.len() as usize,
                 None => 0
             },
             &CqlVarint(_) => 0,
+            &CqlRaw(ref o) => match *o {
+                Some(ref b) => b.len() as usize,
+                None => 0
+            },
             _ => 0
         }
     }
@@ -508,8 +648,8 @@ impl CqlSerializable for CqlValue {
 
 impl< T:CqlSerializable, V:CqlSerializable> CqlSerializable for Pair<T, V> {
     fn serialize_size<S: std::io::Write>(& self, buf: &mut S, bytes_size: CqlBytesSize, version: u8) -> RCResult<()> {
-        self.key.serialize_size(buf, bytes_size, version);
-        self.value.serialize_size(buf, bytes_size, version);
+        try_rc!(self.key.serialize_size(buf, bytes_size, version), "Error serializing Pair (key)");
+        try_rc!(self.value.serialize_size(buf, bytes_size, version), "Error serializing Pair (value)");
         Ok(())
     }
 
@@ -517,8 +657,38 @@ impl< T:CqlSerializable, V:CqlSerializable> CqlSerializable for Pair<T, V> {
         self.serialize_size(buf, Cqli32, version)
     }
 
+    // Key and value lengths, each one preceded by its collection element size
     fn len(& self, version: u8) -> usize {
-        0
+        2 * collection_size_len(version) + self.key.len(version) + self.value.len(version)
     }
 }
 
+// Null values are sent as a negative length with no bytes
+fn write_null<T: std::io::Write>(buf: &mut T, bytes_size: CqlBytesSize) -> RCResult<()> {
+    write_size!(buf, -1i32, bytes_size);
+    Ok(())
+}
+
+// Collection elements are [bytes] since v3 and [short bytes] before
+fn collection_bytes_size(version: u8) -> CqlBytesSize {
+    if version >= 3 { Cqli32 } else { Cqli16 }
+}
+
+fn serialize_collection_length<T: std::io::Write>(buf: &mut T, len: usize, version: u8) -> RCResult<()> {
+    if version >= 3 {
+        try_bo!(buf.write_i32::<BigEndian>(len as i32), "Error serializing collection length");
+    } else {
+        try_bo!(buf.write_i16::<BigEndian>(len as i16), "Error serializing collection length");
+    }
+    Ok(())
+}
+
+// Bytes taken by the collection length and by each element length
+fn collection_size_len(version: u8) -> usize {
+    if version >= 3 { 4 } else { 2 }
+}
+
+fn collection_len<I: Iterator<Item=usize>>(elements: I, version: u8) -> usize {
+    elements.fold(collection_size_len(version), |a, b| a + collection_size_len(version) + b)
+}
+
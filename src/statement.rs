@@ -0,0 +1,126 @@
+use def::*;
+use convert::ToCqlValues;
+
+#[derive(Debug, Clone)]
+pub enum StatementQuery {
+    Query(String),
+    Prepared(CqlPreparedStat)
+}
+
+// A query or prepared statement with the options to execute it:
+//
+//     let statement = Statement::prepared(&preps)
+//                         .values(&(7, "abc"))
+//                         .consistency(Consistency::Quorum)
+//                         .page_size(100);
+//     cluster.execute(statement);
+#[derive(Debug, Clone)]
+pub struct Statement {
+    pub query: StatementQuery,
+    pub params: CqlQueryParams,
+    // Whether it is safe to execute it more than once
+    pub idempotent: bool,
+    pub tracing: bool,
+    // Partition key of the statement, to choose the node to send it to
+    pub routing_key: Option<Vec<u8>>,
+    // Otherwise the cluster's default consistency is used
+    consistency_set: bool
+}
+
+impl Statement {
+    pub fn query(query_str: &str) -> Statement {
+        Statement::new(StatementQuery::Query(query_str.to_string()))
+    }
+
+    // Values are checked against the bind markers of the statement when it is executed
+    pub fn prepared(preps: &CqlPreparedStat) -> Statement {
+        Statement::new(StatementQuery::Prepared(preps.clone()))
+    }
+
+    fn new(query: StatementQuery) -> Statement {
+        Statement {
+            query: query,
+            params: CqlQueryParams::new(Consistency::One),
+            idempotent: false,
+            tracing: false,
+            routing_key: None,
+            consistency_set: false
+        }
+    }
+
+    pub fn values<V: ToCqlValues + ?Sized>(mut self, values: &V) -> Statement {
+        self.params.values = values.to_cql_values();
+        self
+    }
+
+    pub fn consistency(mut self, consistency: Consistency) -> Statement {
+        self.params.consistency = consistency;
+        self.consistency_set = true;
+        self
+    }
+
+    pub fn serial_consistency(mut self, consistency: Consistency) -> Statement {
+        self.params.serial_consistency = Some(consistency);
+        self
+    }
+
+    pub fn page_size(mut self, page_size: i32) -> Statement {
+        self.params.page_size = Some(page_size);
+        self
+    }
+
+    pub fn paging_state(mut self, paging_state: Vec<u8>) -> Statement {
+        self.params.paging_state = Some(paging_state);
+        self
+    }
+
+    // Microseconds since epoch, requires protocol v3
+    pub fn default_timestamp(mut self, timestamp: i64) -> Statement {
+        self.params.default_timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn idempotent(mut self, idempotent: bool) -> Statement {
+        self.idempotent = idempotent;
+        self
+    }
+
+    pub fn tracing(mut self, tracing: bool) -> Statement {
+        self.tracing = tracing;
+        self
+    }
+
+    pub fn routing_key(mut self, routing_key: Vec<u8>) -> Statement {
+        self.routing_key = Some(routing_key);
+        self
+    }
+
+    // Sets the consistency and page size the statement doesn't set itself
+    pub fn apply_defaults(&mut self, consistency: Consistency, page_size: Option<i32>) {
+        if !self.consistency_set {
+            self.params.consistency = consistency;
+        }
+        if self.params.page_size.is_none() {
+            self.params.page_size = page_size;
+        }
+    }
+
+    pub fn to_request(&self, version: u8) -> CqlRequest {
+        let flags = if self.tracing { CQL_FLAG_TRACING } else { 0x00 };
+        let (opcode, body) = match self.query {
+            StatementQuery::Query(ref query_str) => {
+                (OpcodeRequest::OpcodeQuery, CqlRequestBody::RequestQuery(query_str.clone(), self.params.clone()))
+            },
+            StatementQuery::Prepared(ref preps) => {
+                (OpcodeRequest::OpcodeExecute, CqlRequestBody::RequestExec(preps.id.clone(), self.params.clone()))
+            }
+        };
+        CqlRequest {
+            version: version,
+            flags: flags,
+            stream: 0x01,
+            opcode: opcode,
+            body: body
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use def::*;
+
+// Hands out the stream ids of a connection in constant time. Ids are taken from the
+// ones released by answered requests first, then from the never used ones, and a
+// bitmap keeps track of the ids in flight so none is given twice.
+#[derive(Debug)]
+pub struct StreamIdAllocator {
+    // Ids in [0, next) have been used at least once
+    next: i32,
+    max: i32,
+    released: Vec<i16>,
+    in_flight: Vec<u64>,
+}
+
+impl StreamIdAllocator {
+    pub fn new(version: u8) -> StreamIdAllocator {
+        // Negative ids are left for the events pushed by the server
+        let max = if version >= 3 { CQL_MAX_STREAM_ID_V3 } else { CQL_MAX_STREAM_ID_V1_V2 } as i32 + 1;
+        StreamIdAllocator {
+            next: 0,
+            max: max,
+            released: vec![],
+            in_flight: vec![0; (max as usize + 63) / 64],
+        }
+    }
+
+    pub fn allocate(&mut self) -> Option<i16> {
+        let id = match self.released.pop() {
+            Some(id) => id,
+            None if self.next < self.max => {
+                self.next += 1;
+                (self.next - 1) as i16
+            },
+            None => return None
+        };
+        self.in_flight[id as usize / 64] |= 1 << (id as usize % 64);
+        Some(id)
+    }
+
+    // Returns false if the id wasn't in flight
+    pub fn release(&mut self, id: i16) -> bool {
+        if !self.is_in_flight(id) {
+            return false;
+        }
+        self.in_flight[id as usize / 64] &= !(1 << (id as usize % 64));
+        self.released.push(id);
+        true
+    }
+
+    pub fn is_in_flight(&self, id: i16) -> bool {
+        id >= 0 && (id as i32) < self.max && self.in_flight[id as usize / 64] & (1 << (id as usize % 64)) != 0
+    }
+
+    pub fn in_flight(&self) -> usize {
+        (self.next as usize) - self.released.len()
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.max as usize
+    }
+}
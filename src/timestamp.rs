@@ -0,0 +1,46 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Write timestamps set by the client (query flag 0x20, from v3) instead of the coordinator
+pub trait TimestampGenerator: Send + Sync {
+    // Microseconds since epoch, None leaves it to the server
+    fn next_timestamp(&self) -> Option<i64>;
+}
+
+// The coordinator sets the timestamp, as if no generator was used
+pub struct ServerSideTimestampGenerator;
+
+impl TimestampGenerator for ServerSideTimestampGenerator {
+    fn next_timestamp(&self) -> Option<i64> {
+        None
+    }
+}
+
+// Timestamps from the system clock that never go backwards for this client, even if
+// the clock does or several writes happen in the same microsecond
+pub struct MonotonicTimestampGenerator {
+    last: Mutex<i64>
+}
+
+impl MonotonicTimestampGenerator {
+    pub fn new() -> MonotonicTimestampGenerator {
+        MonotonicTimestampGenerator {
+            last: Mutex::new(0)
+        }
+    }
+}
+
+impl TimestampGenerator for MonotonicTimestampGenerator {
+    fn next_timestamp(&self) -> Option<i64> {
+        let now = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64 * 1_000_000 + (duration.subsec_nanos() / 1_000) as i64,
+            Err(_) => 0
+        };
+        let mut last = match self.last.lock() {
+            Ok(last) => last,
+            Err(poisoned) => poisoned.into_inner()
+        };
+        *last = if now > *last { now } else { *last + 1 };
+        Some(*last)
+    }
+}
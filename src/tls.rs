@@ -0,0 +1,120 @@
+#[cfg(feature = "tls")]
+pub extern crate rustls;
+
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+use error::*;
+#[cfg(feature = "tls")]
+use error::RCErrorType::*;
+use frame::FrameBuffer;
+
+// Set with ClusterBuilder::tls. Without the `tls` feature it can't be built, so connections
+// are always in plain text then.
+#[derive(Clone)]
+pub struct TlsConfig {
+    #[cfg(feature = "tls")]
+    config: Arc<rustls::ClientConfig>,
+    #[cfg(not(feature = "tls"))]
+    never: Never
+}
+
+#[cfg(not(feature = "tls"))]
+#[derive(Clone, Debug)]
+enum Never {}
+
+// TLS state of a connection. Records are read from and written to its socket by the
+// connection, which keeps it blocking or not.
+#[derive(Debug)]
+pub struct TlsSession {
+    #[cfg(feature = "tls")]
+    conn: rustls::ClientConnection,
+    #[cfg(not(feature = "tls"))]
+    never: Never
+}
+
+#[cfg(feature = "tls")]
+impl TlsConfig {
+    pub fn new(config: Arc<rustls::ClientConfig>) -> TlsConfig {
+        TlsConfig {
+            config: config
+        }
+    }
+
+    // Nodes are known by their address, so their certificates must be issued for it
+    pub fn session(&self, ip: IpAddr) -> RCResult<TlsSession> {
+        match rustls::ClientConnection::new(self.config.clone(), ip.into()) {
+            Ok(conn) => Ok(TlsSession { conn: conn }),
+            Err(err) => Err(RCError::new(format!("Error starting TLS with {} -> {}", ip, err), ConnectionError))
+        }
+    }
+}
+
+#[cfg(feature = "tls")]
+impl TlsSession {
+    // Reads the records the socket has and adds what they decrypt to `frames`. Returns the
+    // bytes read from the socket, 0 once it's closed. Errors of the socket, WouldBlock
+    // included, are returned as they are.
+    pub fn read_from<R: Read>(&mut self, socket: &mut R, frames: &mut FrameBuffer) -> io::Result<usize> {
+        let read = try!(self.conn.read_tls(socket));
+        if let Err(err) = self.conn.process_new_packets() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, err));
+        }
+        let mut buf = [0u8; 4096];
+        loop {
+            match self.conn.reader().read(&mut buf) {
+                Ok(0) => return Ok(0),
+                Ok(n) => frames.extend(&buf[..n]),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(read),
+                Err(err) => return Err(err)
+            }
+        }
+    }
+
+    // Plain text to send, it's encrypted once the handshake is done. Returns how much was taken.
+    pub fn write_plain(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.conn.writer().write(data)
+    }
+
+    // Writes the pending records until the socket would block
+    pub fn write_to<W: Write>(&mut self, socket: &mut W) -> io::Result<()> {
+        while self.conn.wants_write() {
+            if try!(self.conn.write_tls(socket)) == 0 {
+                return Err(io::Error::new(io::ErrorKind::WriteZero, "Connection closed by the node"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn wants_write(&self) -> bool {
+        self.conn.wants_write()
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+impl TlsConfig {
+    pub fn session(&self, _: IpAddr) -> RCResult<TlsSession> {
+        match self.never {}
+    }
+}
+
+#[cfg(not(feature = "tls"))]
+impl TlsSession {
+    pub fn read_from<R: Read>(&mut self, _: &mut R, _: &mut FrameBuffer) -> io::Result<usize> {
+        match self.never {}
+    }
+
+    pub fn write_plain(&mut self, _: &[u8]) -> io::Result<usize> {
+        match self.never {}
+    }
+
+    pub fn write_to<W: Write>(&mut self, _: &mut W) -> io::Result<()> {
+        match self.never {}
+    }
+
+    pub fn wants_write(&self) -> bool {
+        match self.never {}
+    }
+}
@@ -1,10 +1,13 @@
 
 
 use std::time::Duration;
-use std::sync::mpsc::{Receiver,Sender, channel};
+use std::sync::mpsc::{Receiver,Sender,RecvTimeoutError,channel};
 use std::thread;
-use std::thread::{Builder,sleep};
+use std::thread::{Builder,JoinHandle,sleep};
+use std::net::{SocketAddr,IpAddr,ToSocketAddrs};
 use def::*;
+use error::*;
+use error::RCErrorType::*;
 
 
 
@@ -20,20 +23,39 @@ pub fn to_hex_string(bytes: &Vec<u8>) -> String {
   strs.connect(" ")
 }
 
-pub fn max_stream_id(stream_id: i16,version: u8) -> bool{
-    (stream_id as i32 >= CQL_MAX_STREAM_ID_V1_V2 as i32 && (version == 1 || version == 2))
-      || (stream_id as i32 == CQL_MAX_STREAM_ID_V3 as i32 && version == 3)
-}
-
-pub fn set_interval<F>(delay: Duration,f: F) -> Sender<()>
+// Runs f every delay until a message is sent or the sender is dropped
+pub fn set_interval<F>(delay: Duration,f: F) -> (Sender<()>,JoinHandle<()>)
     where F: Fn(), F: Send + 'static + Sync{
 
     let (tx, rx) = channel::<(())>();
-    thread::Builder::new().name("tick".to_string()).spawn(move || {
-        while !rx.try_recv().is_ok() {
-            sleep(delay);
+    let handle = thread::Builder::new().name("tick".to_string()).spawn(move || {
+        while let Err(RecvTimeoutError::Timeout) = rx.recv_timeout(delay) {
             f();  //Do stuff here
         }
     }).unwrap();
-    tx
-}
\ No newline at end of file
+    (tx,handle)
+}
+// Resolves "host", "host:port", "ip" or "ip:port", the port defaults to default_port
+pub fn resolve_contact_point(contact_point: &str, default_port: u16) -> RCResult<Vec<SocketAddr>> {
+    let addresses: Vec<SocketAddr> = if let Ok(address) = contact_point.parse::<SocketAddr>() {
+        vec![address]
+    } else if let Ok(ip) = contact_point.parse::<IpAddr>() {
+        vec![SocketAddr::new(ip, default_port)]
+    } else {
+        let has_port = contact_point.rsplit(':').next().map(|port| port.parse::<u16>().is_ok()).unwrap_or(false)
+                       && contact_point.matches(':').count() == 1;
+        let resolved = if has_port {
+            contact_point.to_socket_addrs()
+        } else {
+            (contact_point, default_port).to_socket_addrs()
+        };
+        match resolved {
+            Ok(addresses) => addresses.collect(),
+            Err(err) => return Err(RCError::new(format!("Couldn't resolve {} -> {}", contact_point, err), ConnectionError))
+        }
+    };
+    if addresses.is_empty() {
+        return Err(RCError::new(format!("{} doesn't resolve to any address", contact_point), ConnectionError));
+    }
+    Ok(addresses)
+}
@@ -0,0 +1,202 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use cql::rustls;
+#[cfg(feature = "tls")]
+use cql::rustls::pki_types::{CertificateDer, PrivatePkcs8KeyDer};
+
+// Issued for 127.0.0.1 and 127.0.0.2 by the CA, both valid until 2125
+#[cfg(feature = "tls")]
+const CA_CERT: &'static [u8] = include_bytes!("certs/ca.der");
+#[cfg(feature = "tls")]
+const NODE_CERT: &'static [u8] = include_bytes!("certs/node.der");
+#[cfg(feature = "tls")]
+const NODE_KEY: &'static [u8] = include_bytes!("certs/node.key.der");
+
+// What a fake node answers to a QUERY or a PREPARE
+pub enum Reply {
+	Void,
+	Prepared,
+	Error(u32),
+	// Rows cut short, they can't be decoded
+	Garbage,
+	// Closes the connection without answering
+	Close,
+	// Answers Void after this many milliseconds
+	Late(u64)
+}
+
+// Just enough of a v3 node for a cluster to connect: 127.0.0.2 is its only peer. The other
+// queries and the prepares are answered by `answer`, and recorded in the returned list.
+// Schema queries answered Void get no rows, an empty schema
+pub fn fake_node(listener: TcpListener, answer: fn(&str) -> Reply) -> Arc<Mutex<Vec<String>>> {
+	let received = Arc::new(Mutex::new(vec![]));
+	let list = received.clone();
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let list = list.clone();
+			if let Ok(stream) = stream {
+				thread::spawn(move || serve(stream, answer, list));
+			}
+		}
+	});
+	received
+}
+
+// Two fake nodes on 127.0.0.1 and 127.0.0.2, on the same port
+pub fn fake_cluster(first: fn(&str) -> Reply, second: fn(&str) -> Reply) -> (u16, Arc<Mutex<Vec<String>>>, Arc<Mutex<Vec<String>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let other = TcpListener::bind(("127.0.0.2", port)).unwrap();
+	(port, fake_node(listener, first), fake_node(other, second))
+}
+
+// Same as fake_cluster, but the nodes only talk TLS with the certificate of certs/node.der
+#[cfg(feature = "tls")]
+pub fn fake_tls_cluster(first: fn(&str) -> Reply, second: fn(&str) -> Reply) -> (u16, Arc<Mutex<Vec<String>>>, Arc<Mutex<Vec<String>>>) {
+	let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+	let port = listener.local_addr().unwrap().port();
+	let other = TcpListener::bind(("127.0.0.2", port)).unwrap();
+	(port, fake_tls_node(listener, first), fake_tls_node(other, second))
+}
+
+#[cfg(feature = "tls")]
+fn fake_tls_node(listener: TcpListener, answer: fn(&str) -> Reply) -> Arc<Mutex<Vec<String>>> {
+	let cert = CertificateDer::from(NODE_CERT.to_vec());
+	let key = PrivatePkcs8KeyDer::from(NODE_KEY.to_vec());
+	let config = Arc::new(rustls::ServerConfig::builder()
+	                          .with_no_client_auth()
+	                          .with_single_cert(vec![cert], key.into())
+	                          .unwrap());
+	let received = Arc::new(Mutex::new(vec![]));
+	let list = received.clone();
+	thread::spawn(move || {
+		for stream in listener.incoming() {
+			let list = list.clone();
+			let config = config.clone();
+			if let Ok(stream) = stream {
+				thread::spawn(move || {
+					let conn = rustls::ServerConnection::new(config).unwrap();
+					serve(rustls::StreamOwned::new(conn, stream), answer, list)
+				});
+			}
+		}
+	});
+	received
+}
+
+// Trusts the CA that signed the fake nodes' certificate
+#[cfg(feature = "tls")]
+pub fn tls_config() -> Arc<rustls::ClientConfig> {
+	let mut roots = rustls::RootCertStore::empty();
+	roots.add(CertificateDer::from(CA_CERT.to_vec())).unwrap();
+	Arc::new(rustls::ClientConfig::builder().with_root_certificates(roots).with_no_client_auth())
+}
+
+pub fn count(received: &Arc<Mutex<Vec<String>>>, pattern: &str) -> usize {
+	received.lock().unwrap().iter().filter(|query| query.contains(pattern)).count()
+}
+
+fn serve<S: Read + Write>(mut stream: S, answer: fn(&str) -> Reply, received: Arc<Mutex<Vec<String>>>) {
+	let mut header = [0u8; 9];
+	while stream.read_exact(&mut header).is_ok() {
+		let len = header[5..9].iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+		let mut body = vec![0u8; len];
+		if stream.read_exact(&mut body).is_err() {
+			return;
+		}
+		let (opcode, response) = match header[4] {
+			// OPTIONS
+			0x05 => (0x06, supported()),
+			// STARTUP, recorded to count the connections, and REGISTER
+			0x01 => {
+				received.lock().unwrap().push("STARTUP".to_string());
+				(0x02, vec![])
+			},
+			0x0B => (0x02, vec![]),
+			// QUERY and PREPARE
+			0x07 | 0x09 => {
+				let len = body[0..4].iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+				let query = String::from_utf8_lossy(&body[4..4 + len]).into_owned();
+				if query.contains("system.peers") {
+					(0x08, peers())
+				} else {
+					received.lock().unwrap().push(query.clone());
+					match answer(&query) {
+						Reply::Void if query.contains("keyspace_name") => (0x08, no_rows()),
+						Reply::Void => (0x08, vec![0, 0, 0, 1]),
+						Reply::Prepared => (0x08, prepared()),
+						Reply::Error(code) => (0x00, error(code)),
+						Reply::Garbage => (0x08, vec![0, 0, 0, 2, 0]),
+						Reply::Close => return,
+						Reply::Late(ms) => {
+							thread::sleep(Duration::from_millis(ms));
+							(0x08, vec![0, 0, 0, 1])
+						}
+					}
+				}
+			},
+			_ => (0x08, vec![0, 0, 0, 1])
+		};
+		let mut frame = vec![0x83, 0, header[2], header[3], opcode];
+		frame.extend_from_slice(&be32(response.len() as u32));
+		frame.extend_from_slice(&response);
+		if stream.write_all(&frame).is_err() {
+			return;
+		}
+	}
+}
+
+fn be32(n: u32) -> [u8; 4] {
+	[(n >> 24) as u8, (n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn string(s: &str) -> Vec<u8> {
+	let mut bytes = vec![(s.len() >> 8) as u8, s.len() as u8];
+	bytes.extend_from_slice(s.as_bytes());
+	bytes
+}
+
+fn supported() -> Vec<u8> {
+	let mut body = vec![0, 2];
+	body.extend(string("CQL_VERSION"));
+	body.extend_from_slice(&[0, 1]);
+	body.extend(string("3.4.4"));
+	body.extend(string("COMPRESSION"));
+	body.extend_from_slice(&[0, 0]);
+	body
+}
+
+fn peers() -> Vec<u8> {
+	// Rows, global table spec, a single inet column
+	let mut body = vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1];
+	body.extend(string("system"));
+	body.extend(string("peers"));
+	body.extend(string("peer"));
+	body.extend_from_slice(&[0, 0x10, 0, 0, 0, 1, 0, 0, 0, 4, 127, 0, 0, 2]);
+	body
+}
+
+fn no_rows() -> Vec<u8> {
+	// Rows, global table spec, a single varchar column
+	let mut body = vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1];
+	body.extend(string("system_schema"));
+	body.extend(string("keyspaces"));
+	body.extend(string("keyspace_name"));
+	body.extend_from_slice(&[0, 0x0D, 0, 0, 0, 0]);
+	body
+}
+
+fn prepared() -> Vec<u8> {
+	// Prepared, id 0x0102, no bound variables and no result metadata
+	vec![0, 0, 0, 4, 0, 2, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0]
+}
+
+fn error(code: u32) -> Vec<u8> {
+	let mut body = be32(code).to_vec();
+	body.extend(string("error"));
+	body
+}
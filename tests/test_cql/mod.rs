@@ -17,6 +17,37 @@ macro_rules! assert_response(
     );
 );
 
+// Decodes a v3 RESULT Rows frame
+pub fn read_rows(bytes: Vec<u8>) -> CqlRows {
+    match bytes.as_slice().read_cql_response(3).unwrap().body {
+        CqlResponseBody::ResultRows(rows) => rows,
+        _ => panic!("Expected rows")
+    }
+}
+
+// v3 frames shared by the decoding tests
+pub const STATUS_CHANGE_EVENT: &'static [u8] = &[
+    // Header (v3 -> 5 bytes)
+    131, 0, 255, 255, 12,
+    // Length
+    0, 0, 0, 28,
+    // Body -----------
+    //STATUS_CHANGE
+    0,13, 83, 84, 65, 84, 85, 83, 95, 67, 72, 65, 78, 71, 69,
+    //UP
+    0, 2, 85, 80,
+    //[inet]
+    //address = 172.17.0.3
+    4, 172, 17, 0, 3,
+    //Port = 9042
+    0, 0, 35, 82];
+// SELECT peer,data_center,rack FROM system.peers;
+pub const PEERS_ROWS: &'static [u8] = &[131, 0, 0, 1, 8, 0, 0, 0, 94, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 6, 115, 121, 115, 116, 101, 109, 0, 5, 112, 101, 101, 114, 115, 0, 4, 112, 101, 101, 114, 0, 16, 0, 11, 100, 97, 116, 97, 95, 99, 101, 110, 116, 101, 114, 0, 13, 0, 4, 114, 97, 99, 107, 0, 13, 0, 0, 0, 1, 0, 0, 0, 4, 172, 17, 0, 3, 0, 0, 0, 11, 100, 97, 116, 97, 99, 101, 110, 116, 101, 114, 49, 0, 0, 0, 5, 114, 97, 99, 107, 49];
+// Columns: a tuple<int, varchar>, b list<tuple<int, varchar>>
+pub const TUPLE_ROWS: &'static [u8] = &[131, 0, 0, 1, 8, 0, 0, 0, 109, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 2, 107, 115, 0, 1, 116, 0, 1, 97, 0, 49, 0, 2, 0, 9, 0, 13, 0, 1, 98, 0, 32, 0, 49, 0, 2, 0, 9, 0, 13, 0, 0, 0, 1, 0, 0, 0, 14, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 2, 104, 105, 0, 0, 0, 40, 0, 0, 0, 2, 0, 0, 0, 14, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 2, 104, 105, 0, 0, 0, 14, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 2, 104, 105];
+// Columns: a list<frozen<map<varchar, int>>>, b map<varchar, frozen<set<uuid>>>
+pub const NESTED_COLLECTIONS_ROWS: &'static [u8] = &[131, 0, 0, 1, 8, 0, 0, 0, 136, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 2, 0, 2, 107, 115, 0, 1, 116, 0, 1, 97, 0, 32, 0, 33, 0, 13, 0, 9, 0, 1, 98, 0, 33, 0, 13, 0, 34, 0, 12, 0, 0, 0, 1, 0, 0, 0, 46, 0, 0, 0, 2, 0, 0, 0, 17, 0, 0, 0, 1, 0, 0, 0, 1, 107, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 17, 0, 0, 0, 1, 0, 0, 0, 1, 107, 0, 0, 0, 4, 0, 0, 0, 5, 0, 0, 0, 37, 0, 0, 0, 1, 0, 0, 0, 1, 120, 0, 0, 0, 24, 0, 0, 0, 1, 0, 0, 0, 16, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+
 macro_rules! try_test(
     ($call: expr, $msg: expr) => {
         match $call {
@@ -96,9 +127,13 @@ fn test_queries() {
     let preps = try_test!(cluster.prepared_statement(q), "Error creating prepared statement");
     println!("Created prepared with id = {}", to_hex_string(&preps.id));
 
+    println!("Prepare on all nodes: {}", q);
+    let preps_all = try_test!(cluster.prepare(q, true).await().unwrap(), "Error preparing statement on all nodes");
+    assert_eq!(preps_all.id, preps.id);
+
     println!("Execute prepared");
     let params = &vec![cql::CqlInt(Some(7)),CqlVarchar(Some(Cow::Borrowed(""))), cql::CqlBigInt(Some(1234567890))];
-    response = try_test!(cluster.exec_prepared(&preps.id, params, cql::Consistency::One).await().unwrap(), "Error executing prepared statement");
+    response = try_test!(cluster.exec_prepared(&preps, params, cql::Consistency::One).await().unwrap(), "Error executing prepared statement");
     assert_response!(response);
     println!("Result: {:?} \n", response);
     
@@ -123,7 +158,22 @@ fn test_queries() {
     println!("Result: {:?} \n", response);
 }
 
+mod fake_node;
 mod test_reader;
 mod test_multiple_requests;
 mod test_events;
-mod test_async;
\ No newline at end of file
+mod test_async;
+mod test_fuzz;
+mod test_convert;
+mod test_statement;
+mod test_trace;
+mod test_compression;
+mod test_frame;
+mod test_stream_id;
+mod test_cluster_builder;
+mod test_schema;
+mod test_cluster_events;
+mod test_retry;
+mod test_connection;
+mod test_prepare;
+mod test_control_connection;
@@ -0,0 +1,88 @@
+extern crate cql;
+
+use cql::*;
+use std::time::Duration;
+
+#[test]
+fn test_builder_validation(){
+	assert!(Cluster::builder().protocol_version(0).config().validate().is_err());
+	assert!(Cluster::builder().protocol_version(4).config().validate().is_err());
+	assert!(Cluster::builder().protocol_version(1).credentials("user", "pass").config().validate().is_err());
+
+	let builder = Cluster::builder()
+					.contact_point("127.0.0.1:9043")
+					.port(9043)
+					.protocol_version(2)
+					.credentials("user", "pass")
+					.consistency(Consistency::Quorum)
+					.page_size(100)
+					.event_debounce(Duration::from_millis(200));
+	assert!(builder.config().validate().is_ok());
+	let cluster = builder.build().unwrap();
+	assert_eq!(cluster.config().version, 2);
+	assert_eq!(cluster.config().port, 9043);
+	assert_eq!(cluster.config().contact_points.len(), 1);
+	assert_eq!(cluster.config().credentials, Some(("user".to_string(), "pass".to_string())));
+	assert_eq!(cluster.config().event_debounce, Duration::from_millis(200));
+}
+
+#[test]
+fn test_connect_without_contact_points(){
+	let mut cluster = Cluster::builder().build().unwrap();
+	assert!(cluster.connect().is_err());
+}
+
+#[test]
+fn test_connect_tries_every_contact_point(){
+	let mut cluster = Cluster::builder()
+						.contact_points(&["127.0.0.1:1", "host.invalid"])
+						.build()
+						.unwrap();
+	let err = cluster.connect().err().expect("Nothing listens on the contact points");
+	assert!(err.desc.contains("127.0.0.1:1"));
+	assert!(err.desc.contains("host.invalid"));
+}
+
+#[test]
+fn test_statement_defaults(){
+	let mut statement = Statement::query("select");
+	statement.apply_defaults(Consistency::Quorum, Some(100));
+	assert_eq!(statement.params.consistency, Consistency::Quorum);
+	assert_eq!(statement.params.page_size, Some(100));
+
+	let mut statement = Statement::query("select").consistency(Consistency::One).page_size(10);
+	statement.apply_defaults(Consistency::Quorum, Some(100));
+	assert_eq!(statement.params.consistency, Consistency::One);
+	assert_eq!(statement.params.page_size, Some(10));
+}
+
+#[test]
+fn test_retry_policies(){
+	let read = Statement::query("select");
+	let write = Statement::query("insert").idempotent(true);
+	let policy = DefaultRetryPolicy;
+	assert_eq!(policy.on_server_error(&read, 0x1200, 0), RetryDecision::Retry);
+	assert_eq!(policy.on_server_error(&read, 0x1200, 1), RetryDecision::Rethrow);
+	assert_eq!(policy.on_server_error(&read, 0x1100, 0), RetryDecision::Rethrow);
+	assert_eq!(policy.on_server_error(&write, 0x1100, 0), RetryDecision::Retry);
+	assert_eq!(policy.on_server_error(&read, 0x2000, 0), RetryDecision::Rethrow);
+
+	let error = RCError::new("Connection lost", ConnectionError);
+	assert_eq!(policy.on_client_error(&read, &error, 0), RetryDecision::Rethrow);
+	assert_eq!(policy.on_client_error(&write, &error, 0), RetryDecision::Retry);
+	assert_eq!(FallthroughRetryPolicy.on_server_error(&read, 0x1200, 0), RetryDecision::Rethrow);
+}
+
+#[test]
+fn test_reconnection_policies(){
+	let constant = ConstantReconnectionPolicy::new(Duration::from_millis(500), Some(2));
+	assert_eq!(constant.next_delay(0), Some(Duration::from_millis(500)));
+	assert_eq!(constant.next_delay(1), Some(Duration::from_millis(500)));
+	assert_eq!(constant.next_delay(2), None);
+
+	let exponential = ExponentialReconnectionPolicy::new(Duration::from_secs(1), Duration::from_secs(60));
+	assert_eq!(exponential.next_delay(0), Some(Duration::from_secs(1)));
+	assert_eq!(exponential.next_delay(3), Some(Duration::from_secs(8)));
+	assert_eq!(exponential.next_delay(10), Some(Duration::from_secs(60)));
+	assert_eq!(exponential.next_delay(100), Some(Duration::from_secs(60)));
+}
@@ -0,0 +1,55 @@
+extern crate cql;
+
+use cql::*;
+use std::borrow::Cow;
+use std::time::Duration;
+use super::fake_node::{Reply, fake_cluster};
+
+#[test]
+fn test_cluster_event_from_cql(){
+	let address = "10.0.0.1:9042".parse().unwrap();
+	let table = SchemaChangeOptions::Table(Cow::Borrowed("ks"), Cow::Borrowed("users"));
+	assert_eq!(ClusterEvent::from_cql(&CqlEvent::StatusChange(StatusChangeType::Down, address)),
+			   Some(ClusterEvent::HostDown(address)));
+	assert_eq!(ClusterEvent::from_cql(&CqlEvent::TopologyChange(TopologyChangeType::NewNode, address)),
+			   Some(ClusterEvent::HostAdded(address)));
+	assert_eq!(ClusterEvent::from_cql(&CqlEvent::SchemaChange(SchemaChangeType::Dropped, table.clone())),
+			   Some(ClusterEvent::SchemaDropped(table)));
+	assert_eq!(ClusterEvent::from_cql(&CqlEvent::TopologyChange(TopologyChangeType::MovedNode, address)), None);
+	assert_eq!(ClusterEvent::from_cql(&CqlEvent::UnknownEvent), None);
+}
+
+#[test]
+fn test_subscribers_disconnected_on_shutdown(){
+	let mut cluster = Cluster::new();
+	let first = cluster.subscribe();
+	let second = cluster.subscribe();
+	cluster.shutdown(Duration::from_secs(1)).unwrap();
+	assert!(first.recv().is_err());
+	assert!(second.recv().is_err());
+}
+
+fn void(_: &str) -> Reply {
+	Reply::Void
+}
+
+#[test]
+fn test_unreachable_contact_point_given_up(){
+	let (port, _, _) = fake_cluster(void, void);
+	// Nothing listens on 127.0.0.3
+	let mut cluster = Cluster::builder()
+						.contact_points(&["127.0.0.3", "127.0.0.1"])
+						.port(port)
+						.reconnection_policy(ConstantReconnectionPolicy::new(Duration::from_millis(10), Some(2)))
+						.build()
+						.unwrap();
+	let events = cluster.subscribe();
+	cluster.connect().unwrap();
+
+	let address = ("127.0.0.3:".to_string() + &port.to_string()).parse().unwrap();
+	let unreachable: Vec<ClusterEvent> = events.iter().take_while(|event| *event != ClusterEvent::HostGivenUp(address))
+		.filter(|event| match *event { ClusterEvent::HostUnreachable(host, _) => host == address, _ => false })
+		.collect();
+	// The contact point that failed and the health check
+	assert_eq!(unreachable.len(), 2);
+}
@@ -0,0 +1,86 @@
+extern crate cql;
+
+use cql::*;
+
+fn query_request() -> CqlRequest {
+	Statement::query("select * from test.test where id = 1").to_request(3)
+}
+
+// Void result response, with the compression flag and the given body
+fn compressed_response(body: Vec<u8>) -> Vec<u8> {
+	let mut bytes = vec![0x83, 0x01, 0, 1, 0x08, 0, 0, 0, body.len() as u8];
+	bytes.extend(body);
+	bytes
+}
+
+fn round_trip(compression: Compression) {
+	let request = query_request();
+	let mut plain = vec![];
+	request.serialize(&mut plain, 3).unwrap();
+	let mut compressed = vec![];
+	request.serialize_compressed(&mut compressed, 3, compression).unwrap();
+
+	// Same header but the compression flag and the length
+	assert_eq!(compressed[1], 0x01);
+	assert_eq!(&compressed[2..5], &plain[2..5]);
+	let len = ((compressed[7] as usize) << 8) | compressed[8] as usize;
+	assert_eq!(len, compressed.len() - 9);
+	assert_eq!(decompress(compression, &compressed[9..]).unwrap(), &plain[9..]);
+
+	let response = compressed_response(compress(compression, &[0, 0, 0, 1]).unwrap());
+	let response = response.as_slice().read_cql_response_compressed(3, compression).unwrap();
+	match response.body {
+		CqlResponseBody::ResultVoid => (),
+		body => panic!("Unexpected body {:?}", body)
+	}
+}
+
+#[test]
+fn test_compressed_response_without_compression(){
+	let response = compressed_response(vec![0, 0, 0, 1]);
+	assert!(response.as_slice().read_cql_response(3).is_err());
+}
+
+#[test]
+fn test_no_compression(){
+	let request = query_request();
+	let mut plain = vec![];
+	request.serialize(&mut plain, 3).unwrap();
+	let mut buf = vec![];
+	request.serialize_compressed(&mut buf, 3, Compression::None).unwrap();
+	assert_eq!(buf, plain);
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_lz4(){
+	round_trip(Compression::Lz4);
+	// Uncompressed length prepended as an [int]
+	assert_eq!(&compress(Compression::Lz4, &[7; 100]).unwrap()[..4], &[0, 0, 0, 100]);
+	assert!(decompress(Compression::Lz4, &[0, 0, 0]).is_err());
+	// Length that doesn't match the block
+	let mut bytes = compress(Compression::Lz4, &[7; 100]).unwrap();
+	bytes[3] = 50;
+	assert!(decompress(Compression::Lz4, &bytes).is_err());
+}
+
+#[cfg(not(feature = "lz4"))]
+#[test]
+fn test_lz4_not_compiled(){
+	assert!(compress(Compression::Lz4, &[1, 2, 3]).is_err());
+	assert!(!available_compressions().contains(&Compression::Lz4));
+}
+
+#[cfg(feature = "snappy")]
+#[test]
+fn test_snappy(){
+	round_trip(Compression::Snappy);
+	assert!(decompress(Compression::Snappy, &[0xff, 0xff]).is_err());
+}
+
+#[cfg(not(feature = "snappy"))]
+#[test]
+fn test_snappy_not_compiled(){
+	assert!(compress(Compression::Snappy, &[1, 2, 3]).is_err());
+	assert!(!available_compressions().contains(&Compression::Snappy));
+}
@@ -0,0 +1,137 @@
+extern crate cql;
+extern crate eventual;
+
+use std::time::{Duration, Instant};
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+use self::eventual::Async;
+use cql::*;
+use super::fake_node::{Reply, fake_cluster, count};
+#[cfg(feature = "tls")]
+use super::fake_node::{fake_tls_cluster, tls_config};
+
+fn void(_: &str) -> Reply {
+	Reply::Void
+}
+
+fn late(query: &str) -> Reply {
+	if query.contains("late") { Reply::Late(500) } else { Reply::Void }
+}
+
+fn garbage(query: &str) -> Reply {
+	if query.contains("garbage") { Reply::Garbage } else { Reply::Void }
+}
+
+#[test]
+fn test_unserializable_request(){
+	let (port, first, second) = fake_cluster(void, void);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	cluster.connect().unwrap();
+
+	// Varints can't be serialized yet, the request fails without reaching the node
+	let statement = Statement::query("insert varint").values(&vec![CqlVarint(None)]);
+	let err = cluster.execute(statement).await().unwrap().err().expect("The value can't be serialized");
+	assert!(err.desc.contains("Varint"));
+
+	// Nothing is left half written in the connection
+	let response = cluster.execute(Statement::query("select after")).await().unwrap().unwrap();
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	assert_eq!(count(&first, "varint") + count(&second, "varint"), 0);
+	assert_eq!(count(&first, "after") + count(&second, "after"), 1);
+}
+
+#[test]
+fn test_undecodable_response(){
+	let (port, _, _) = fake_cluster(garbage, garbage);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	cluster.connect().unwrap();
+
+	// The stream id in the header tells which request failed
+	let err = cluster.execute(Statement::query("select garbage")).await().unwrap().err().expect("The rows can't be decoded");
+	assert!(err.desc.contains("Error decoding the response"));
+
+	let response = cluster.execute(Statement::query("select after")).await().unwrap().unwrap();
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+}
+
+#[test]
+fn test_request_timeout(){
+	let (port, first, _) = fake_cluster(late, void);
+	let mut cluster = Cluster::builder()
+						.contact_point("127.0.0.1")
+						.port(port)
+						.load_balancing(BalancerType::RoundRobin, Duration::from_secs(3600))
+						.request_timeout(Some(Duration::from_millis(200)))
+						.build()
+						.unwrap();
+	cluster.connect().unwrap();
+
+	let err = cluster.execute(Statement::query("select late")).await().unwrap().err().expect("The node answers too late");
+	assert!(err.desc.contains("timed out"));
+
+	// The late answer doesn't complete the next request, which waits for it on the same connection
+	let response = cluster.execute(Statement::query("select after")).await().unwrap().unwrap();
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	assert_eq!(count(&first, "after"), 1);
+}
+
+#[test]
+fn test_pool_size(){
+	let (port, first, _) = fake_cluster(late, void);
+	let mut cluster = Cluster::builder()
+						.contact_point("127.0.0.1")
+						.port(port)
+						.load_balancing(BalancerType::RoundRobin, Duration::from_secs(3600))
+						.pool_size(3)
+						.build()
+						.unwrap();
+	cluster.connect().unwrap();
+	// And the control connection
+	assert_eq!(count(&first, "STARTUP"), 4);
+
+	// A node answers the requests of each connection one at a time, so these would take 1.5 s through a single one
+	let start = Instant::now();
+	let futures: Vec<_> = (0..3).map(|_| cluster.execute(Statement::query("select late"))).collect();
+	for future in futures {
+		let response = future.await().unwrap().unwrap();
+		assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	}
+	assert!(start.elapsed() < Duration::from_millis(1200));
+}
+
+#[cfg(feature = "lz4")]
+#[test]
+fn test_compression_not_supported(){
+	// The fake nodes support no compression
+	let (port, _, _) = fake_cluster(void, void);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).compression(Compression::Lz4).build().unwrap();
+	let err = cluster.connect().err().expect("The node doesn't support lz4");
+	assert!(err.desc.contains("doesn't support lz4 compression"));
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls(){
+	let (port, first, second) = fake_tls_cluster(void, void);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).tls(tls_config()).build().unwrap();
+	cluster.connect().unwrap();
+
+	// Both nodes got a connection of the pool, through which the queries go
+	for _ in 0..4 {
+		let response = cluster.execute(Statement::query("select tls")).await().unwrap().unwrap();
+		assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	}
+	assert_eq!(count(&first, "tls") + count(&second, "tls"), 4);
+	assert!(count(&second, "STARTUP") > 0);
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn test_tls_untrusted_node(){
+	// Nothing is trusted, the nodes' certificate is refused
+	let (port, _, _) = fake_tls_cluster(void, void);
+	let config = rustls::ClientConfig::builder().with_root_certificates(rustls::RootCertStore::empty()).with_no_client_auth();
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).tls(Arc::new(config)).build().unwrap();
+	let err = cluster.connect().err().expect("The certificate isn't trusted");
+	assert!(err.desc.contains("UnknownIssuer"));
+}
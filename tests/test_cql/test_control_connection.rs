@@ -0,0 +1,28 @@
+extern crate cql;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use cql::*;
+use super::fake_node::{Reply, fake_cluster};
+
+static LOCAL_HANGS: AtomicBool = AtomicBool::new(false);
+
+fn hangs_later(query: &str) -> Reply {
+	if query.contains("system.local") && LOCAL_HANGS.load(Ordering::SeqCst) { Reply::Late(60_000) } else { Reply::Void }
+}
+
+#[test]
+fn test_shutdown_while_refreshing(){
+	let (port, _, _) = fake_cluster(hangs_later, hangs_later);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	let events = cluster.subscribe();
+	cluster.connect().unwrap();
+
+	// The refresh would wait for the request timeout, then try every node again
+	LOCAL_HANGS.store(true, Ordering::SeqCst);
+	cluster.refresh_nodes();
+	let start = Instant::now();
+	cluster.shutdown(Duration::from_millis(500)).unwrap();
+	assert!(start.elapsed() < Duration::from_secs(2));
+	assert!(events.iter().any(|event| match event { ClusterEvent::ControlConnectionLost(..) => true, _ => false }));
+}
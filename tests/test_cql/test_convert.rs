@@ -0,0 +1,179 @@
+extern crate cql;
+extern crate uuid;
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use self::uuid::Uuid;
+use cql::*;
+use super::{read_rows, PEERS_ROWS};
+
+#[test]
+fn test_get_by_index_and_name(){
+	// SELECT peer,data_center,rack FROM system.peers;
+	let rows = read_rows(PEERS_ROWS.to_vec());
+	let row = &rows.rows[0];
+
+	assert_eq!(row.get::<IpAddr>(0).unwrap(), IpAddr::V4(Ipv4Addr::new(172, 17, 0, 3)));
+	assert_eq!(row.get::<String>(1).unwrap(), "datacenter1".to_string());
+	assert_eq!(row.get_by_name::<String>("rack").unwrap(), "rack1".to_string());
+	assert_eq!(row.get_by_name::<Option<String>>("rack").unwrap(), Some("rack1".to_string()));
+
+	// Wrong type, missing column and out of range index
+	assert!(row.get::<i32>(1).is_err());
+	assert!(row.get_by_name::<String>("tokens").is_err());
+	assert!(row.get::<String>(3).is_err());
+}
+
+#[test]
+fn test_from_cql(){
+	assert_eq!(i32::from_cql(&CqlInt(Some(7))).unwrap(), 7);
+	assert_eq!(i64::from_cql(&CqlCounter(Some(7))).unwrap(), 7);
+	assert_eq!(Option::<i32>::from_cql(&CqlInt(None)).unwrap(), None);
+	assert!(i32::from_cql(&CqlInt(None)).is_err());
+	assert!(i32::from_cql(&CqlBigInt(Some(7))).is_err());
+
+	let uuid = Uuid::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).unwrap();
+	assert_eq!(Uuid::from_cql(&CqlTimeUuid(Some(uuid))).unwrap(), uuid);
+
+	let list = CqlList(Some(vec![CqlInt(Some(1)), CqlInt(Some(2))]));
+	assert_eq!(Vec::<i32>::from_cql(&list).unwrap(), vec![1, 2]);
+	assert_eq!(Vec::<u8>::from_cql(&CqlBlob(Some(vec![1, 2]))).unwrap(), vec![1, 2]);
+
+	let map = CqlMap(Some(vec![Pair{ key: CqlVarchar(Some(Cow::Borrowed("k"))), value: CqlInt(Some(5)) }]));
+	let mut expected = HashMap::new();
+	expected.insert("k".to_string(), 5);
+	assert_eq!(HashMap::<String, i32>::from_cql(&map).unwrap(), expected);
+
+	let tuple = CqlTuple(Some(vec![CqlInt(Some(7)), CqlVarchar(Some(Cow::Borrowed("hi")))]));
+	assert_eq!(<(i32, String)>::from_cql(&tuple).unwrap(), (7, "hi".to_string()));
+	assert_eq!(<(i32, String, Option<i32>)>::from_cql(&tuple).unwrap(), (7, "hi".to_string(), None));
+}
+
+fn column(name: &'static str, col_type: CqlColType) -> CqlColMetadata {
+	CqlColMetadata {
+		keyspace: Cow::Borrowed("rust"),
+		table: Cow::Borrowed("test_types"),
+		col_name: Cow::Borrowed(name),
+		col_type: col_type
+	}
+}
+
+#[test]
+fn test_to_cql(){
+	assert_eq!(7.to_cql(), CqlInt(Some(7)));
+	assert_eq!("hi".to_cql(), CqlVarchar(Some(Cow::Borrowed("hi"))));
+	assert_eq!(None::<i32>.to_cql(), CqlRaw(None));
+	assert_eq!(vec![1i64, 2].to_cql(), CqlList(Some(vec![CqlBigInt(Some(1)), CqlBigInt(Some(2))])));
+	assert_eq!((1, "a").to_cql(), CqlTuple(Some(vec![CqlInt(Some(1)), CqlVarchar(Some(Cow::Borrowed("a")))])));
+	assert_eq!((1, "a", Some(true)).to_cql_values(), vec![CqlInt(Some(1)), CqlVarchar(Some(Cow::Borrowed("a"))), CqlBoolean(Some(true))]);
+}
+
+#[test]
+fn test_bind_checks_types(){
+	let preps = CqlPreparedStat {
+		id: vec![1, 2, 3],
+		meta: CqlMetadata {
+			flags: 0,
+			column_count: 3,
+			keyspace: Cow::Borrowed("rust"),
+			table: Cow::Borrowed("test_types"),
+			row_metadata: vec![
+				column("int_", CqlColType::new(CqlValueType::ColumnInt)),
+				column("ascii_", CqlColType::new(CqlValueType::ColumnASCII)),
+				column("set_", CqlColType::with_params(CqlValueType::ColumnSet, vec![CqlColType::new(CqlValueType::ColumnBigInt)]))
+			]
+		},
+		meta_result: None
+	};
+
+	let values = preps.bind(&(7, "abc", vec![1i64, 2])).unwrap();
+	assert_eq!(values[0], CqlInt(Some(7)));
+	assert!(preps.bind(&(7, None::<String>, vec![1i64])).is_ok());
+
+	// Wrong element type, wrong column type and wrong number of values
+	let err = preps.bind(&(7, "abc", vec![1i32])).unwrap_err();
+	assert!(err.desc.contains("set_"));
+	assert!(err.desc.contains("set<bigint>"));
+	assert!(preps.bind(&(7i64, "abc", vec![1i64])).is_err());
+	assert!(preps.bind(&(7, "abc")).is_err());
+}
+
+#[test]
+fn test_bind_orders_udt_fields(){
+	let mut address = CqlColType::with_params(CqlValueType::ColumnUdt, vec![CqlColType::new(CqlValueType::ColumnVarChar), CqlColType::new(CqlValueType::ColumnInt)]);
+	address.custom = Some(Cow::Borrowed("rust.address"));
+	address.fields = vec![Cow::Borrowed("street"), Cow::Borrowed("zip_code")];
+	let preps = CqlPreparedStat {
+		id: vec![1, 2, 3],
+		meta: CqlMetadata {
+			flags: 0,
+			column_count: 1,
+			keyspace: Cow::Borrowed("rust"),
+			table: Cow::Borrowed("users"),
+			row_metadata: vec![column("addresses", CqlColType::with_params(CqlValueType::ColumnList, vec![address]))]
+		},
+		meta_result: None
+	};
+
+	let value = CqlList(Some(vec![CqlUdt(Some(vec![(Cow::Borrowed("zip_code"), CqlInt(Some(8000)))]))]));
+	let values = preps.bind(&vec![value]).unwrap();
+	assert_eq!(values[0], CqlList(Some(vec![CqlUdt(Some(vec![
+		(Cow::Borrowed("street"), CqlRaw(None)),
+		(Cow::Borrowed("zip_code"), CqlInt(Some(8000)))
+	]))])));
+
+	// Unknown field
+	let value = CqlList(Some(vec![CqlUdt(Some(vec![(Cow::Borrowed("city"), CqlInt(Some(1)))]))]));
+	assert!(preps.bind(&vec![value]).is_err());
+}
+
+#[derive(Debug, PartialEq)]
+struct Peer {
+	peer: IpAddr,
+	rack: Option<String>,
+	tokens: Option<Vec<String>>
+}
+
+// What #[derive(FromRow)] in cql_derive generates
+impl FromRow for Peer {
+	fn from_row(row: &CqlRow) -> RCResult<Peer> {
+		Ok(Peer {
+			peer: try!(row.get_by_name::<IpAddr>("peer")),
+			rack: try!(row.get_opt_by_name::<String>("rack")),
+			tokens: try!(row.get_opt_by_name::<Vec<String>>("tokens"))
+		})
+	}
+}
+
+#[test]
+fn test_from_row(){
+	let rows = read_rows(PEERS_ROWS.to_vec());
+	let peers = rows.parse::<Peer>().unwrap();
+	assert_eq!(peers, vec![Peer {
+		peer: IpAddr::V4(Ipv4Addr::new(172, 17, 0, 3)),
+		rack: Some("rack1".to_string()),
+		tokens: None
+	}]);
+
+	let fields = vec![(Cow::Borrowed("street"), CqlVarchar(Some(Cow::Borrowed("Main St"))))];
+	assert_eq!(from_udt_field::<String>(&fields, "street").unwrap(), "Main St".to_string());
+	assert_eq!(from_udt_field::<Option<i32>>(&fields, "zip_code").unwrap(), None);
+	assert!(from_udt_field::<i32>(&fields, "zip_code").is_err());
+}
+
+#[test]
+fn test_lwt_result(){
+	// INSERT INTO rust.users (id, name) VALUES (7, 'bob') IF NOT EXISTS
+	let rows = read_rows(vec![131, 0, 0, 1, 8, 0, 0, 0, 47, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 4, 114, 117, 115, 116, 0, 5, 117, 115, 101, 114, 115, 0, 9, 91, 97, 112, 112, 108, 105, 101, 100, 93, 0, 4, 0, 0, 0, 1, 0, 0, 0, 1, 1]);
+	let result = rows.lwt_result().unwrap();
+	assert!(result.applied);
+	assert!(result.current.is_empty());
+
+	// Same insert when the row already exists, the current values are returned
+	let rows = read_rows(vec![131, 0, 0, 1, 8, 0, 0, 0, 76, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 4, 114, 117, 115, 116, 0, 5, 117, 115, 101, 114, 115, 0, 9, 91, 97, 112, 112, 108, 105, 101, 100, 93, 0, 4, 0, 2, 105, 100, 0, 9, 0, 4, 110, 97, 109, 101, 0, 13, 0, 0, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 4, 0, 0, 0, 7, 0, 0, 0, 3, 98, 111, 98]);
+	let result = rows.lwt_result().unwrap();
+	assert!(!result.applied);
+	assert_eq!(result.current[0].get_by_name::<i32>("id").unwrap(), 7);
+	assert_eq!(result.current[0].get_by_name::<String>("name").unwrap(), "bob".to_string());
+}
@@ -0,0 +1,54 @@
+extern crate cql;
+
+use cql::*;
+
+// Void result on the given stream
+fn void_frame(stream: u8) -> Vec<u8> {
+	vec![131, 0, 0, stream, 8, 0, 0, 0, 4, 0, 0, 0, 1]
+}
+
+fn read_stream(frame: Vec<u8>) -> i16 {
+	let response = frame.as_slice().read_cql_response(3).unwrap();
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	response.stream
+}
+
+#[test]
+fn test_partial_frame(){
+	let frame = void_frame(1);
+	let mut buffer = FrameBuffer::new();
+	// Header split in two, and then the body
+	for chunk in frame.chunks(5) {
+		assert_eq!(buffer.next_frame(3), None);
+		buffer.extend(chunk);
+	}
+	assert_eq!(buffer.next_frame(3), Some(frame));
+	assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_coalesced_frames(){
+	let mut bytes = void_frame(1);
+	bytes.extend(void_frame(2));
+	bytes.extend(&void_frame(3)[.. 10]);
+
+	let mut buffer = FrameBuffer::new();
+	buffer.extend(&bytes);
+	assert_eq!(read_stream(buffer.next_frame(3).unwrap()), 1);
+	assert_eq!(read_stream(buffer.next_frame(3).unwrap()), 2);
+	assert_eq!(buffer.next_frame(3), None);
+	// The remainder is kept for the next read
+	assert_eq!(buffer.len(), 10);
+	buffer.extend(&void_frame(3)[10 ..]);
+	assert_eq!(read_stream(buffer.next_frame(3).unwrap()), 3);
+	assert!(buffer.is_empty());
+}
+
+#[test]
+fn test_frame_without_body(){
+	// READY before v3, with an 8 bytes header
+	let mut buffer = FrameBuffer::new();
+	buffer.extend(&[130, 0, 1, 2, 0, 0, 0, 0, 130]);
+	assert_eq!(buffer.next_frame(2), Some(vec![130, 0, 1, 2, 0, 0, 0, 0]));
+	assert_eq!(buffer.len(), 1);
+}
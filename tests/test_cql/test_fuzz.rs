@@ -0,0 +1,88 @@
+extern crate cql;
+
+use cql::*;
+use super::{STATUS_CHANGE_EVENT, TUPLE_ROWS, NESTED_COLLECTIONS_ROWS};
+
+// Small xorshift generator, so every run feeds the decoder the same inputs
+struct XorShift {
+	state: u64
+}
+
+impl XorShift {
+	fn next(&mut self) -> u64 {
+		self.state ^= self.state << 13;
+		self.state ^= self.state >> 7;
+		self.state ^= self.state << 17;
+		self.state
+	}
+
+	fn below(&mut self, max: usize) -> usize {
+		(self.next() % (max as u64)) as usize
+	}
+}
+
+fn seeds() -> Vec<Vec<u8>> {
+	vec![
+	// STATUS_CHANGE event
+	STATUS_CHANGE_EVENT.to_vec(),
+	// Rows with tuple<int, varchar> and list<tuple<int, varchar>>
+	TUPLE_ROWS.to_vec(),
+	// Rows with list<frozen<map<varchar, int>>> and map<varchar, frozen<set<uuid>>>
+	NESTED_COLLECTIONS_ROWS.to_vec(),
+	// Error
+	vec![131, 0, 0, 1, 0, 0, 0, 0, 9, 0, 0, 37, 0, 0, 3, 98, 97, 100],
+	]
+}
+
+fn mutate(rng: &mut XorShift, seed: &Vec<u8>) -> Vec<u8> {
+	let mut bytes = seed.clone();
+	match rng.below(4) {
+		// Truncated frame
+		0 => {
+			let len = rng.below(bytes.len());
+			bytes.truncate(len);
+		},
+		// Random bytes overwritten
+		1 => {
+			for _ in 0 .. rng.below(8) + 1 {
+				let pos = rng.below(bytes.len());
+				bytes[pos] = rng.next() as u8;
+			}
+		},
+		// Random bytes inserted
+		2 => {
+			for _ in 0 .. rng.below(8) + 1 {
+				let pos = rng.below(bytes.len());
+				bytes.insert(pos, rng.next() as u8);
+			}
+		},
+		// Valid header, random body
+		_ => {
+			bytes.truncate(9);
+			for _ in 0 .. rng.below(256) {
+				bytes.push(rng.next() as u8);
+			}
+		}
+	}
+	bytes
+}
+
+#[test]
+fn test_fuzz_read_cql_response(){
+	let mut rng = XorShift { state: 0x2545F4914F6CDD1D };
+	let seeds = seeds();
+
+	for _ in 0 .. 20000 {
+		let bytes =
+			if rng.below(8) == 0 {
+				(0 .. rng.below(512)).map(|_| rng.next() as u8).collect()
+			} else {
+				let seed = &seeds[rng.below(seeds.len())];
+				mutate(&mut rng, seed)
+			};
+		for version in 1 .. 4 {
+			// Any result is fine, as long as it doesn't panic
+			let _ = bytes.as_slice().read_cql_response(version);
+		}
+	}
+}
@@ -0,0 +1,43 @@
+extern crate cql;
+extern crate eventual;
+
+use self::eventual::Async;
+use cql::*;
+use super::fake_node::{Reply, fake_cluster, count};
+
+fn prepared(query: &str) -> Reply {
+	if query.contains("prepared") { Reply::Prepared } else { Reply::Void }
+}
+
+fn closed(query: &str) -> Reply {
+	if query.contains("prepared") { Reply::Close } else { Reply::Void }
+}
+
+fn unprepared(query: &str) -> Reply {
+	// Syntax error
+	if query.contains("prepared") { Reply::Error(0x2000) } else { Reply::Void }
+}
+
+#[test]
+fn test_prepare_all_nodes_one_fails(){
+	let (port, first, second) = fake_cluster(prepared, closed);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	cluster.connect().unwrap();
+
+	// 127.0.0.2 drops the connection, the statement is still prepared on 127.0.0.1
+	let preps = cluster.prepare("select prepared", true).await().unwrap().unwrap();
+	assert_eq!(preps.id, vec![1, 2]);
+	assert_eq!(count(&first, "prepared"), 1);
+	assert_eq!(count(&second, "prepared"), 1);
+}
+
+#[test]
+fn test_prepare_all_nodes_all_fail(){
+	let (port, _, _) = fake_cluster(unprepared, closed);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	cluster.connect().unwrap();
+
+	let err = cluster.prepare("select prepared", true).await().unwrap().err().expect("No node prepared it");
+	assert!(err.desc.contains("127.0.0.1"));
+	assert!(err.desc.contains("127.0.0.2"));
+}
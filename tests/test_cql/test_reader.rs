@@ -6,6 +6,7 @@ extern crate uuid;
 use std::borrow::Cow;
 use self::uuid::Uuid;
 use cql::*;
+use super::{STATUS_CHANGE_EVENT, TUPLE_ROWS, NESTED_COLLECTIONS_ROWS};
 
 pub fn to_hex_string(bytes: &Vec<u8>) -> String {
   let strs: Vec<String> = bytes.iter()
@@ -18,21 +19,7 @@ pub fn to_hex_string(bytes: &Vec<u8>) -> String {
 fn test_read_event(){
 	let version = 3;
 	// Event example
-	let bytes = 
-	// Header (v3 -> 5 bytes)
-	vec![131, 0, 255, 255, 12, 	
-	// Length
-	0, 0, 0, 28, 
-	// Body -----------
-	//STATUS_CHANGE
-	0,13, 83, 84, 65, 84, 85, 83, 95, 67, 72, 65, 78, 71, 69, 
-	//UP
-	0, 2, 85, 80, 
-	//[inet]
-	//address = 172.17.0.0
-	4, 172, 17, 0, 3, 
-	//Port = 11346
-	0, 0, 35, 82];
+	let bytes = STATUS_CHANGE_EVENT.to_vec();
 
 	let result = bytes.as_slice().read_cql_response(version).unwrap();
     println!("{:?}",result);
@@ -71,6 +58,103 @@ fn test_read_uuid(){
 }
 
 
+#[test]
+fn test_read_tuple(){
+	let version = 3;
+	// Columns: a tuple<int, varchar>, b list<tuple<int, varchar>>
+	let bytes = TUPLE_ROWS.to_vec();
+
+	let result = bytes.as_slice().read_cql_response(version).unwrap();
+    println!("{:?}",result);
+    assert_response!(result);
+
+    let tuple = CqlTuple(Some(vec![CqlInt(Some(7)), CqlVarchar(Some(Cow::Borrowed("hi")))]));
+    match result.body {
+    	CqlResponseBody::ResultRows(rows) => {
+    		assert_eq!(rows.rows[0].cols[0], tuple);
+    		assert_eq!(rows.rows[0].cols[1], CqlList(Some(vec![tuple.clone(), tuple.clone()])));
+    	},
+    	_ => panic!("Expected rows")
+    }
+}
+
+
+#[test]
+fn test_read_nested_collections(){
+	let version = 3;
+	// Columns: a list<frozen<map<varchar, int>>>, b map<varchar, frozen<set<uuid>>>
+	let bytes = NESTED_COLLECTIONS_ROWS.to_vec();
+
+	let result = bytes.as_slice().read_cql_response(version).unwrap();
+    println!("{:?}",result);
+    assert_response!(result);
+
+    let map = CqlMap(Some(vec![Pair{ key: CqlVarchar(Some(Cow::Borrowed("k"))), value: CqlInt(Some(5)) }]));
+    let uuid = Uuid::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).unwrap();
+    let set = CqlSet(Some(vec![CqlUuid(Some(uuid))]));
+    match result.body {
+    	CqlResponseBody::ResultRows(rows) => {
+    		assert_eq!(rows.rows[0].cols[0], CqlList(Some(vec![map.clone(), map.clone()])));
+    		assert_eq!(rows.rows[0].cols[1], CqlMap(Some(vec![Pair{ key: CqlVarchar(Some(Cow::Borrowed("x"))), value: set }])));
+    	},
+    	_ => panic!("Expected rows")
+    }
+}
+
+
+#[test]
+fn test_read_udt(){
+	let version = 3;
+	// Column: a frozen<address>, with address (street varchar, zip_code int)
+	let bytes = vec![131, 0, 0, 1, 8, 0, 0, 0, 88, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 1, 0, 2, 107, 115, 0, 1, 116, 0, 1, 97, 0, 48, 0, 2, 107, 115, 0, 7, 97, 100, 100, 114, 101, 115, 115, 0, 2, 0, 6, 115, 116, 114, 101, 101, 116, 0, 13, 0, 8, 122, 105, 112, 95, 99, 111, 100, 101, 0, 9, 0, 0, 0, 1, 0, 0, 0, 19, 0, 0, 0, 7, 77, 97, 105, 110, 32, 83, 116, 0, 0, 0, 4, 0, 0, 31, 64];
+
+	let result = bytes.as_slice().read_cql_response(version).unwrap();
+    println!("{:?}",result);
+    assert_response!(result);
+
+    match result.body {
+    	CqlResponseBody::ResultRows(rows) => {
+    		assert_eq!(format!("{}", rows.metadata.row_metadata[0].col_type), "ks.address");
+    		assert_eq!(rows.rows[0].cols[0], CqlUdt(Some(vec![
+    			(Cow::Borrowed("street"), CqlVarchar(Some(Cow::Borrowed("Main St")))),
+    			(Cow::Borrowed("zip_code"), CqlInt(Some(8000)))
+    		])));
+    	},
+    	_ => panic!("Expected rows")
+    }
+}
+
+
+#[test]
+fn test_read_supported(){
+	let version = 3;
+	// CQL_VERSION: [3.4.4, 3.10.0], COMPRESSION: [snappy, lz4], PROTOCOL_VERSIONS: [3/v3, 4/v4, 5/v5-beta]
+	let bytes = vec![131, 0, 0, 1, 6, 0, 0, 0, 104, 0, 3, 0, 11, 67, 81, 76, 95, 86, 69, 82, 83, 73, 79, 78, 0, 2, 0, 5, 51, 46, 52, 46, 52, 0, 6, 51, 46, 49, 48, 46, 48, 0, 11, 67, 79, 77, 80, 82, 69, 83, 83, 73, 79, 78, 0, 2, 0, 6, 115, 110, 97, 112, 112, 121, 0, 3, 108, 122, 52, 0, 17, 80, 82, 79, 84, 79, 67, 79, 76, 95, 86, 69, 82, 83, 73, 79, 78, 83, 0, 3, 0, 4, 51, 47, 118, 51, 0, 4, 52, 47, 118, 52, 0, 9, 53, 47, 118, 53, 45, 98, 101, 116, 97];
+
+	let result = bytes.as_slice().read_cql_response(version).unwrap();
+    assert_response!(result);
+
+    match result.body {
+    	CqlResponseBody::ResponseSupported(capabilities) => {
+    		assert_eq!(capabilities.cql_versions, vec!["3.4.4", "3.10.0"]);
+    		assert_eq!(capabilities.cql_version(), Some("3.10.0"));
+    		assert!(capabilities.supports_compression(Compression::Lz4));
+    		assert!(capabilities.supports_compression(Compression::Snappy));
+    		assert!(capabilities.supports_compression(Compression::None));
+    		assert_eq!(capabilities.protocol_versions, vec![3, 4, 5]);
+    		assert!(capabilities.supports_protocol_version(3));
+    		assert!(!capabilities.supports_protocol_version(2));
+    		assert_eq!(capabilities.options.len(), 3);
+    	},
+    	_ => panic!("Expected SUPPORTED")
+    }
+
+	// Servers before 3.0 don't list their protocol versions, nor compression if no library is available
+	let capabilities = ServerCapabilities::new(vec![("CQL_VERSION".to_string(), vec!["3.2.1".to_string()])].into_iter().collect());
+	assert!(capabilities.supports_protocol_version(3));
+	assert!(!capabilities.supports_compression(Compression::Lz4));
+}
+
 //#[test]
 fn test_large_response(){
 	let version = 3;
@@ -254,3 +338,69 @@ fn test_select_types2(){
     assert_response!(result);
 }
 
+
+fn cql_string(s: &str) -> Vec<u8> {
+	let mut bytes = vec![0, s.len() as u8];
+	bytes.extend(s.as_bytes());
+	bytes
+}
+
+fn event_frame(version: u8, strings: &[&str], tail: Vec<u8>) -> Vec<u8> {
+	let mut body: Vec<u8> = strings.iter().flat_map(|s| cql_string(s)).collect();
+	body.extend(tail);
+	// Stream -1
+	let mut frame = if version >= 3 { vec![0x80 | version, 0, 255, 255, 12] } else { vec![0x80 | version, 0, 255, 12] };
+	frame.extend(vec![0, 0, 0, body.len() as u8]);
+	frame.extend(body);
+	frame
+}
+
+fn read_schema_change(bytes: Vec<u8>, version: u8) -> (SchemaChangeType, SchemaChangeOptions) {
+	match bytes.as_slice().read_cql_response(version).unwrap().body {
+		CqlResponseBody::ResponseEvent(CqlEvent::SchemaChange(change, options)) => (change, options),
+		body => panic!("Expected a schema change event, got {:?}", body)
+	}
+}
+
+#[test]
+fn test_read_schema_change(){
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "CREATED", "TABLE", "ks", "users"], vec![]);
+	assert_eq!(read_schema_change(bytes, 3),
+			   (SchemaChangeType::Created, SchemaChangeOptions::Table(Cow::Borrowed("ks"), Cow::Borrowed("users"))));
+
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "DROPPED", "KEYSPACE", "ks"], vec![]);
+	assert_eq!(read_schema_change(bytes, 3),
+			   (SchemaChangeType::Dropped, SchemaChangeOptions::Keyspace(Cow::Borrowed("ks"))));
+
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "UPDATED", "TYPE", "ks", "address"], vec![]);
+	assert_eq!(read_schema_change(bytes, 3),
+			   (SchemaChangeType::Updated, SchemaChangeOptions::Type(Cow::Borrowed("ks"), Cow::Borrowed("address"))));
+
+	// Argument types as a string list
+	let mut args = vec![0, 2];
+	args.extend(cql_string("int"));
+	args.extend(cql_string("text"));
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "CREATED", "FUNCTION", "ks", "f"], args.clone());
+	assert_eq!(read_schema_change(bytes, 3),
+			   (SchemaChangeType::Created, SchemaChangeOptions::Function(Cow::Borrowed("ks"), Cow::Borrowed("f"),
+			   															vec![Cow::Borrowed("int"), Cow::Borrowed("text")])));
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "DROPPED", "AGGREGATE", "ks", "a"], args);
+	assert_eq!(read_schema_change(bytes, 3).1,
+			   SchemaChangeOptions::Aggregate(Cow::Borrowed("ks"), Cow::Borrowed("a"),
+			   								  vec![Cow::Borrowed("int"), Cow::Borrowed("text")]));
+
+	let bytes = event_frame(3, &["SCHEMA_CHANGE", "CREATED", "TRIGGER", "ks"], vec![]);
+	assert!(bytes.as_slice().read_cql_response(3).is_err());
+}
+
+#[test]
+fn test_read_schema_change_v2(){
+	// Keyspace and table, empty for keyspace changes
+	let bytes = event_frame(2, &["SCHEMA_CHANGE", "UPDATED", "ks", "users"], vec![]);
+	assert_eq!(read_schema_change(bytes, 2),
+			   (SchemaChangeType::Updated, SchemaChangeOptions::Table(Cow::Borrowed("ks"), Cow::Borrowed("users"))));
+
+	let bytes = event_frame(2, &["SCHEMA_CHANGE", "CREATED", "ks", ""], vec![]);
+	assert_eq!(read_schema_change(bytes, 2),
+			   (SchemaChangeType::Created, SchemaChangeOptions::Keyspace(Cow::Borrowed("ks"))));
+}
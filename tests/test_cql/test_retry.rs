@@ -0,0 +1,35 @@
+extern crate cql;
+extern crate eventual;
+
+use std::time::Duration;
+use self::eventual::Async;
+use cql::*;
+use super::fake_node::{Reply, fake_cluster, count};
+
+fn overloaded(query: &str) -> Reply {
+	// Overloaded
+	if query.contains("retried") { Reply::Error(0x1001) } else { Reply::Void }
+}
+
+fn void(_: &str) -> Reply {
+	Reply::Void
+}
+
+#[test]
+fn test_retry_goes_to_another_node(){
+	let (port, first, second) = fake_cluster(overloaded, void);
+
+	// The current node stays the contact point
+	let mut cluster = Cluster::builder()
+						.contact_point("127.0.0.1")
+						.port(port)
+						.load_balancing(BalancerType::RoundRobin, Duration::from_secs(3600))
+						.build()
+						.unwrap();
+	cluster.connect().unwrap();
+
+	let response = cluster.execute(Statement::query("select retried")).await().unwrap().unwrap();
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+	assert_eq!(count(&first, "retried"), 1);
+	assert_eq!(count(&second, "retried"), 1);
+}
@@ -0,0 +1,66 @@
+extern crate cql;
+
+use cql::*;
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicBool, Ordering};
+use super::fake_node::{Reply, fake_cluster};
+
+#[test]
+fn test_apply_schema_changes(){
+	let mut schema = SchemaMetadata::new();
+	schema.apply(SchemaChangeType::Created, &SchemaChangeOptions::Keyspace(Cow::Borrowed("ks")));
+	schema.apply(SchemaChangeType::Created, &SchemaChangeOptions::Table(Cow::Borrowed("ks"), Cow::Borrowed("users")));
+	schema.apply(SchemaChangeType::Created, &SchemaChangeOptions::Type(Cow::Borrowed("ks"), Cow::Borrowed("address")));
+	schema.apply(SchemaChangeType::Created, &SchemaChangeOptions::Function(Cow::Borrowed("ks"), Cow::Borrowed("f"),
+																		   vec![Cow::Borrowed("int"), Cow::Borrowed("text")]));
+	{
+		let keyspace = schema.keyspace("ks").unwrap();
+		assert!(keyspace.tables.contains("users"));
+		assert!(keyspace.types.contains("address"));
+		assert!(keyspace.functions.contains("f(int,text)"));
+	}
+
+	// Changes to keyspaces not seen yet add them
+	schema.apply(SchemaChangeType::Updated, &SchemaChangeOptions::Table(Cow::Borrowed("other"), Cow::Borrowed("t")));
+	assert!(schema.keyspace("other").unwrap().tables.contains("t"));
+
+	schema.apply(SchemaChangeType::Dropped, &SchemaChangeOptions::Table(Cow::Borrowed("ks"), Cow::Borrowed("users")));
+	assert!(schema.keyspace("ks").unwrap().tables.is_empty());
+
+	schema.apply(SchemaChangeType::Dropped, &SchemaChangeOptions::Keyspace(Cow::Borrowed("ks")));
+	assert!(schema.keyspace("ks").is_none());
+	assert_eq!(schema.keyspaces.len(), 1);
+}
+
+fn unreadable(query: &str) -> Reply {
+	// Unauthorized
+	if query.contains("keyspace_name") { Reply::Error(0x2100) } else { Reply::Void }
+}
+
+static SCHEMA_FAILS: AtomicBool = AtomicBool::new(false);
+
+fn fails_later(query: &str) -> Reply {
+	if query.contains("keyspace_name") && SCHEMA_FAILS.load(Ordering::SeqCst) { Reply::Error(0x2100) } else { Reply::Void }
+}
+
+#[test]
+fn test_schema_load_fails_connect(){
+	let (port, _, _) = fake_cluster(unreadable, unreadable);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	let err = cluster.connect().err().expect("The schema can't be read");
+	assert!(err.desc.contains("Error reading the schema"));
+}
+
+#[test]
+fn test_schema_refresh_fails_stale(){
+	let (port, _, _) = fake_cluster(fails_later, fails_later);
+	let mut cluster = Cluster::builder().contact_point("127.0.0.1").port(port).build().unwrap();
+	let events = cluster.subscribe();
+	cluster.connect().unwrap();
+	assert!(!cluster.schema().stale);
+
+	SCHEMA_FAILS.store(true, Ordering::SeqCst);
+	cluster.refresh_nodes();
+	assert!(events.iter().any(|event| match event { ClusterEvent::SchemaStale(_) => true, _ => false }));
+	assert!(cluster.schema().stale);
+}
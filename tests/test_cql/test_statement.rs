@@ -0,0 +1,78 @@
+extern crate cql;
+
+use cql::*;
+
+fn serialize(statement: &Statement, version: u8) -> Vec<u8> {
+	let request = statement.to_request(version);
+	let mut buf = vec![];
+	request.serialize(&mut buf, version).unwrap();
+	buf
+}
+
+#[test]
+fn test_query_flags(){
+	let statement = Statement::query("select")
+						.values(&(7,))
+						.consistency(Consistency::Quorum)
+						.serial_consistency(Consistency::One)
+						.page_size(100)
+						.paging_state(vec![1, 2])
+						.default_timestamp(42)
+						.tracing(true);
+
+	assert_eq!(serialize(&statement, 3), vec![
+		// Header, with the tracing flag
+		3, 2, 0, 1, 7, 0, 0, 0, 43,
+		// Query string
+		0, 0, 0, 6, 115, 101, 108, 101, 99, 116,
+		// Consistency and flags: values, page size, paging state, serial consistency and timestamp
+		0, 4, 0x3d,
+		// Values
+		0, 1, 0, 0, 0, 4, 0, 0, 0, 7,
+		// Page size
+		0, 0, 0, 100,
+		// Paging state
+		0, 0, 0, 2, 1, 2,
+		// Serial consistency
+		0, 1,
+		// Default timestamp
+		0, 0, 0, 0, 0, 0, 0, 42]);
+
+	// No default timestamp before v3
+	assert_eq!(serialize(&statement, 2)[20], 0x1d);
+
+	// v1 only sends the consistency
+	assert_eq!(serialize(&statement, 1), vec![1, 2, 1, 7, 0, 0, 0, 12, 0, 0, 0, 6, 115, 101, 108, 101, 99, 116, 0, 4]);
+}
+
+#[test]
+fn test_statement_without_options(){
+	let statement = Statement::query("select");
+	assert_eq!(serialize(&statement, 3), vec![3, 0, 0, 1, 7, 0, 0, 0, 13, 0, 0, 0, 6, 115, 101, 108, 101, 99, 116, 0, 1, 0]);
+	assert!(!statement.idempotent);
+	assert!(statement.routing_key.is_none());
+}
+
+#[test]
+fn test_serial_consistency(){
+	let statement = Statement::query("select").serial_consistency(Consistency::LocalSerial);
+	assert_eq!(serialize(&statement, 3), vec![3, 0, 0, 1, 7, 0, 0, 0, 15, 0, 0, 0, 6, 115, 101, 108, 101, 99, 116, 0, 1, 0x10, 0, 9]);
+
+	let statement = Statement::query("select").consistency(Consistency::LocalOne);
+	assert_eq!(serialize(&statement, 3)[19..21].to_vec(), vec![0, 10]);
+}
+
+#[test]
+fn test_timestamp_generators(){
+	assert_eq!(ServerSideTimestampGenerator.next_timestamp(), None);
+
+	let generator = MonotonicTimestampGenerator::new();
+	let mut last = generator.next_timestamp().unwrap();
+	// After 2015, in microseconds
+	assert!(last > 1_420_070_400_000_000);
+	for _ in 0..10000 {
+		let next = generator.next_timestamp().unwrap();
+		assert!(next > last);
+		last = next;
+	}
+}
@@ -0,0 +1,49 @@
+extern crate cql;
+
+use cql::*;
+
+#[test]
+fn test_stream_id_limits(){
+	let mut ids = StreamIdAllocator::new(2);
+	for id in 0 .. 128 {
+		assert_eq!(ids.allocate(), Some(id));
+	}
+	assert_eq!(ids.allocate(), None);
+	assert_eq!(ids.in_flight(), 128);
+
+	let mut ids = StreamIdAllocator::new(3);
+	assert_eq!(ids.capacity(), 32768);
+	for _ in 0 .. 32768 {
+		assert!(ids.allocate().is_some());
+	}
+	assert_eq!(ids.allocate(), None);
+	assert!(ids.release(32767));
+	assert_eq!(ids.allocate(), Some(32767));
+}
+
+#[test]
+fn test_stream_id_reuse(){
+	let mut ids = StreamIdAllocator::new(3);
+	let first = ids.allocate().unwrap();
+	let second = ids.allocate().unwrap();
+	let third = ids.allocate().unwrap();
+
+	// Any id can be released, not only the last one
+	assert!(ids.release(first));
+	assert!(!ids.is_in_flight(first));
+	assert!(ids.is_in_flight(second));
+	assert_eq!(ids.in_flight(), 2);
+
+	// Released ids are reused, in flight ones never
+	let reused = ids.allocate().unwrap();
+	assert_eq!(reused, first);
+	let next = ids.allocate().unwrap();
+	assert!(next != second && next != third);
+
+	// Releasing twice, or an id never given, does nothing
+	assert!(ids.release(second));
+	assert!(!ids.release(second));
+	assert!(!ids.release(100));
+	assert!(!ids.release(-1));
+	assert_eq!(ids.in_flight(), 3);
+}
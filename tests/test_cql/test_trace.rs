@@ -0,0 +1,47 @@
+extern crate cql;
+extern crate uuid;
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr};
+use self::uuid::Uuid;
+use cql::*;
+use super::read_rows;
+
+#[test]
+fn test_read_tracing_id(){
+	// Void result of a traced request, the session id comes before the body
+	let bytes = vec![131, 2, 0, 1, 8, 0, 0, 0, 20, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 0, 0, 1];
+	let response = bytes.as_slice().read_cql_response(3).unwrap();
+	assert_eq!(response.tracing_id, Uuid::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]));
+	assert_eq!(response.body, CqlResponseBody::ResultVoid);
+}
+
+#[test]
+fn test_parse_trace(){
+	// SELECT ... FROM system_traces.sessions WHERE session_id = ?
+	let session = read_rows(vec![131, 0, 0, 1, 8, 0, 0, 0, 222, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 6, 0, 13, 115, 121, 115, 116, 101, 109, 95, 116, 114, 97, 99, 101, 115, 0, 8, 115, 101, 115, 115, 105, 111, 110, 115, 0, 10, 115, 101, 115, 115, 105, 111, 110, 95, 105, 100, 0, 12, 0, 11, 99, 111, 111, 114, 100, 105, 110, 97, 116, 111, 114, 0, 16, 0, 8, 100, 117, 114, 97, 116, 105, 111, 110, 0, 9, 0, 10, 112, 97, 114, 97, 109, 101, 116, 101, 114, 115, 0, 33, 0, 13, 0, 13, 0, 7, 114, 101, 113, 117, 101, 115, 116, 0, 13, 0, 10, 115, 116, 97, 114, 116, 101, 100, 95, 97, 116, 0, 11, 0, 0, 0, 1, 0, 0, 0, 16, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 0, 0, 4, 172, 17, 0, 2, 0, 0, 0, 4, 0, 0, 5, 220, 0, 0, 0, 23, 0, 0, 0, 1, 0, 0, 0, 5, 113, 117, 101, 114, 121, 0, 0, 0, 6, 115, 101, 108, 101, 99, 116, 0, 0, 0, 18, 69, 120, 101, 99, 117, 116, 101, 32, 67, 81, 76, 51, 32, 113, 117, 101, 114, 121, 0, 0, 0, 8, 0, 0, 1, 74, 162, 202, 176, 0]);
+	// SELECT ... FROM system_traces.events WHERE session_id = ?
+	let events = read_rows(vec![131, 0, 0, 1, 8, 0, 0, 0, 160, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 5, 0, 13, 115, 121, 115, 116, 101, 109, 95, 116, 114, 97, 99, 101, 115, 0, 6, 101, 118, 101, 110, 116, 115, 0, 8, 101, 118, 101, 110, 116, 95, 105, 100, 0, 15, 0, 8, 97, 99, 116, 105, 118, 105, 116, 121, 0, 13, 0, 6, 115, 111, 117, 114, 99, 101, 0, 16, 0, 14, 115, 111, 117, 114, 99, 101, 95, 101, 108, 97, 112, 115, 101, 100, 0, 9, 0, 6, 116, 104, 114, 101, 97, 100, 0, 13, 0, 0, 0, 1, 0, 0, 0, 16, 16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 0, 0, 0, 7, 80, 97, 114, 115, 105, 110, 103, 0, 0, 0, 4, 172, 17, 0, 2, 0, 0, 0, 4, 0, 0, 0, 120, 0, 0, 0, 8, 78, 97, 116, 105, 118, 101, 45, 49]);
+
+	let trace = parse_trace(&session, &events).unwrap().unwrap();
+	let source = IpAddr::V4(Ipv4Addr::new(172, 17, 0, 2));
+	let mut parameters = HashMap::new();
+	parameters.insert("query".to_string(), "select".to_string());
+	assert_eq!(trace.session_id, Uuid::from_bytes(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).unwrap());
+	assert_eq!(trace.coordinator, source);
+	assert_eq!(trace.duration, 1500);
+	assert_eq!(trace.parameters, parameters);
+	assert_eq!(trace.request, "Execute CQL3 query".to_string());
+	assert_eq!(trace.started_at, 1420070400000);
+	assert_eq!(trace.events, vec![TraceEvent {
+		event_id: Uuid::from_bytes(&[16, 17, 18, 19, 20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31]).unwrap(),
+		activity: "Parsing".to_string(),
+		source: source,
+		source_elapsed: 120,
+		thread: "Native-1".to_string()
+	}]);
+
+	// The duration is null until the request completes
+	let session = read_rows(vec![131, 0, 0, 1, 8, 0, 0, 0, 218, 0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 6, 0, 13, 115, 121, 115, 116, 101, 109, 95, 116, 114, 97, 99, 101, 115, 0, 8, 115, 101, 115, 115, 105, 111, 110, 115, 0, 10, 115, 101, 115, 115, 105, 111, 110, 95, 105, 100, 0, 12, 0, 11, 99, 111, 111, 114, 100, 105, 110, 97, 116, 111, 114, 0, 16, 0, 8, 100, 117, 114, 97, 116, 105, 111, 110, 0, 9, 0, 10, 112, 97, 114, 97, 109, 101, 116, 101, 114, 115, 0, 33, 0, 13, 0, 13, 0, 7, 114, 101, 113, 117, 101, 115, 116, 0, 13, 0, 10, 115, 116, 97, 114, 116, 101, 100, 95, 97, 116, 0, 11, 0, 0, 0, 1, 0, 0, 0, 16, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 0, 0, 0, 4, 172, 17, 0, 2, 255, 255, 255, 255, 0, 0, 0, 23, 0, 0, 0, 1, 0, 0, 0, 5, 113, 117, 101, 114, 121, 0, 0, 0, 6, 115, 101, 108, 101, 99, 116, 0, 0, 0, 18, 69, 120, 101, 99, 117, 116, 101, 32, 67, 81, 76, 51, 32, 113, 117, 101, 114, 121, 0, 0, 0, 8, 0, 0, 1, 74, 162, 202, 176, 0]);
+	assert_eq!(parse_trace(&session, &events).unwrap(), None);
+}